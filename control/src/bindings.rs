@@ -0,0 +1,339 @@
+//! Actions of the module triggered by gestures.
+//!
+//! Play from beginning reacts on press, so it can be used to cue the tape
+//! precisely. Play and pause toggle only on tap, holding the button is
+//! reserved for combos with knobs.
+//!
+//! Recording of a track starts right on the press of its record button.
+//! Tapping the button leaves the recording on, tapping it again stops it on
+//! release. Holding the button punches in only for as long as it is held.
+//! Each track is handled on its own, so buttons of several tracks can be
+//! pressed and released in any order.
+//!
+//! Pressing play from beginning while play and pause is down toggles
+//! learning of CV mappings. While learning, the transport is left alone
+//! and tapped buttons are offered as targets of the mapping instead.
+//!
+//! Turning the cassette switch while play and pause is down selects the
+//! bank instead of the cassette. The button then does not toggle play on
+//! its release.
+//!
+//! Turning the effect knob of a track while play and pause is down selects
+//! its effect. The range of the knob is split evenly between all the
+//! effects, the strength then follows the knob from where it stopped.
+
+use placeholder_dsp::effect::EFFECTS;
+use placeholder_dsp::paging_buffer::{SettingsPickup, Transport, TRACKS};
+
+use crate::cv::Mapper;
+use crate::gestures::{Button, Event, BUTTONS};
+use crate::pots::{Pot, Snapshot};
+
+/// Translates gestures into commands of the transport.
+#[derive(Default, Debug)]
+pub struct Bindings {
+    // Whether the press of the record button started the recording, as
+    // opposed to the track being recorded already.
+    started_on_press: [bool; TRACKS],
+    // Buttons that took part in toggling of CV learning or selection of a
+    // bank, the rest of their gestures is ignored until they are pressed
+    // again.
+    consumed: [bool; BUTTONS],
+    modifier_down: bool,
+}
+
+impl Bindings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn apply(
+        &mut self,
+        event: Event,
+        transport: &mut Transport,
+        mapper: &mut Mapper,
+        pickup: &mut SettingsPickup,
+        pots: &Snapshot,
+    ) {
+        match event {
+            Event::Press(Button::PlayPause) => self.modifier_down = true,
+            Event::Release(Button::PlayPause) => self.modifier_down = false,
+            _ => (),
+        }
+
+        if let Event::Press(button) = event {
+            self.consumed[button.index()] = false;
+            if button == Button::PlayFromBeginning && self.modifier_down {
+                self.consumed[Button::PlayFromBeginning.index()] = true;
+                self.consumed[Button::PlayPause.index()] = true;
+                mapper.toggle_learning();
+                return;
+            }
+        }
+        if event_button(event).is_some_and(|button| self.consumed[button.index()]) {
+            return;
+        }
+
+        if mapper.is_learning() {
+            if let Event::Tap(button) = event {
+                mapper.learn_button(button);
+            }
+            return;
+        }
+
+        match event {
+            Event::Press(Button::PlayFromBeginning) => transport.play_from_beginning(),
+            Event::Tap(Button::PlayPause) => transport.toggle_play(),
+            Event::Combo(Button::PlayPause, Pot::Effect(track)) => {
+                pickup.select_effect(track, effect_at(pots.effect[track]));
+            }
+            Event::Press(button) => {
+                if let Some(track) = record_track(button) {
+                    self.started_on_press[track] = !transport.is_recording(track);
+                    transport.set_recording(track, true);
+                }
+            }
+            Event::Tap(button) => {
+                if let Some(track) = record_track(button) {
+                    if !self.started_on_press[track] {
+                        transport.set_recording(track, false);
+                    }
+                }
+            }
+            Event::HoldEnd(button) => {
+                if let Some(track) = record_track(button) {
+                    transport.set_recording(track, false);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Whether the cassette switch selects the bank.
+    pub fn is_bank_held(&self) -> bool {
+        self.modifier_down
+    }
+
+    /// Recording stops on all tracks when another cassette gets selected.
+    pub fn cassette_changed(&mut self, transport: &mut Transport) {
+        self.started_on_press = [false; TRACKS];
+        transport.stop_recording();
+        if self.modifier_down {
+            self.consumed[Button::PlayPause.index()] = true;
+        }
+    }
+}
+
+fn event_button(event: Event) -> Option<Button> {
+    match event {
+        Event::Press(button)
+        | Event::Release(button)
+        | Event::Tap(button)
+        | Event::HoldStart(button)
+        | Event::HoldEnd(button)
+        | Event::Combo(button, _)
+        | Event::LongPressAtBoot(button) => Some(button),
+    }
+}
+
+fn record_track(button: Button) -> Option<usize> {
+    (0..TRACKS).find(|track| Button::record(*track) == button)
+}
+
+fn effect_at(position: f32) -> u8 {
+    ((position * EFFECTS as f32) as usize).min(EFFECTS - 1) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use placeholder_dsp::paging_buffer::{PickupModes, Settings};
+    use placeholder_dsp::save::{Mapping, Save};
+
+    use super::*;
+
+    struct Harness {
+        bindings: Bindings,
+        transport: Transport,
+        mapper: Mapper,
+        pickup: SettingsPickup,
+        pots: Snapshot,
+    }
+
+    impl Harness {
+        fn new() -> Self {
+            Self {
+                bindings: Bindings::new(),
+                transport: Transport::new(),
+                mapper: Mapper::new(&Save::default()),
+                pickup: SettingsPickup::new(Settings::default(), &PickupModes::default()),
+                pots: Snapshot::default(),
+            }
+        }
+
+        fn apply(&mut self, event: Event) {
+            self.bindings.apply(
+                event,
+                &mut self.transport,
+                &mut self.mapper,
+                &mut self.pickup,
+                &self.pots,
+            );
+        }
+
+        fn tap(&mut self, button: Button) {
+            self.apply(Event::Press(button));
+            self.apply(Event::Tap(button));
+            self.apply(Event::Release(button));
+        }
+
+        fn toggle_learning(&mut self) {
+            self.apply(Event::Press(Button::PlayPause));
+            self.tap(Button::PlayFromBeginning);
+            self.apply(Event::Tap(Button::PlayPause));
+            self.apply(Event::Release(Button::PlayPause));
+        }
+    }
+
+    #[test]
+    fn play_pause_toggles_only_on_tap() {
+        let mut harness = Harness::new();
+
+        harness.apply(Event::Press(Button::PlayPause));
+        harness.apply(Event::HoldStart(Button::PlayPause));
+        harness.apply(Event::HoldEnd(Button::PlayPause));
+        assert!(!harness.transport.is_playing());
+
+        harness.apply(Event::Tap(Button::PlayPause));
+        assert!(harness.transport.is_playing());
+    }
+
+    #[test]
+    fn play_from_beginning_reacts_on_press() {
+        let mut harness = Harness::new();
+
+        harness.apply(Event::Press(Button::PlayFromBeginning));
+        assert!(harness.transport.is_playing());
+        assert!(harness.transport.is_rewind_pending());
+    }
+
+    #[test]
+    fn tap_toggles_recording_starting_on_press_stopping_on_release() {
+        let mut harness = Harness::new();
+
+        harness.apply(Event::Press(Button::Record2));
+        assert!(harness.transport.is_recording(1));
+        harness.apply(Event::Tap(Button::Record2));
+        assert!(harness.transport.is_recording(1));
+
+        harness.apply(Event::Press(Button::Record2));
+        assert!(harness.transport.is_recording(1));
+        harness.apply(Event::Tap(Button::Record2));
+        assert!(!harness.transport.is_recording(1));
+    }
+
+    #[test]
+    fn hold_records_only_while_held() {
+        let mut harness = Harness::new();
+
+        harness.apply(Event::Press(Button::Record1));
+        harness.apply(Event::HoldStart(Button::Record1));
+        assert!(harness.transport.is_recording(0));
+        harness.apply(Event::HoldEnd(Button::Record1));
+        assert!(!harness.transport.is_recording(0));
+    }
+
+    #[test]
+    fn overlapping_presses_are_handled_per_track() {
+        let mut harness = Harness::new();
+
+        harness.apply(Event::Press(Button::Record3));
+        harness.apply(Event::Press(Button::Record4));
+        harness.apply(Event::HoldStart(Button::Record3));
+        harness.apply(Event::Tap(Button::Record4));
+        harness.apply(Event::HoldEnd(Button::Record3));
+
+        assert!(!harness.transport.is_recording(2));
+        assert!(harness.transport.is_recording(3));
+    }
+
+    #[test]
+    fn changing_cassette_stops_recording_on_all_tracks() {
+        let mut harness = Harness::new();
+        harness.apply(Event::Press(Button::Record1));
+        harness.apply(Event::Tap(Button::Record1));
+        harness.apply(Event::Press(Button::Record3));
+
+        harness.bindings.cassette_changed(&mut harness.transport);
+
+        assert!((0..TRACKS).all(|track| !harness.transport.is_recording(track)));
+    }
+
+    #[test]
+    fn selecting_bank_while_pp_is_down_does_not_toggle_play() {
+        let mut harness = Harness::new();
+        harness.apply(Event::Press(Button::PlayPause));
+        assert!(harness.bindings.is_bank_held());
+
+        harness.bindings.cassette_changed(&mut harness.transport);
+        harness.apply(Event::Tap(Button::PlayPause));
+        harness.apply(Event::Release(Button::PlayPause));
+
+        assert!(!harness.bindings.is_bank_held());
+        assert!(!harness.transport.is_playing());
+    }
+
+    #[test]
+    fn pressing_pfb_while_pp_is_down_toggles_cv_learning() {
+        let mut harness = Harness::new();
+
+        harness.toggle_learning();
+        assert!(harness.mapper.is_learning());
+        assert!(!harness.transport.is_playing());
+        assert!(!harness.transport.is_rewind_pending());
+
+        harness.toggle_learning();
+        assert!(!harness.mapper.is_learning());
+        assert!(!harness.transport.is_playing());
+    }
+
+    #[test]
+    fn buttons_tapped_while_learning_become_targets() {
+        let mut harness = Harness::new();
+        harness.toggle_learning();
+
+        harness.mapper.update([1.0, 0.0], &Default::default());
+        harness.tap(Button::Record3);
+        assert!(!harness.transport.is_recording(2));
+        harness.toggle_learning();
+
+        assert_eq!(
+            harness.mapper.mappings()[0],
+            Some(Mapping::Button(Button::Record3.index() as u8))
+        );
+    }
+
+    #[test]
+    fn turning_effect_knob_while_pp_is_down_selects_effect() {
+        let mut harness = Harness::new();
+        harness.apply(Event::Press(Button::PlayPause));
+
+        harness.pots.effect[2] = 0.99;
+        harness.apply(Event::Combo(Button::PlayPause, Pot::Effect(2)));
+        assert_eq!(
+            harness.pickup.settings().tracks[2].effect,
+            EFFECTS as u8 - 1
+        );
+        harness.pots.effect[2] = 0.0;
+        harness.apply(Event::Combo(Button::PlayPause, Pot::Effect(2)));
+        assert_eq!(harness.pickup.settings().tracks[2].effect, 0);
+
+        harness.pots.effect[1] = 0.99;
+        harness.apply(Event::Combo(Button::PlayPause, Pot::Effect(1)));
+        harness.apply(Event::Release(Button::PlayPause));
+        assert_eq!(
+            harness.pickup.settings().tracks[1].effect,
+            EFFECTS as u8 - 1
+        );
+        assert!(!harness.transport.is_playing());
+    }
+}
@@ -0,0 +1,282 @@
+//! Guided calibration of pots and CV inputs.
+//!
+//! Calibration is entered by holding the PFB button while the module
+//! starts. The user is then walked through the following steps, each
+//! confirmed by tapping the PP button:
+//!
+//! 1. Turn all knobs fully counter-clockwise.
+//! 2. Turn all knobs fully clockwise.
+//! 3. Disconnect both CV inputs.
+//! 4. Send the reference voltage of 2 V into both CV inputs.
+//!
+//! Tapping PFB cancels the calibration and keeps the previous one. The
+//! firmware shows the current step on LEDs.
+//!
+//! Pots are calibrated to cover the full range between the measured ends.
+//! CV inputs get their offset from the zero point and their scale from the
+//! reference, so pitch CV tracks volts per octave precisely. Pots or inputs
+//! that did not move far enough between the steps, e.g. because they were
+//! not turned or patched, keep their previous scale.
+
+use placeholder_dsp::save::{Calibration, Linear, CV_INPUTS};
+
+use crate::gestures::{Button, Event};
+use crate::pots::{Pot, Snapshot};
+
+/// Reference voltage expected in the last step.
+pub const REFERENCE_VOLTS: f32 = 2.0;
+
+/// Number of steps of the calibration.
+pub const STEPS: usize = 4;
+
+/// Reference voltage as represented by CV ranging from -1.0 to 1.0.
+const REFERENCE: f32 = REFERENCE_VOLTS / 5.0;
+
+/// Smallest span between the ends of a pot accepted as a measurement.
+const MIN_POT_SPAN: f32 = 0.5;
+
+/// Smallest part of the reference an input has to move by to be accepted
+/// as patched.
+const MIN_REFERENCE_RATIO: f32 = 0.5;
+
+/// Weight of a new CV reading in its average.
+const SMOOTHING: f32 = 0.05;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Step {
+    PotsMin,
+    PotsMax,
+    CvZero,
+    CvReference,
+}
+
+impl Step {
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Result of a finished calibration.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Outcome {
+    Finished(Calibration),
+    Canceled,
+}
+
+/// Whether the event requests the calibration.
+pub fn is_requested(event: Event) -> bool {
+    event == Event::LongPressAtBoot(Button::PlayFromBeginning)
+}
+
+/// Walks the user through the calibration.
+///
+/// Readings fed in must be raw, without the previous calibration applied.
+#[derive(Debug)]
+pub struct Calibrator {
+    step: Step,
+    previous: Calibration,
+    pots: Snapshot,
+    cv: Option<[f32; CV_INPUTS]>,
+    pots_min: Snapshot,
+    pots_max: Snapshot,
+    cv_zero: [f32; CV_INPUTS],
+}
+
+impl Calibrator {
+    pub fn new(previous: &Calibration) -> Self {
+        Self {
+            step: Step::PotsMin,
+            previous: *previous,
+            pots: Snapshot::default(),
+            cv: None,
+            pots_min: Snapshot::default(),
+            pots_max: Snapshot::default(),
+            cv_zero: [0.0; CV_INPUTS],
+        }
+    }
+
+    pub fn step(&self) -> Step {
+        self.step
+    }
+
+    /// Feed the snapshot of pots and CV readings, both uncalibrated.
+    ///
+    /// CV is averaged, since unlike pots it is not filtered upstream.
+    pub fn update(&mut self, pots: &Snapshot, cv: [f32; CV_INPUTS]) {
+        self.pots = *pots;
+        self.cv = Some(match self.cv {
+            Some(mut average) => {
+                for (average, reading) in average.iter_mut().zip(cv) {
+                    *average += (reading - *average) * SMOOTHING;
+                }
+                average
+            }
+            None => cv,
+        });
+    }
+
+    pub fn apply(&mut self, event: Event) -> Option<Outcome> {
+        match event {
+            Event::Tap(Button::PlayPause) => self.confirm().map(Outcome::Finished),
+            Event::Tap(Button::PlayFromBeginning) => Some(Outcome::Canceled),
+            _ => None,
+        }
+    }
+
+    /// Take the measurement of the current step and move to the next one.
+    /// Returns the calibration once all steps are done.
+    pub fn confirm(&mut self) -> Option<Calibration> {
+        let cv = self.cv.unwrap_or_default();
+        match self.step {
+            Step::PotsMin => {
+                self.pots_min = self.pots;
+                self.step = Step::PotsMax;
+            }
+            Step::PotsMax => {
+                self.pots_max = self.pots;
+                self.step = Step::CvZero;
+            }
+            Step::CvZero => {
+                self.cv_zero = cv;
+                self.step = Step::CvReference;
+            }
+            Step::CvReference => return Some(self.calibration(cv)),
+        }
+        // Measurements of the next step start afresh.
+        self.cv = None;
+        None
+    }
+
+    fn calibration(&self, cv_reference: [f32; CV_INPUTS]) -> Calibration {
+        let mut calibration = self.previous;
+
+        for pot in Pot::all() {
+            let min = self.pots_min.get(pot);
+            let span = self.pots_max.get(pot) - min;
+            if span > MIN_POT_SPAN {
+                let scale = 1.0 / span;
+                calibration.pots[pot.index()] = Linear {
+                    offset: -min * scale,
+                    scale,
+                };
+            }
+        }
+
+        let measurements = self.cv_zero.iter().zip(cv_reference);
+        for (linear, (zero, reference)) in calibration.cv.iter_mut().zip(measurements) {
+            let span = reference - zero;
+            if span > REFERENCE * MIN_REFERENCE_RATIO {
+                linear.scale = REFERENCE / span;
+            }
+            linear.offset = -zero * linear.scale;
+        }
+
+        calibration
+    }
+}
+
+const _: () = assert!(STEPS == Step::CvReference as usize + 1);
+
+#[cfg(test)]
+mod tests {
+    use placeholder_dsp::paging_buffer::TRACKS;
+
+    use super::*;
+
+    fn snapshot(value: f32) -> Snapshot {
+        Snapshot {
+            volume: [value; TRACKS],
+            pan: [value; TRACKS],
+            effect: [value; TRACKS],
+            pitch: value,
+        }
+    }
+
+    fn measure(
+        calibrator: &mut Calibrator,
+        pots: f32,
+        cv: [f32; CV_INPUTS],
+    ) -> Option<Calibration> {
+        calibrator.update(&snapshot(pots), cv);
+        calibrator.confirm()
+    }
+
+    #[test]
+    fn calibration_covers_measured_ranges() {
+        let mut calibrator = Calibrator::new(&Calibration::default());
+        assert_eq!(measure(&mut calibrator, 0.1, [0.0; CV_INPUTS]), None);
+        assert_eq!(measure(&mut calibrator, 0.9, [0.0; CV_INPUTS]), None);
+        assert_eq!(measure(&mut calibrator, 0.5, [0.02, -0.01]), None);
+        assert_eq!(calibrator.step(), Step::CvReference);
+        let calibration = measure(&mut calibrator, 0.5, [0.52, 0.39]).unwrap();
+
+        for pot in Pot::all() {
+            let linear = calibration.pots[pot.index()];
+            assert!(linear.apply(0.1).abs() < 0.001);
+            assert!((linear.apply(0.9) - 1.0).abs() < 0.001);
+        }
+        for (linear, (zero, reference)) in calibration.cv.iter().zip([(0.02, 0.52), (-0.01, 0.39)])
+        {
+            assert!(linear.apply(zero).abs() < 0.001);
+            assert!((linear.apply(reference) - REFERENCE).abs() < 0.001);
+        }
+    }
+
+    #[test]
+    fn unturned_pots_and_unpatched_inputs_keep_their_scale() {
+        let mut previous = Calibration::default();
+        previous.pots[Pot::Pitch.index()].scale = 1.1;
+        previous.cv[1].scale = 0.9;
+        let mut calibrator = Calibrator::new(&previous);
+
+        let mut min = snapshot(0.0);
+        min.pitch = 0.4;
+        calibrator.update(&min, [0.0; CV_INPUTS]);
+        calibrator.confirm();
+        let mut max = snapshot(1.0);
+        max.pitch = 0.45;
+        calibrator.update(&max, [0.0; CV_INPUTS]);
+        calibrator.confirm();
+        measure(&mut calibrator, 0.0, [0.0, 0.1]);
+        let calibration = measure(&mut calibrator, 0.0, [REFERENCE, 0.1]).unwrap();
+
+        assert_eq!(
+            calibration.pots[Pot::Pitch.index()],
+            previous.pots[Pot::Pitch.index()]
+        );
+        assert_eq!(calibration.cv[1].scale, 0.9);
+        assert!(calibration.cv[1].apply(0.1).abs() < 0.001);
+    }
+
+    #[test]
+    fn cv_readings_are_averaged_within_a_step() {
+        let mut calibrator = Calibrator::new(&Calibration::default());
+        measure(&mut calibrator, 0.0, [0.0; CV_INPUTS]);
+        measure(&mut calibrator, 1.0, [0.0; CV_INPUTS]);
+
+        calibrator.update(&snapshot(0.0), [0.0; CV_INPUTS]);
+        for _ in 0..200 {
+            calibrator.update(&snapshot(0.0), [0.01, -0.01]);
+            calibrator.update(&snapshot(0.0), [-0.01, 0.01]);
+        }
+        calibrator.confirm();
+        let calibration = measure(&mut calibrator, 0.0, [REFERENCE; CV_INPUTS]).unwrap();
+
+        for linear in calibration.cv {
+            assert!(linear.offset.abs() < 0.002);
+        }
+    }
+
+    #[test]
+    fn pp_confirms_steps_and_pfb_cancels() {
+        let mut calibrator = Calibrator::new(&Calibration::default());
+        assert_eq!(calibrator.apply(Event::Tap(Button::PlayPause)), None);
+        assert_eq!(calibrator.step(), Step::PotsMax);
+        assert_eq!(calibrator.apply(Event::Press(Button::PlayPause)), None);
+        assert_eq!(calibrator.step(), Step::PotsMax);
+        assert_eq!(
+            calibrator.apply(Event::Tap(Button::PlayFromBeginning)),
+            Some(Outcome::Canceled)
+        );
+    }
+}
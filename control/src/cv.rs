@@ -0,0 +1,373 @@
+//! Mapping of CV inputs onto knobs and buttons of the panel.
+//!
+//! CV is represented ranging from -1.0 to 1.0, covering the -5 to +5 V
+//! accepted by the inputs. An input mapped to a pot offsets its position by
+//! the CV scaled by the range of the mapping. An input mapped to a button
+//! presses it while the CV is high, as detected by a Schmitt trigger.
+//!
+//! Mappings are learned. While learning, the user wiggles one of the CV
+//! inputs and turns the target knob or taps the target button. The knob
+//! turn also sets the range, turning it by a half makes the full-scale CV
+//! move the pot by a half too, and turning it down inverts the input. Once
+//! learning is finished, the most wiggled input is bound to the target. An
+//! input wiggled without any target gets its mapping cleared.
+//!
+//! The gate input is learned the same way, sending a gate to it and tapping
+//! a button makes the gate act as that button. A gate sent without tapping
+//! any button restores its default. The gate takes precedence over CV
+//! inputs wiggled during the same learning.
+
+use placeholder_dsp::save::{Calibration, Linear, Mapping, Save, CV_INPUTS, POTS};
+
+use crate::gestures::{Button, BUTTONS};
+use crate::pots::{Pot, Snapshot};
+
+/// Level above which a trigger turns on, about 1.5 V.
+const TRIGGER_HIGH: f32 = 0.3;
+
+/// Level below which a trigger turns off, about 1 V.
+const TRIGGER_LOW: f32 = 0.2;
+
+/// Distance the CV has to travel to be considered wiggled while learning.
+const CV_TRAVEL: f32 = 0.2;
+
+/// Distance a knob has to travel to be considered turned while learning.
+const POT_TRAVEL: f32 = 0.03;
+
+/// Comparator with hysteresis, so a noisy or slow CV does not chatter.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct SchmittTrigger {
+    high: bool,
+}
+
+impl SchmittTrigger {
+    pub fn update(&mut self, value: f32) -> bool {
+        if self.high && value < TRIGGER_LOW {
+            self.high = false;
+        } else if !self.high && value > TRIGGER_HIGH {
+            self.high = true;
+        }
+        self.high
+    }
+
+    pub fn is_high(&self) -> bool {
+        self.high
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Learning {
+    cv_at_start: [f32; CV_INPUTS],
+    pots_at_start: Snapshot,
+    cv_travel: [f32; CV_INPUTS],
+    // Signed travel of pots, the furthest one becomes the target.
+    pot_travel: [f32; POTS],
+    button: Option<Button>,
+    gate_changed: bool,
+}
+
+/// Keeps mappings of CV inputs and applies them on the controls.
+#[derive(Debug)]
+pub struct Mapper {
+    mappings: [Option<Mapping>; CV_INPUTS],
+    ranges: [f32; CV_INPUTS],
+    gate: Option<Mapping>,
+    calibration: [Linear; CV_INPUTS],
+    cv: [f32; CV_INPUTS],
+    pots: Snapshot,
+    gate_high: bool,
+    triggers: [SchmittTrigger; CV_INPUTS],
+    learning: Option<Learning>,
+}
+
+impl Mapper {
+    pub fn new(save: &Save) -> Self {
+        Self {
+            mappings: save.mappings,
+            ranges: save.mapping_ranges,
+            gate: save.gate_mapping,
+            calibration: save.calibration.cv,
+            cv: [0.0; CV_INPUTS],
+            pots: Snapshot::default(),
+            gate_high: false,
+            triggers: [SchmittTrigger::default(); CV_INPUTS],
+            learning: None,
+        }
+    }
+
+    pub fn set_calibration(&mut self, calibration: &Calibration) {
+        self.calibration = calibration.cv;
+    }
+
+    pub fn mappings(&self) -> [Option<Mapping>; CV_INPUTS] {
+        self.mappings
+    }
+
+    pub fn ranges(&self) -> [f32; CV_INPUTS] {
+        self.ranges
+    }
+
+    pub fn gate_mapping(&self) -> Option<Mapping> {
+        self.gate
+    }
+
+    /// Feed the current level of the gate input.
+    pub fn update_gate(&mut self, high: bool) {
+        if high != self.gate_high {
+            self.gate_high = high;
+            if let Some(learning) = self.learning.as_mut() {
+                learning.gate_changed = true;
+            }
+        }
+    }
+
+    /// Feed the current CV readings and the snapshot of pots, as set on
+    /// the panel. Calibration of the inputs is applied on the readings.
+    pub fn update(&mut self, readings: [f32; CV_INPUTS], pots: &Snapshot) {
+        let mut cv = readings;
+        for (value, linear) in cv.iter_mut().zip(&self.calibration) {
+            *value = linear.apply(*value);
+        }
+        self.cv = cv;
+        self.pots = *pots;
+        for (trigger, value) in self.triggers.iter_mut().zip(cv) {
+            trigger.update(value);
+        }
+
+        if let Some(learning) = self.learning.as_mut() {
+            for (input, value) in cv.into_iter().enumerate() {
+                let travel = (value - learning.cv_at_start[input]).abs();
+                learning.cv_travel[input] = learning.cv_travel[input].max(travel);
+            }
+            for pot in Pot::all() {
+                let travel = pots.get(pot) - learning.pots_at_start.get(pot);
+                let furthest = &mut learning.pot_travel[pot.index()];
+                if travel.abs() > furthest.abs() {
+                    *furthest = travel;
+                }
+            }
+        }
+    }
+
+    /// Snapshot of pots offset by the mapped CV.
+    ///
+    /// Mappings are not applied while learning, so the user hears the
+    /// knobs as they are set.
+    pub fn modulate(&self, pots: &Snapshot) -> Snapshot {
+        let mut modulated = *pots;
+        if self.learning.is_some() {
+            return modulated;
+        }
+        for (input, mapping) in self.mappings.iter().enumerate() {
+            if let Some(Mapping::Pot(index)) = mapping {
+                let Some(pot) = Pot::all().find(|pot| pot.index() == *index as usize) else {
+                    continue;
+                };
+                let value = pots.get(pot) + self.cv[input] * self.ranges[input];
+                modulated.set(pot, value.clamp(0.0, 1.0));
+            }
+        }
+        modulated
+    }
+
+    /// Press the buttons mapped to inputs with high CV.
+    pub fn press(&self, pressed: &mut [bool; BUTTONS]) {
+        if self.learning.is_some() {
+            return;
+        }
+        for (mapping, trigger) in self.mappings.iter().zip(&self.triggers) {
+            if let Some(Mapping::Button(index)) = mapping {
+                if let Some(pressed) = pressed.get_mut(*index as usize) {
+                    *pressed |= trigger.is_high();
+                }
+            }
+        }
+    }
+
+    pub fn is_learning(&self) -> bool {
+        self.learning.is_some()
+    }
+
+    pub fn start_learning(&mut self) {
+        self.learning = Some(Learning {
+            cv_at_start: self.cv,
+            pots_at_start: self.pots,
+            cv_travel: [0.0; CV_INPUTS],
+            pot_travel: [0.0; POTS],
+            button: None,
+            gate_changed: false,
+        });
+    }
+
+    /// Mark the button as the target of the mapping being learned.
+    pub fn learn_button(&mut self, button: Button) {
+        if let Some(learning) = self.learning.as_mut() {
+            learning.button = Some(button);
+        }
+    }
+
+    /// Bind the wiggled input to the selected target.
+    pub fn finish_learning(&mut self) {
+        let Some(learning) = self.learning.take() else {
+            return;
+        };
+
+        if learning.gate_changed {
+            self.gate = learning
+                .button
+                .map(|button| Mapping::Button(button.index() as u8));
+            return;
+        }
+
+        let Some((input, _)) = learning
+            .cv_travel
+            .iter()
+            .enumerate()
+            .filter(|(_, travel)| **travel > CV_TRAVEL)
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        else {
+            return;
+        };
+
+        let pot = learning
+            .pot_travel
+            .iter()
+            .enumerate()
+            .filter(|(_, travel)| travel.abs() > POT_TRAVEL)
+            .max_by(|(_, a), (_, b)| a.abs().total_cmp(&b.abs()));
+
+        self.mappings[input] = if let Some(button) = learning.button {
+            Some(Mapping::Button(button.index() as u8))
+        } else if let Some((index, travel)) = pot {
+            self.ranges[input] = *travel;
+            Some(Mapping::Pot(index as u8))
+        } else {
+            None
+        };
+    }
+
+    pub fn toggle_learning(&mut self) {
+        if self.is_learning() {
+            self.finish_learning();
+        } else {
+            self.start_learning();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn learn(mapper: &mut Mapper, cv: [f32; CV_INPUTS], pots: &Snapshot) {
+        mapper.update([0.0; CV_INPUTS], &Snapshot::default());
+        mapper.start_learning();
+        mapper.update(cv, pots);
+        mapper.update([0.0; CV_INPUTS], pots);
+        mapper.finish_learning();
+    }
+
+    #[test]
+    fn schmitt_trigger_has_hysteresis() {
+        let mut trigger = SchmittTrigger::default();
+        assert!(!trigger.update(0.25));
+        assert!(trigger.update(0.35));
+        assert!(trigger.update(0.25));
+        assert!(!trigger.update(0.15));
+    }
+
+    #[test]
+    fn learn_pot_mapping_with_range_of_the_turn() {
+        let mut mapper = Mapper::new(&Save::default());
+        let mut pots = Snapshot::default();
+        pots.pan[1] = 0.5;
+
+        learn(&mut mapper, [0.0, 0.8], &pots);
+
+        let index = Pot::Pan(1).index() as u8;
+        assert_eq!(mapper.mappings(), [None, Some(Mapping::Pot(index))]);
+        assert_eq!(mapper.ranges()[1], 0.5);
+    }
+
+    #[test]
+    fn pot_is_offset_by_scaled_cv() {
+        let mut save = Save::default();
+        save.mappings[0] = Some(Mapping::Pot(Pot::Volume(2).index() as u8));
+        save.mapping_ranges[0] = 0.5;
+        let mut mapper = Mapper::new(&save);
+        let mut pots = Snapshot::default();
+        pots.volume[2] = 0.4;
+
+        mapper.update([0.4, 0.0], &pots);
+        assert!((mapper.modulate(&pots).volume[2] - 0.6).abs() < 0.001);
+
+        mapper.update([-1.0, 0.0], &pots);
+        assert_eq!(mapper.modulate(&pots).volume[2], 0.0);
+    }
+
+    #[test]
+    fn learn_button_mapping_and_trigger_it() {
+        let mut mapper = Mapper::new(&Save::default());
+        mapper.start_learning();
+        mapper.update([1.0, 0.0], &Snapshot::default());
+        mapper.learn_button(Button::PlayPause);
+        mapper.update([0.0, 0.0], &Snapshot::default());
+        mapper.finish_learning();
+
+        let mut pressed = [false; BUTTONS];
+        mapper.press(&mut pressed);
+        assert!(!pressed[Button::PlayPause.index()]);
+
+        mapper.update([1.0, 0.0], &Snapshot::default());
+        mapper.press(&mut pressed);
+        assert!(pressed[Button::PlayPause.index()]);
+    }
+
+    #[test]
+    fn wiggling_input_without_target_clears_its_mapping() {
+        let save = Save {
+            mappings: [Some(Mapping::Pot(0)), Some(Mapping::Button(1))],
+            ..Save::default()
+        };
+        let mut mapper = Mapper::new(&save);
+
+        learn(&mut mapper, [0.0, -0.7], &Snapshot::default());
+
+        assert_eq!(mapper.mappings(), [Some(Mapping::Pot(0)), None]);
+    }
+
+    #[test]
+    fn learning_without_wiggled_input_keeps_mappings() {
+        let mut save = Save::default();
+        save.mappings[0] = Some(Mapping::Pot(0));
+        let mut mapper = Mapper::new(&save);
+        let pots = Snapshot {
+            pitch: 0.9,
+            ..Snapshot::default()
+        };
+
+        learn(&mut mapper, [0.05, 0.0], &pots);
+
+        assert_eq!(mapper.mappings(), save.mappings);
+    }
+
+    #[test]
+    fn learn_gate_mapping_and_restore_its_default() {
+        let mut mapper = Mapper::new(&Save::default());
+
+        mapper.start_learning();
+        mapper.update([0.9, 0.0], &Snapshot::default());
+        mapper.update_gate(true);
+        mapper.update_gate(false);
+        mapper.learn_button(Button::Record2);
+        mapper.finish_learning();
+        let index = Button::Record2.index() as u8;
+        assert_eq!(mapper.gate_mapping(), Some(Mapping::Button(index)));
+        assert_eq!(mapper.mappings(), [None; CV_INPUTS]);
+
+        mapper.start_learning();
+        mapper.update_gate(true);
+        mapper.finish_learning();
+        assert_eq!(mapper.gate_mapping(), None);
+    }
+}
@@ -0,0 +1,114 @@
+//! Smoothing and hysteresis of noisy analog readings.
+
+/// Weight of a new reading in the smoothed value.
+const SMOOTHING: f32 = 0.2;
+
+/// Smallest change of the smoothed value that gets published. Smaller
+/// changes are considered noise.
+const HYSTERESIS: f32 = 0.002;
+
+/// Margin at both ends of the range that is stretched over, so the ends
+/// are reachable despite the smoothing never quite converging.
+const EDGE: f32 = 0.005;
+
+/// Turns noisy readings of a single control into a stable value ranging
+/// from 0.0 to 1.0.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Filter {
+    state: Option<State>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct State {
+    smoothed: f32,
+    published: f32,
+}
+
+impl Filter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed a new reading and return the published value.
+    ///
+    /// The first reading is published right away.
+    pub fn update(&mut self, reading: f32) -> f32 {
+        let state = match self.state {
+            Some(state) => {
+                let smoothed = state.smoothed + (reading - state.smoothed) * SMOOTHING;
+                let stretched = stretch(smoothed);
+                let moved = (stretched - state.published).abs() > HYSTERESIS;
+                let reached_end = stretched == 0.0 || stretched == 1.0;
+                State {
+                    smoothed,
+                    published: if moved || reached_end {
+                        stretched
+                    } else {
+                        state.published
+                    },
+                }
+            }
+            None => State {
+                smoothed: reading,
+                published: stretch(reading),
+            },
+        };
+        self.state = Some(state);
+        state.published
+    }
+
+    /// The last published value, `None` before the first reading.
+    pub fn value(&self) -> Option<f32> {
+        self.state.map(|state| state.published)
+    }
+}
+
+fn stretch(value: f32) -> f32 {
+    ((value - EDGE) / (1.0 - 2.0 * EDGE)).clamp(0.0, 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_reading_is_published_immediately() {
+        let mut filter = Filter::new();
+        assert_eq!(filter.value(), None);
+        assert!((filter.update(0.5) - 0.5).abs() < 0.001);
+    }
+
+    #[test]
+    fn noise_below_hysteresis_is_ignored() {
+        let mut filter = Filter::new();
+        let published = filter.update(0.5);
+        for i in 0..100 {
+            let noise = if i % 2 == 0 { 0.003 } else { -0.003 };
+            assert_eq!(filter.update(0.5 + noise), published);
+        }
+    }
+
+    #[test]
+    fn value_follows_a_turned_knob() {
+        let mut filter = Filter::new();
+        filter.update(0.2);
+        for _ in 0..50 {
+            filter.update(0.8);
+        }
+        assert!((filter.value().unwrap() - 0.8).abs() < 0.01);
+    }
+
+    #[test]
+    fn ends_of_the_range_are_reachable() {
+        let mut filter = Filter::new();
+        filter.update(0.5);
+        for _ in 0..50 {
+            filter.update(0.0);
+        }
+        assert_eq!(filter.value(), Some(0.0));
+        for _ in 0..50 {
+            filter.update(1.0);
+        }
+        assert_eq!(filter.value(), Some(1.0));
+    }
+}
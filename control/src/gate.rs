@@ -0,0 +1,202 @@
+//! Gate input driving the transport.
+//!
+//! Edges of the gate are caught by an interrupt, timestamped by a
+//! free-running clock counting samples, and cued at their offset within the
+//! block being captured. The audio routine processes that block next, so
+//! the tape reacts on the very sample of the input audio the gate arrived
+//! with. Edges learned about only after their block was processed are cued
+//! at the start of the next one.
+//!
+//! By default the gate plays from the beginning, as labeled on the panel.
+//! It can be mapped to any button through CV learning. Mapped to play and
+//! pause, each rising edge toggles play. Mapped to a record button, the
+//! track is recorded for as long as the gate is high.
+
+use placeholder_dsp::paging_buffer::{Cue, Transport};
+use placeholder_dsp::save::Mapping;
+
+use crate::gestures::Button;
+
+/// Change of the gate level.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Edge {
+    /// Time of the edge, in samples of the free-running clock.
+    pub time: u32,
+    pub rising: bool,
+}
+
+/// Offset of the sample at `time` within a block of `length` samples,
+/// starting at `block_start`.
+///
+/// Edges preceding the block are placed on its start, those following it
+/// on its end. The clock may wrap around in between.
+pub fn block_offset(time: u32, block_start: u32, length: usize) -> usize {
+    let delta = time.wrapping_sub(block_start) as i32;
+    (delta.max(0) as usize).min(length)
+}
+
+/// Action of the gate, as mapped to a button.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Gate {
+    button: Button,
+}
+
+impl Gate {
+    /// Gates mapped to anything but a button play from the beginning.
+    pub fn new(mapping: Option<Mapping>) -> Self {
+        let button = match mapping {
+            Some(Mapping::Button(index)) => Button::ALL
+                .get(index as usize)
+                .copied()
+                .unwrap_or(Button::PlayFromBeginning),
+            _ => Button::PlayFromBeginning,
+        };
+        Self { button }
+    }
+
+    pub fn cue(&self, edge: Edge) -> Option<Cue> {
+        match self.button {
+            Button::PlayFromBeginning => edge.rising.then_some(Cue::PlayFromBeginning),
+            Button::PlayPause => edge.rising.then_some(Cue::TogglePlay),
+            button => Some(Cue::Record(button.index(), edge.rising)),
+        }
+    }
+
+    /// Cue the edges on the transport for the block starting at
+    /// `block_start`.
+    pub fn schedule(
+        &self,
+        edges: impl IntoIterator<Item = Edge>,
+        block_start: u32,
+        length: usize,
+        transport: &mut Transport,
+    ) {
+        for edge in edges {
+            if let Some(cue) = self.cue(edge) {
+                transport.cue(block_offset(edge.time, block_start, length), cue);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use placeholder_dsp::paging_buffer::testing::with_deck;
+    use placeholder_dsp::paging_buffer::TRACKS;
+
+    use super::*;
+
+    const BLOCK: usize = 32;
+
+    #[test]
+    fn edges_within_block_are_placed_on_their_sample() {
+        assert_eq!(block_offset(1000, 1000, BLOCK), 0);
+        assert_eq!(block_offset(1017, 1000, BLOCK), 17);
+        assert_eq!(block_offset(1031, 1000, BLOCK), 31);
+    }
+
+    #[test]
+    fn late_edges_are_placed_on_block_start() {
+        assert_eq!(block_offset(990, 1000, BLOCK), 0);
+    }
+
+    #[test]
+    fn offset_survives_clock_wrapping() {
+        assert_eq!(block_offset(4, u32::MAX - 9, BLOCK), 14);
+        assert_eq!(block_offset(u32::MAX, 3, BLOCK), 0);
+    }
+
+    /// Record a block with the gate mapped to the second record button,
+    /// then play the track back.
+    fn record_through_gate(edges: &[Edge], block_start: u32) -> [f32; BLOCK] {
+        with_deck(|deck| {
+            let gate = Gate::new(Some(Mapping::Button(Button::Record2.index() as u8)));
+            let mut transport = Transport::new();
+            transport.toggle_play();
+            let mut output = [[0.0; TRACKS]; BLOCK];
+
+            gate.schedule(edges.iter().copied(), block_start, BLOCK, &mut transport);
+            let input: [[f32; TRACKS]; BLOCK] = core::array::from_fn(|i| [i as f32 + 1.0; TRACKS]);
+            deck.process(&mut transport, &input, &mut output);
+
+            transport.cue(0, Cue::PlayFromBeginning);
+            deck.process(&mut transport, &[[0.0; TRACKS]; BLOCK], &mut output);
+            output.map(|frame| frame[1])
+        })
+    }
+
+    #[test]
+    fn edges_captured_with_the_block_punch_in_on_their_sample() {
+        let edges = [
+            Edge {
+                time: 1037,
+                rising: true,
+            },
+            Edge {
+                time: 1050,
+                rising: false,
+            },
+        ];
+        let recorded = record_through_gate(&edges, 1024);
+
+        for (i, x) in recorded.iter().enumerate() {
+            let expected = if (13..26).contains(&i) {
+                i as f32 + 1.0
+            } else {
+                0.0
+            };
+            assert_eq!(*x, expected, "sample {i}");
+        }
+    }
+
+    #[test]
+    fn late_edges_punch_in_on_the_first_sample() {
+        let edges = [Edge {
+            time: 1000,
+            rising: true,
+        }];
+        let recorded = record_through_gate(&edges, 1024);
+
+        assert_eq!(recorded, core::array::from_fn(|i| i as f32 + 1.0));
+    }
+
+    #[test]
+    fn unmapped_gate_plays_from_beginning_on_rising_edge() {
+        let gate = Gate::new(None);
+        let rising = Edge {
+            time: 0,
+            rising: true,
+        };
+        let falling = Edge {
+            time: 1,
+            rising: false,
+        };
+        assert_eq!(gate.cue(rising), Some(Cue::PlayFromBeginning));
+        assert_eq!(gate.cue(falling), None);
+    }
+
+    #[test]
+    fn gate_mapped_to_record_button_punches_in_and_out() {
+        let gate = Gate::new(Some(Mapping::Button(Button::Record3.index() as u8)));
+        let rising = Edge {
+            time: 105,
+            rising: true,
+        };
+        let falling = Edge {
+            time: 120,
+            rising: false,
+        };
+        assert_eq!(gate.cue(rising), Some(Cue::Record(2, true)));
+        assert_eq!(gate.cue(falling), Some(Cue::Record(2, false)));
+    }
+
+    #[test]
+    fn gate_mapped_to_play_pause_toggles_on_rising_edge() {
+        let gate = Gate::new(Some(Mapping::Button(Button::PlayPause.index() as u8)));
+        let rising = Edge {
+            time: 0,
+            rising: true,
+        };
+        assert_eq!(gate.cue(rising), Some(Cue::TogglePlay));
+    }
+}
@@ -0,0 +1,401 @@
+//! Recognition of gestures performed with the buttons.
+//!
+//! Buttons are read once per scan of the multiplexer, which is also the
+//! unit all the timings are measured in. Each button is tracked on its own,
+//! so gestures on several buttons may overlap.
+//!
+//! A press is reported right away, followed by a `Tap` if the button is
+//! released soon enough, or by `HoldStart` and `HoldEnd` otherwise. Turning
+//! a knob while the button is down reports `Combo` instead, and the
+//! release is then not considered a tap nor a hold. Buttons that are
+//! already down when the module starts are reserved for
+//! `LongPressAtBoot`, their other gestures are not reported until they get
+//! released.
+
+use heapless::Vec;
+
+use placeholder_dsp::save::POTS;
+
+use crate::pots::{Pot, Snapshot};
+use crate::scanner::CHANNELS;
+
+/// Number of buttons on the panel.
+pub const BUTTONS: usize = 6;
+
+/// Most events that can be reported by a single update, each button may
+/// report a combo with every pot and up to two other events.
+pub const MAX_EVENTS: usize = BUTTONS * (POTS + 2);
+
+/// Distance a knob has to travel while a button is down to be considered
+/// turned, so noise and an accidental touch do not trigger a combo.
+const COMBO_TRAVEL: f32 = 0.03;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Button {
+    Record1,
+    Record2,
+    Record3,
+    Record4,
+    PlayFromBeginning,
+    PlayPause,
+}
+
+impl Button {
+    pub const ALL: [Button; BUTTONS] = [
+        Button::Record1,
+        Button::Record2,
+        Button::Record3,
+        Button::Record4,
+        Button::PlayFromBeginning,
+        Button::PlayPause,
+    ];
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    /// Record button of the given track.
+    pub fn record(track: usize) -> Button {
+        Button::ALL[track]
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Event {
+    Press(Button),
+    Release(Button),
+    /// The button was released shortly after being pressed.
+    Tap(Button),
+    HoldStart(Button),
+    HoldEnd(Button),
+    /// The knob was turned while the button was down. Reported on every
+    /// movement of the knob after it travelled far enough.
+    Combo(Button, Pot),
+    /// The button was held since the start of the module.
+    LongPressAtBoot(Button),
+}
+
+/// Durations of gestures, in number of updates.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Timings {
+    /// Consecutive equal readings required to accept a change of a button.
+    pub debounce: u32,
+    /// Time after which a press is no longer a tap, but a hold.
+    pub hold: u32,
+    /// Time a button has to be held since the start to be reported as
+    /// `LongPressAtBoot`.
+    pub boot_hold: u32,
+}
+
+impl Default for Timings {
+    fn default() -> Self {
+        // Updates are done once per scan of the multiplexer, the pace of
+        // which is driven by a 1 ms tick.
+        const UPDATES_PER_SECOND: u32 = 1000 / CHANNELS as u32;
+        Self {
+            debounce: 2,
+            hold: UPDATES_PER_SECOND * 3 / 10,
+            boot_hold: UPDATES_PER_SECOND * 2,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, Debug)]
+struct ButtonState {
+    pressed: bool,
+    changing_for: u32,
+    pressed_for: u32,
+    held: bool,
+    combo: bool,
+    since_boot: bool,
+    pots_at_press: Snapshot,
+    turned: [bool; POTS],
+}
+
+/// Turns readings of buttons and knobs into gestures.
+#[derive(Debug)]
+pub struct Gestures {
+    timings: Timings,
+    buttons: [ButtonState; BUTTONS],
+    pots: Option<Snapshot>,
+    started: bool,
+}
+
+impl Gestures {
+    pub fn new(timings: Timings) -> Self {
+        Self {
+            timings,
+            buttons: [ButtonState::default(); BUTTONS],
+            pots: None,
+            started: false,
+        }
+    }
+
+    /// Update with the current state of buttons, ordered as in
+    /// `Button::ALL`, and with the latest snapshot of pots.
+    pub fn update(&mut self, pressed: [bool; BUTTONS], pots: &Snapshot) -> Vec<Event, MAX_EVENTS> {
+        let mut events = Vec::new();
+        let previous_pots = self.pots.replace(*pots).unwrap_or(*pots);
+
+        if !self.started {
+            self.started = true;
+            for (state, pressed) in self.buttons.iter_mut().zip(pressed) {
+                state.pressed = pressed;
+                state.since_boot = pressed;
+            }
+            return events;
+        }
+
+        for (button, pressed) in Button::ALL.into_iter().zip(pressed) {
+            let state = &mut self.buttons[button.index()];
+            let mut emit = |event| events.push(event).ok().unwrap();
+
+            if pressed != state.pressed {
+                state.changing_for += 1;
+                if state.changing_for >= self.timings.debounce {
+                    state.changing_for = 0;
+                    state.pressed = pressed;
+                    if pressed {
+                        *state = ButtonState {
+                            pressed: true,
+                            pots_at_press: *pots,
+                            ..ButtonState::default()
+                        };
+                        emit(Event::Press(button));
+                    } else if state.since_boot {
+                        state.since_boot = false;
+                    } else {
+                        if state.held {
+                            emit(Event::HoldEnd(button));
+                        } else if !state.combo {
+                            emit(Event::Tap(button));
+                        }
+                        emit(Event::Release(button));
+                    }
+                    continue;
+                }
+            } else {
+                state.changing_for = 0;
+            }
+
+            if !state.pressed {
+                continue;
+            }
+            state.pressed_for = state.pressed_for.saturating_add(1);
+
+            if state.since_boot {
+                if state.pressed_for == self.timings.boot_hold {
+                    emit(Event::LongPressAtBoot(button));
+                }
+                continue;
+            }
+
+            for pot in Pot::all() {
+                let value = pots.get(pot);
+                let turned = &mut state.turned[pot.index()];
+                if !*turned && (value - state.pots_at_press.get(pot)).abs() > COMBO_TRAVEL {
+                    *turned = true;
+                    state.combo = true;
+                    emit(Event::Combo(button, pot));
+                } else if *turned && value != previous_pots.get(pot) {
+                    emit(Event::Combo(button, pot));
+                }
+            }
+
+            if !state.held && !state.combo && state.pressed_for >= self.timings.hold {
+                state.held = true;
+                emit(Event::HoldStart(button));
+            }
+        }
+
+        events
+    }
+
+    /// Whether the button is currently down, regardless of gestures.
+    pub fn is_pressed(&self, button: Button) -> bool {
+        self.buttons[button.index()].pressed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMINGS: Timings = Timings {
+        debounce: 2,
+        hold: 10,
+        boot_hold: 50,
+    };
+
+    struct Harness {
+        gestures: Gestures,
+        pressed: [bool; BUTTONS],
+        pots: Snapshot,
+    }
+
+    impl Harness {
+        fn new() -> Self {
+            let mut harness = Self {
+                gestures: Gestures::new(TIMINGS),
+                pressed: [false; BUTTONS],
+                pots: Snapshot::default(),
+            };
+            harness.tick();
+            harness
+        }
+
+        fn tick(&mut self) -> std::vec::Vec<Event> {
+            self.gestures
+                .update(self.pressed, &self.pots)
+                .into_iter()
+                .collect()
+        }
+
+        fn ticks(&mut self, count: u32) -> std::vec::Vec<Event> {
+            (0..count).flat_map(|_| self.tick()).collect()
+        }
+
+        fn press(&mut self, button: Button) -> std::vec::Vec<Event> {
+            self.pressed[button.index()] = true;
+            self.ticks(TIMINGS.debounce)
+        }
+
+        fn release(&mut self, button: Button) -> std::vec::Vec<Event> {
+            self.pressed[button.index()] = false;
+            self.ticks(TIMINGS.debounce)
+        }
+    }
+
+    #[test]
+    fn short_press_is_a_tap() {
+        let mut harness = Harness::new();
+
+        assert_eq!(
+            harness.press(Button::PlayPause),
+            [Event::Press(Button::PlayPause)]
+        );
+        assert_eq!(harness.ticks(3), []);
+        assert_eq!(
+            harness.release(Button::PlayPause),
+            [
+                Event::Tap(Button::PlayPause),
+                Event::Release(Button::PlayPause)
+            ]
+        );
+    }
+
+    #[test]
+    fn long_press_is_a_hold() {
+        let mut harness = Harness::new();
+
+        harness.press(Button::Record2);
+        assert_eq!(
+            harness.ticks(TIMINGS.hold),
+            [Event::HoldStart(Button::Record2)]
+        );
+        assert_eq!(harness.ticks(20), []);
+        assert_eq!(
+            harness.release(Button::Record2),
+            [
+                Event::HoldEnd(Button::Record2),
+                Event::Release(Button::Record2)
+            ]
+        );
+    }
+
+    #[test]
+    fn bounces_are_ignored() {
+        let mut harness = Harness::new();
+
+        harness.pressed[0] = true;
+        assert_eq!(harness.tick(), []);
+        harness.pressed[0] = false;
+        assert_eq!(harness.ticks(5), []);
+    }
+
+    #[test]
+    fn turning_a_knob_while_holding_is_a_combo() {
+        let mut harness = Harness::new();
+
+        harness.press(Button::PlayPause);
+        harness.pots.effect[1] = 0.01;
+        assert_eq!(harness.tick(), []);
+        harness.pots.effect[1] = 0.2;
+        assert_eq!(
+            harness.tick(),
+            [Event::Combo(Button::PlayPause, Pot::Effect(1))]
+        );
+        harness.pots.effect[1] = 0.21;
+        assert_eq!(
+            harness.tick(),
+            [Event::Combo(Button::PlayPause, Pot::Effect(1))]
+        );
+        assert_eq!(harness.ticks(TIMINGS.hold * 2), []);
+        assert_eq!(
+            harness.release(Button::PlayPause),
+            [Event::Release(Button::PlayPause)]
+        );
+    }
+
+    #[test]
+    fn knobs_turned_while_no_button_is_down_are_ignored() {
+        let mut harness = Harness::new();
+
+        harness.pots.pan[0] = 0.9;
+        assert_eq!(harness.tick(), []);
+    }
+
+    #[test]
+    fn gestures_of_several_buttons_overlap() {
+        let mut harness = Harness::new();
+
+        harness.press(Button::Record1);
+        harness.ticks(TIMINGS.hold);
+        assert_eq!(
+            harness.press(Button::Record3),
+            [Event::Press(Button::Record3)]
+        );
+        assert_eq!(
+            harness.release(Button::Record1),
+            [
+                Event::HoldEnd(Button::Record1),
+                Event::Release(Button::Record1)
+            ]
+        );
+        assert_eq!(
+            harness.release(Button::Record3),
+            [Event::Tap(Button::Record3), Event::Release(Button::Record3)]
+        );
+    }
+
+    #[test]
+    fn button_held_since_start_is_a_long_press_at_boot() {
+        let mut harness = Harness {
+            gestures: Gestures::new(TIMINGS),
+            pressed: [false; BUTTONS],
+            pots: Snapshot::default(),
+        };
+        harness.pressed[Button::PlayPause.index()] = true;
+
+        let events = harness.ticks(TIMINGS.boot_hold + 10);
+        assert_eq!(events, [Event::LongPressAtBoot(Button::PlayPause)]);
+        assert_eq!(harness.release(Button::PlayPause), []);
+        assert_eq!(
+            harness.press(Button::PlayPause),
+            [Event::Press(Button::PlayPause)]
+        );
+    }
+
+    #[test]
+    fn button_released_early_after_start_reports_nothing() {
+        let mut harness = Harness {
+            gestures: Gestures::new(TIMINGS),
+            pressed: [false; BUTTONS],
+            pots: Snapshot::default(),
+        };
+        harness.pressed[Button::Record4.index()] = true;
+
+        assert_eq!(harness.ticks(5), []);
+        assert_eq!(harness.release(Button::Record4), []);
+    }
+}
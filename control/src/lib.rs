@@ -0,0 +1,16 @@
+//! Hardware-independent processing of the module's controls.
+//!
+//! The firmware feeds raw readings of the hardware in, this crate turns
+//! them into a stable, calibrated state of the panel for the DSP.
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod bindings;
+pub mod calibration;
+pub mod cv;
+pub mod filter;
+pub mod gate;
+pub mod gestures;
+pub mod pots;
+pub mod scanner;
+pub mod switch;
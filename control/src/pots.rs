@@ -0,0 +1,254 @@
+//! Potentiometers of the panel.
+//!
+//! Pan and effect pots are read through the pot multiplexer, one of them
+//! per step. Volume and pitch pots are wired to the ADC directly and are
+//! read once per scan of the multiplexer, so all the pots are filtered at
+//! the same rate. A snapshot of all of them is published after each scan.
+
+use placeholder_dsp::paging_buffer::{Settings, TRACKS};
+use placeholder_dsp::save::{Calibration, Linear, POTS};
+
+use crate::filter::Filter;
+use crate::scanner::{Scanner, CHANNELS};
+
+/// Number of pots wired to the ADC directly.
+pub const DIRECT_POTS: usize = 5;
+
+/// Pots wired to the ADC directly, in the order their readings are passed
+/// to `Pots::step`.
+pub const DIRECT: [Pot; DIRECT_POTS] = [
+    Pot::Volume(0),
+    Pot::Volume(1),
+    Pot::Volume(2),
+    Pot::Volume(3),
+    Pot::Pitch,
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Pot {
+    Volume(usize),
+    Pan(usize),
+    Effect(usize),
+    Pitch,
+}
+
+impl Pot {
+    /// Pot routed to the given channel of the pot multiplexer.
+    pub fn multiplexed(channel: u8) -> Pot {
+        match channel {
+            0 => Pot::Pan(2),
+            1 => Pot::Pan(1),
+            2 => Pot::Pan(0),
+            3 => Pot::Pan(3),
+            4 => Pot::Effect(1),
+            5 => Pot::Effect(3),
+            6 => Pot::Effect(0),
+            7 => Pot::Effect(2),
+            _ => unreachable!(),
+        }
+    }
+
+    /// All the pots, multiplexed first.
+    pub fn all() -> impl Iterator<Item = Pot> {
+        (0..CHANNELS).map(Pot::multiplexed).chain(DIRECT)
+    }
+
+    /// Index of the pot within `Calibration::pots`.
+    pub fn index(self) -> usize {
+        match self {
+            Pot::Volume(track) => track,
+            Pot::Pan(track) => TRACKS + track,
+            Pot::Effect(track) => 2 * TRACKS + track,
+            Pot::Pitch => 3 * TRACKS,
+        }
+    }
+}
+
+const _: () = assert!(3 * TRACKS + 1 == POTS);
+
+/// Filtered positions of all pots, ranging from 0.0 to 1.0.
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+pub struct Snapshot {
+    pub volume: [f32; TRACKS],
+    pub pan: [f32; TRACKS],
+    pub effect: [f32; TRACKS],
+    pub pitch: f32,
+}
+
+impl Snapshot {
+    pub fn get(&self, pot: Pot) -> f32 {
+        match pot {
+            Pot::Volume(track) => self.volume[track],
+            Pot::Pan(track) => self.pan[track],
+            Pot::Effect(track) => self.effect[track],
+            Pot::Pitch => self.pitch,
+        }
+    }
+
+    /// Positions of the knobs as mix settings, to be reconciled with the
+    /// settings recovered from a cassette. Effects are not selected by a
+    /// knob and are left at the first one.
+    pub fn knobs(&self) -> Settings {
+        let mut settings = Settings {
+            pitch: self.pitch,
+            ..Settings::default()
+        };
+        for (track, settings) in settings.tracks.iter_mut().enumerate() {
+            settings.volume = self.volume[track];
+            settings.pan = self.pan[track];
+            settings.strength = self.effect[track];
+        }
+        settings
+    }
+
+    pub(crate) fn set(&mut self, pot: Pot, value: f32) {
+        match pot {
+            Pot::Volume(track) => self.volume[track] = value,
+            Pot::Pan(track) => self.pan[track] = value,
+            Pot::Effect(track) => self.effect[track] = value,
+            Pot::Pitch => self.pitch = value,
+        }
+    }
+}
+
+/// Scanning, calibration and filtering of all pots.
+pub struct Pots {
+    scanner: Scanner,
+    calibration: [Linear; POTS],
+    filters: [Filter; POTS],
+    snapshot: Snapshot,
+}
+
+impl Pots {
+    pub fn new(calibration: &Calibration) -> Self {
+        Self {
+            scanner: Scanner::new(),
+            calibration: calibration.pots,
+            filters: [Filter::new(); POTS],
+            snapshot: Snapshot::default(),
+        }
+    }
+
+    pub fn set_calibration(&mut self, calibration: &Calibration) {
+        self.calibration = calibration.pots;
+    }
+
+    /// Channel to be selected on the multiplexer before the next step.
+    pub fn channel(&self) -> u8 {
+        self.scanner.channel()
+    }
+
+    /// Feed the reading of the pot multiplexer on the selected `channel`.
+    ///
+    /// Readings of the directly wired pots are requested through
+    /// `read_direct` once per scan. Returns the snapshot of all pots when
+    /// the scan finishes.
+    pub fn step(
+        &mut self,
+        multiplexed: f32,
+        read_direct: impl FnOnce() -> [f32; DIRECT_POTS],
+    ) -> Option<Snapshot> {
+        self.update(Pot::multiplexed(self.scanner.channel()), multiplexed);
+        if !self.scanner.advance() {
+            return None;
+        }
+        for (pot, reading) in DIRECT.into_iter().zip(read_direct()) {
+            self.update(pot, reading);
+        }
+        Some(self.snapshot)
+    }
+
+    fn update(&mut self, pot: Pot, reading: f32) {
+        let calibrated = self.calibration[pot.index()].apply(reading);
+        let value = self.filters[pot.index()].update(calibrated);
+        self.snapshot.set(pot, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_pot_has_its_own_index() {
+        let mut seen = [false; POTS];
+        for pot in Pot::all() {
+            assert!(!seen[pot.index()]);
+            seen[pot.index()] = true;
+        }
+        assert!(seen.iter().all(|seen| *seen));
+    }
+
+    #[test]
+    fn knobs_are_taken_as_settings() {
+        let mut snapshot = Snapshot::default();
+        for (i, pot) in Pot::all().enumerate() {
+            snapshot.set(pot, i as f32 / POTS as f32);
+        }
+
+        let knobs = snapshot.knobs();
+
+        for (track, settings) in knobs.tracks.iter().enumerate() {
+            assert_eq!(settings.volume, snapshot.get(Pot::Volume(track)));
+            assert_eq!(settings.pan, snapshot.get(Pot::Pan(track)));
+            assert_eq!(settings.strength, snapshot.get(Pot::Effect(track)));
+        }
+        assert_eq!(knobs.pitch, snapshot.get(Pot::Pitch));
+    }
+
+    #[test]
+    fn publish_snapshot_after_each_scan() {
+        let mut pots = Pots::new(&Calibration::default());
+        let mut direct_reads = 0;
+        for _ in 0..CHANNELS - 1 {
+            assert!(pots
+                .step(0.5, || {
+                    direct_reads += 1;
+                    [0.5; DIRECT_POTS]
+                })
+                .is_none());
+        }
+        let snapshot = pots.step(0.5, || {
+            direct_reads += 1;
+            [0.5; DIRECT_POTS]
+        });
+
+        assert!(snapshot.is_some());
+        assert_eq!(direct_reads, 1);
+    }
+
+    #[test]
+    fn readings_are_assigned_to_their_pots() {
+        let mut pots = Pots::new(&Calibration::default());
+        let mut snapshot = None;
+        for channel in 0..CHANNELS {
+            let reading = 0.1 * f32::from(channel) + 0.1;
+            assert_eq!(pots.channel(), channel);
+            snapshot = pots.step(reading, || [0.1, 0.2, 0.3, 0.4, 0.9]);
+        }
+        let snapshot = snapshot.unwrap();
+
+        let close = |a: f32, b: f32| (a - b).abs() < 0.01;
+        assert!(close(snapshot.pan[2], 0.1));
+        assert!(close(snapshot.pan[0], 0.3));
+        assert!(close(snapshot.effect[3], 0.6));
+        assert!(close(snapshot.volume[1], 0.2));
+        assert!(close(snapshot.pitch, 0.9));
+    }
+
+    #[test]
+    fn calibration_is_applied_before_filtering() {
+        let mut calibration = Calibration::default();
+        calibration.pots[Pot::Pitch.index()] = Linear {
+            offset: -0.1,
+            scale: 2.0,
+        };
+        let mut pots = Pots::new(&calibration);
+        let mut snapshot = None;
+        for _ in 0..CHANNELS {
+            snapshot = pots.step(0.5, || [0.3; DIRECT_POTS]);
+        }
+
+        assert!((snapshot.unwrap().pitch - 0.5).abs() < 0.01);
+    }
+}
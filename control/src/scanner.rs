@@ -0,0 +1,53 @@
+//! Scheduling of reads through the 4051 multiplexers.
+//!
+//! The address lines are shared by the pot and the GPIO multiplexer. A
+//! single channel is read per step, after which the next one is selected.
+//! This gives the output of the multiplexer a whole step to settle, without
+//! any busy waiting.
+
+/// Number of channels of a 4051 multiplexer.
+pub const CHANNELS: u8 = 8;
+
+/// Cycles through all channels of the multiplexers.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Scanner {
+    channel: u8,
+}
+
+impl Scanner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Channel that should be currently selected on the multiplexers.
+    pub fn channel(&self) -> u8 {
+        self.channel
+    }
+
+    /// Move to the next channel. Returns `true` when it starts a new scan
+    /// of all the channels.
+    pub fn advance(&mut self) -> bool {
+        self.channel = (self.channel + 1) % CHANNELS;
+        self.channel == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn visit_all_channels_before_starting_over() {
+        let mut scanner = Scanner::new();
+        let mut visited = [false; CHANNELS as usize];
+        for _ in 0..CHANNELS - 1 {
+            visited[scanner.channel() as usize] = true;
+            assert!(!scanner.advance());
+        }
+        visited[scanner.channel() as usize] = true;
+        assert!(scanner.advance());
+
+        assert!(visited.iter().all(|visited| *visited));
+        assert_eq!(scanner.channel(), 0);
+    }
+}
@@ -0,0 +1,183 @@
+//! Decoding of the cassette rotary switch.
+//!
+//! The switch taps a resistor ladder, turning each of its positions into a
+//! distinct voltage, see `hardware/rotary_switch_resistor_ladder.circuitjs.txt`.
+//! While the switch moves between positions, its break-before-make contacts
+//! leave the output open, reading as 0 V.
+//!
+//! The ladder is powered from the reference of the ADC, so the decoding
+//! works with readings relative to it and does not depend on the exact
+//! supply voltage.
+
+/// Number of positions of the switch.
+pub const POSITIONS: usize = 8;
+
+/// Resistors of the ladder, from the first position down to the output.
+const LADDER: [f32; POSITIONS - 1] = [
+    180_000.0, 62_000.0, 30_000.0, 18_000.0, 13_000.0, 9_100.0, 6_800.0,
+];
+
+/// Resistor between the output and the ground.
+const PULL_DOWN: f32 = 47_000.0;
+
+/// Number of consecutive readings of the same position required before
+/// it is accepted.
+const DEBOUNCE_READINGS: u32 = 10;
+
+/// Nominal reading of the given position, relative to the supply.
+const fn nominal(position: usize) -> f32 {
+    let mut resistance = 0.0;
+    let mut i = position;
+    while i < POSITIONS - 1 {
+        resistance += LADDER[i];
+        i += 1;
+    }
+    PULL_DOWN / (PULL_DOWN + resistance)
+}
+
+/// Lowest reading of each position. The first threshold separates an open
+/// switch from the first position, the rest lie half way between
+/// neighbouring positions to leave the most room for spread of resistors.
+const THRESHOLDS: [f32; POSITIONS] = {
+    let mut thresholds = [nominal(0) / 2.0; POSITIONS];
+    let mut position = 1;
+    while position < POSITIONS {
+        thresholds[position] = (nominal(position - 1) + nominal(position)) / 2.0;
+        position += 1;
+    }
+    thresholds
+};
+
+/// Position of the switch indicated by the reading, `None` while the switch
+/// is between positions.
+fn decode(reading: f32) -> Option<usize> {
+    if reading < THRESHOLDS[0] {
+        return None;
+    }
+    Some(
+        THRESHOLDS[1..]
+            .iter()
+            .take_while(|threshold| reading >= **threshold)
+            .count(),
+    )
+}
+
+/// Turns readings of the ladder into stable positions of the switch.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct CassetteSwitch {
+    position: Option<usize>,
+    candidate: Option<usize>,
+    readings: u32,
+}
+
+impl CassetteSwitch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed a reading relative to the supply. Returns the new position once
+    /// it settles on one different from the last.
+    ///
+    /// Readings taken while the contacts are open are skipped, the last
+    /// position is kept until the switch settles elsewhere.
+    pub fn update(&mut self, reading: f32) -> Option<usize> {
+        let Some(decoded) = decode(reading) else {
+            self.candidate = None;
+            return None;
+        };
+
+        if self.candidate == Some(decoded) {
+            self.readings = self.readings.saturating_add(1);
+        } else {
+            self.candidate = Some(decoded);
+            self.readings = 1;
+        }
+
+        if self.readings >= DEBOUNCE_READINGS && self.position != Some(decoded) {
+            self.position = Some(decoded);
+            Some(decoded)
+        } else {
+            None
+        }
+    }
+
+    /// Last settled position, `None` until the first one is found.
+    pub fn position(&self) -> Option<usize> {
+        self.position
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settle(switch: &mut CassetteSwitch, reading: f32) -> Option<usize> {
+        (0..DEBOUNCE_READINGS).fold(None, |_, _| switch.update(reading))
+    }
+
+    #[test]
+    fn nominal_readings_match_the_measured_circuit() {
+        let measured_volts = [0.424, 0.834, 1.252, 1.652, 2.044, 2.466, 2.883, 3.3];
+        for (position, volts) in measured_volts.iter().enumerate() {
+            assert!((nominal(position) * 3.3 - volts).abs() < 0.005);
+        }
+    }
+
+    #[test]
+    fn positions_are_decoded_despite_resistor_spread() {
+        const TOLERANCE: f32 = 0.05;
+        for position in 0..POSITIONS {
+            let below: f32 = LADDER[position..].iter().sum();
+            let lowest = PULL_DOWN * (1.0 - TOLERANCE)
+                / (PULL_DOWN * (1.0 - TOLERANCE) + below * (1.0 + TOLERANCE));
+            let highest = PULL_DOWN * (1.0 + TOLERANCE)
+                / (PULL_DOWN * (1.0 + TOLERANCE) + below * (1.0 - TOLERANCE));
+            assert_eq!(decode(lowest), Some(position));
+            assert_eq!(decode(highest.min(1.0)), Some(position));
+        }
+    }
+
+    #[test]
+    fn open_switch_is_not_decoded() {
+        assert_eq!(decode(0.0), None);
+        assert_eq!(decode(0.02), None);
+    }
+
+    #[test]
+    fn report_position_once_it_settles() {
+        let mut switch = CassetteSwitch::new();
+
+        for _ in 0..DEBOUNCE_READINGS - 1 {
+            assert_eq!(switch.update(nominal(3)), None);
+        }
+        assert_eq!(switch.update(nominal(3)), Some(3));
+        assert_eq!(switch.position(), Some(3));
+        assert_eq!(settle(&mut switch, nominal(3)), None);
+    }
+
+    #[test]
+    fn keep_position_over_break_before_make_gap() {
+        let mut switch = CassetteSwitch::new();
+        settle(&mut switch, nominal(3));
+
+        for _ in 0..50 {
+            assert_eq!(switch.update(0.0), None);
+        }
+        assert_eq!(switch.position(), Some(3));
+        assert_eq!(settle(&mut switch, nominal(4)), Some(4));
+    }
+
+    #[test]
+    fn ignore_positions_passed_only_briefly() {
+        let mut switch = CassetteSwitch::new();
+        settle(&mut switch, nominal(1));
+
+        for position in 2..6 {
+            for _ in 0..DEBOUNCE_READINGS / 2 {
+                assert_eq!(switch.update(nominal(position)), None);
+            }
+            switch.update(0.0);
+        }
+        assert_eq!(settle(&mut switch, nominal(6)), Some(6));
+    }
+}
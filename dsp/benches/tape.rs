@@ -0,0 +1,41 @@
+//! Tape saturation running on all tracks.
+//!
+//! The benchmark processes one second of audio with tape on every track.
+//! It runs on the host to compare revisions of the model, the measured time
+//! does not translate to the Cortex-M7 of the module. The share of the
+//! audio routine's budget taken on the target is reported by the
+//! `bench_tape` binary of the firmware, see `make bench-tape` there.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use placeholder_dsp::effect::{Effect, Tape};
+use placeholder_dsp::paging_buffer::TRACKS;
+
+const SAMPLE_RATE: usize = 48_000;
+const BLOCK: usize = 32;
+
+fn tape_on_all_tracks(c: &mut Criterion) {
+    let mut tapes = [Tape::default(); TRACKS];
+    for tape in tapes.iter_mut() {
+        tape.set_strength(0.8);
+    }
+    let mut phase = 0.0_f32;
+    let mut block = [0.0; BLOCK];
+
+    c.bench_function("tape on all tracks, 1 s", |b| {
+        b.iter(|| {
+            for _ in 0..SAMPLE_RATE / BLOCK {
+                for tape in tapes.iter_mut() {
+                    for x in block.iter_mut() {
+                        phase = (phase + 0.01) % 1.0;
+                        *x = phase * 2.0 - 1.0;
+                    }
+                    tape.process(black_box(&mut block));
+                }
+            }
+        })
+    });
+}
+
+criterion_group!(benches, tape_on_all_tracks);
+criterion_main!(benches);
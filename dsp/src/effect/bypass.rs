@@ -0,0 +1,14 @@
+//! Effect leaving the signal untouched.
+
+use super::Effect;
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Bypass;
+
+impl Effect for Bypass {
+    fn set_strength(&mut self, _strength: f32) {}
+
+    fn process(&mut self, _block: &mut [f32]) {}
+
+    fn reset(&mut self) {}
+}
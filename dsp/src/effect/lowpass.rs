@@ -0,0 +1,68 @@
+//! One-pole low-pass filter, darkening the track with increasing strength.
+
+use super::Effect;
+
+/// Coefficient of the filter at full strength, cutting off around 80 Hz at
+/// 48 kHz.
+const MIN_COEFFICIENT: f32 = 0.01;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Lowpass {
+    coefficient: f32,
+    memory: f32,
+}
+
+impl Default for Lowpass {
+    fn default() -> Self {
+        Self {
+            coefficient: 1.0,
+            memory: 0.0,
+        }
+    }
+}
+
+impl Effect for Lowpass {
+    fn set_strength(&mut self, strength: f32) {
+        // Squared, so the cutoff sweeps evenly to the ear.
+        let openness = 1.0 - strength.clamp(0.0, 1.0);
+        self.coefficient = MIN_COEFFICIENT + (1.0 - MIN_COEFFICIENT) * openness * openness;
+    }
+
+    fn process(&mut self, block: &mut [f32]) {
+        for x in block.iter_mut() {
+            self.memory += (*x - self.memory) * self.coefficient;
+            *x = self.memory;
+        }
+    }
+
+    fn reset(&mut self) {
+        self.memory = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alternating() -> [f32; 64] {
+        core::array::from_fn(|i| if i % 2 == 0 { 1.0 } else { -1.0 })
+    }
+
+    #[test]
+    fn zero_strength_passes_the_signal() {
+        let mut lowpass = Lowpass::default();
+        lowpass.set_strength(0.0);
+        let mut block = alternating();
+        lowpass.process(&mut block);
+        assert_eq!(block, alternating());
+    }
+
+    #[test]
+    fn full_strength_attenuates_high_frequencies() {
+        let mut lowpass = Lowpass::default();
+        lowpass.set_strength(1.0);
+        let mut block = alternating();
+        lowpass.process(&mut block);
+        assert!(block.iter().all(|x| x.abs() < 0.02));
+    }
+}
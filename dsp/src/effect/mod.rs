@@ -0,0 +1,47 @@
+//! Audio effects applied on individual tracks.
+//!
+//! Each effect is controlled by a single macro parameter, its strength,
+//! ranging from 0.0 to 1.0. The strength is set by the effect knob of the
+//! track, while holding the PP button and turning the knob switches
+//! between the effects.
+//!
+//! Effects are listed in a static registry, see `registry`, and are
+//! referred to by their index in it. Each track holds its effect in a
+//! `Slot`, which crossfades between the old and the new effect when they
+//! get switched, so the switch does not click.
+
+mod bypass;
+mod lowpass;
+mod registry;
+mod slot;
+mod tape;
+mod wow_flutter;
+
+pub use bypass::Bypass;
+pub use lowpass::Lowpass;
+pub use registry::{create, name, Instance, EFFECTS};
+pub use slot::{Rack, Slot, CROSSFADE};
+pub use tape::{Tape, OVERSAMPLING};
+pub use wow_flutter::WowFlutter;
+
+/// Size of the state an effect can persist.
+pub const STATE_SIZE: usize = 16;
+
+pub trait Effect {
+    /// Set the macro parameter, ranging from 0.0 to 1.0.
+    fn set_strength(&mut self, strength: f32);
+
+    /// Process the block of samples in place.
+    fn process(&mut self, block: &mut [f32]);
+
+    /// Clear the internal state, e.g. buffers and filter memory, as if the
+    /// effect was just created.
+    fn reset(&mut self);
+
+    /// Store state that should survive a restart, besides the strength,
+    /// e.g. a random seed.
+    fn save(&self, _state: &mut [u8; STATE_SIZE]) {}
+
+    /// Recover state stored by `save`.
+    fn load(&mut self, _state: &[u8; STATE_SIZE]) {}
+}
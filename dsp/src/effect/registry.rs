@@ -0,0 +1,101 @@
+//! Static list of all available effects.
+//!
+//! Effects are stored without allocation, so the registry generates an
+//! enum holding any of them. To add an effect, implement `Effect` and
+//! `Default` for it and append it to the list below. Indices of effects
+//! are persisted in cassette settings, so existing effects must never be
+//! reordered.
+
+use super::{Bypass, Effect, Lowpass, Tape, WowFlutter, STATE_SIZE};
+
+macro_rules! registry {
+    ($($variant:ident => $name:literal),* $(,)?) => {
+        /// Any of the registered effects.
+        // Without allocation, each slot reserves space for the largest
+        // effect anyway.
+        #[allow(clippy::large_enum_variant)]
+        #[derive(Clone, Copy, Debug)]
+        pub enum Instance {
+            $($variant($variant)),*
+        }
+
+        const NAMES: &[&str] = &[$($name),*];
+
+        /// Create the effect with the given index, falling back to the
+        /// first one for unknown indices.
+        pub fn create(index: u8) -> Instance {
+            let constructors: &[fn() -> Instance] =
+                &[$(|| Instance::$variant($variant::default())),*];
+            constructors
+                .get(index as usize)
+                .unwrap_or(&constructors[0])()
+        }
+
+        impl Effect for Instance {
+            fn set_strength(&mut self, strength: f32) {
+                match self {
+                    $(Instance::$variant(effect) => effect.set_strength(strength)),*
+                }
+            }
+
+            fn process(&mut self, block: &mut [f32]) {
+                match self {
+                    $(Instance::$variant(effect) => effect.process(block)),*
+                }
+            }
+
+            fn reset(&mut self) {
+                match self {
+                    $(Instance::$variant(effect) => effect.reset()),*
+                }
+            }
+
+            fn save(&self, state: &mut [u8; STATE_SIZE]) {
+                match self {
+                    $(Instance::$variant(effect) => effect.save(state)),*
+                }
+            }
+
+            fn load(&mut self, state: &[u8; STATE_SIZE]) {
+                match self {
+                    $(Instance::$variant(effect) => effect.load(state)),*
+                }
+            }
+        }
+    };
+}
+
+registry! {
+    Bypass => "Bypass",
+    Lowpass => "Low-pass",
+    Tape => "Tape",
+    WowFlutter => "Wow and flutter",
+}
+
+/// Number of registered effects.
+pub const EFFECTS: usize = NAMES.len();
+
+/// Human readable name of the effect, used in logs.
+pub fn name(index: u8) -> Option<&'static str> {
+    NAMES.get(index as usize).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn effects_are_created_by_their_index() {
+        assert!(matches!(create(0), Instance::Bypass(_)));
+        assert!(matches!(create(1), Instance::Lowpass(_)));
+        assert!(matches!(create(2), Instance::Tape(_)));
+        assert!(matches!(create(3), Instance::WowFlutter(_)));
+        assert_eq!(name(1), Some("Low-pass"));
+    }
+
+    #[test]
+    fn unknown_index_falls_back_to_the_first_effect() {
+        assert!(matches!(create(EFFECTS as u8), Instance::Bypass(_)));
+        assert_eq!(name(EFFECTS as u8), None);
+    }
+}
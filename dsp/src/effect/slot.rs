@@ -0,0 +1,271 @@
+//! Holders of effects of each track, crossfading between switched effects.
+
+use crate::paging_buffer::{Settings, TRACKS};
+
+use super::{create, Effect, Instance, EFFECTS, STATE_SIZE};
+
+/// Length of the crossfade between switched effects, 10 ms at 48 kHz.
+pub const CROSSFADE: usize = 480;
+
+/// Blocks are processed in chunks of this size, bounding the scratch
+/// buffers kept on the stack.
+const CHUNK: usize = 32;
+
+/// Effect of a single track.
+#[derive(Debug)]
+pub struct Slot {
+    active: Instance,
+    index: u8,
+    strength: f32,
+    // Effect being faded out after a switch.
+    outgoing: Option<Instance>,
+    fade: usize,
+    // Effect selected while a crossfade was running, switched to once it
+    // finishes.
+    pending: Option<u8>,
+}
+
+impl Default for Slot {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl Slot {
+    pub fn new(index: u8) -> Self {
+        Self {
+            index: known(index),
+            active: create(index),
+            strength: 0.0,
+            outgoing: None,
+            fade: 0,
+            pending: None,
+        }
+    }
+
+    /// Index of the active effect within the registry.
+    pub fn effect(&self) -> u8 {
+        self.index
+    }
+
+    /// Switch to the effect with the given index, crossfading from the
+    /// current one. Selecting the active effect again is ignored.
+    ///
+    /// A selection made during a running crossfade is held until it
+    /// finishes, since replacing the faded out effect would click.
+    ///
+    /// The new effect starts with the current strength.
+    pub fn select(&mut self, index: u8) {
+        let index = known(index);
+        if self.outgoing.is_some() {
+            self.pending = (index != self.index).then_some(index);
+            return;
+        }
+        if index == self.index {
+            return;
+        }
+        let mut effect = create(index);
+        effect.set_strength(self.strength);
+        self.index = index;
+        self.outgoing = Some(core::mem::replace(&mut self.active, effect));
+        self.fade = 0;
+    }
+
+    pub fn set_strength(&mut self, strength: f32) {
+        self.strength = strength;
+        self.active.set_strength(strength);
+    }
+
+    pub fn process(&mut self, block: &mut [f32]) {
+        for chunk in block.chunks_mut(CHUNK) {
+            let Some(outgoing) = self.outgoing.as_mut() else {
+                self.active.process(chunk);
+                continue;
+            };
+
+            let mut old = [0.0; CHUNK];
+            let old = &mut old[..chunk.len()];
+            old.copy_from_slice(chunk);
+            outgoing.process(old);
+            self.active.process(chunk);
+
+            for (x, old) in chunk.iter_mut().zip(old.iter()) {
+                self.fade += 1;
+                let gain = (self.fade as f32 / CROSSFADE as f32).min(1.0);
+                *x = old + (*x - old) * gain;
+            }
+            if self.fade >= CROSSFADE {
+                self.outgoing = None;
+                if let Some(index) = self.pending.take() {
+                    self.select(index);
+                }
+            }
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.active.reset();
+        self.outgoing = None;
+        if let Some(index) = self.pending.take() {
+            self.select(index);
+            self.outgoing = None;
+        }
+    }
+
+    pub fn save(&self, state: &mut [u8; STATE_SIZE]) {
+        self.active.save(state);
+    }
+
+    pub fn load(&mut self, state: &[u8; STATE_SIZE]) {
+        self.active.load(state);
+    }
+}
+
+/// Index of the effect as created by `create`, unknown ones falling back
+/// to the first effect.
+fn known(index: u8) -> u8 {
+    if (index as usize) < EFFECTS {
+        index
+    } else {
+        0
+    }
+}
+
+/// Slots of all tracks.
+#[derive(Default, Debug)]
+pub struct Rack {
+    pub slots: [Slot; TRACKS],
+}
+
+impl Rack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Rack with effects and their strengths as given by the settings,
+    /// settled right away instead of crossfading from the first effect.
+    pub fn with_settings(settings: &Settings) -> Self {
+        let mut rack = Self {
+            slots: core::array::from_fn(|track| Slot::new(settings.tracks[track].effect)),
+        };
+        for (slot, track) in rack.slots.iter_mut().zip(&settings.tracks) {
+            slot.set_strength(track.strength);
+            slot.reset();
+        }
+        rack
+    }
+
+    /// Select effects and their strengths as given by the settings.
+    pub fn set_settings(&mut self, settings: &Settings) {
+        for (slot, track) in self.slots.iter_mut().zip(&settings.tracks) {
+            slot.select(track.effect);
+            slot.set_strength(track.strength);
+        }
+    }
+
+    /// Apply effects on the block of tracks in place.
+    pub fn process(&mut self, tracks: &mut [[f32; TRACKS]]) {
+        for frames in tracks.chunks_mut(CHUNK) {
+            for (track, slot) in self.slots.iter_mut().enumerate() {
+                let mut block = [0.0; CHUNK];
+                let block = &mut block[..frames.len()];
+                for (x, frame) in block.iter_mut().zip(frames.iter()) {
+                    *x = frame[track];
+                }
+                slot.process(block);
+                for (x, frame) in block.iter().zip(frames.iter_mut()) {
+                    frame[track] = *x;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOWPASS: u8 = 1;
+
+    fn alternating<const N: usize>() -> [f32; N] {
+        core::array::from_fn(|i| if i % 2 == 0 { 1.0 } else { -1.0 })
+    }
+
+    #[test]
+    fn switching_effects_crossfades_between_them() {
+        let mut slot = Slot::new(0);
+        slot.set_strength(1.0);
+        slot.select(LOWPASS);
+        assert_eq!(slot.effect(), LOWPASS);
+
+        let mut block: [f32; CROSSFADE] = alternating();
+        slot.process(&mut block);
+
+        // The bypassed signal fades out, samples with the same sign of
+        // input decrease in amplitude steadily.
+        let even: heapless::Vec<f32, CROSSFADE> = block.iter().step_by(2).copied().collect();
+        for pair in even.windows(2) {
+            assert!(pair[1] <= pair[0] + 0.0001);
+        }
+        assert!(block[0] > 0.9);
+        assert!(block[CROSSFADE - 2].abs() < 0.05);
+
+        // The crossfade is over, the new effect is processed alone.
+        let mut block: [f32; 64] = alternating();
+        slot.process(&mut block);
+        assert!(block.iter().all(|x| x.abs() < 0.02));
+    }
+
+    #[test]
+    fn switching_during_crossfade_waits_for_it_to_finish() {
+        const HALF: usize = CROSSFADE / 2;
+        let mut slot = Slot::new(0);
+        slot.set_strength(1.0);
+        slot.select(LOWPASS);
+        let mut first: [f32; HALF] = alternating();
+        slot.process(&mut first);
+
+        // Switching back within the crossfade must not jump, the amplitude
+        // keeps following the running crossfade.
+        slot.select(0);
+        let mut second: [f32; CROSSFADE] = alternating();
+        slot.process(&mut second);
+        let amplitudes = first.iter().chain(second.iter()).step_by(2);
+        let amplitudes: heapless::Vec<f32, CROSSFADE> = amplitudes.copied().collect();
+        for pair in amplitudes.windows(2) {
+            assert!((pair[1] - pair[0]).abs() < 0.02);
+        }
+
+        // The held selection faded in after the first crossfade.
+        assert_eq!(slot.effect(), 0);
+        let mut block: [f32; CROSSFADE] = alternating();
+        slot.process(&mut block);
+        assert_eq!(block[CROSSFADE - 2], 1.0);
+    }
+
+    #[test]
+    fn selecting_active_effect_does_not_restart_it() {
+        let mut slot = Slot::new(LOWPASS);
+        slot.select(LOWPASS);
+        let mut block = [1.0; 8];
+        slot.process(&mut block);
+        assert_eq!(block, [1.0; 8]);
+    }
+
+    #[test]
+    fn rack_applies_effects_per_track() {
+        let mut rack = Rack::new();
+        let mut settings = Settings::default();
+        settings.tracks[2].effect = LOWPASS;
+        settings.tracks[2].strength = 1.0;
+        rack.set_settings(&settings);
+
+        let mut tracks: [[f32; TRACKS]; CROSSFADE + 64] =
+            core::array::from_fn(|i| [if i % 2 == 0 { 1.0 } else { -1.0 }; TRACKS]);
+        rack.process(&mut tracks);
+
+        let last = tracks[CROSSFADE + 62];
+        assert_eq!(last[0], 1.0);
+        assert!(last[2].abs() < 0.02);
+    }
+}
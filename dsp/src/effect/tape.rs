@@ -0,0 +1,312 @@
+//! Tape saturation with magnetic hysteresis.
+//!
+//! The model follows the structure of Jiles-Atherton, splitting the
+//! magnetization of the tape into a reversible and an irreversible part,
+//! but trades its differential equation for an efficient approximation.
+//! The irreversible part is driven through a play operator, which lags
+//! behind the field by the coercivity of the tape, and then saturated. The
+//! reversible part saturates the field directly. Their mix draws the
+//! typical hysteresis loop, widening and compressing with the drive.
+//!
+//! Saturation generates harmonics well above the audible range. Those
+//! exceeding the Nyquist frequency would fold back as inharmonic aliases,
+//! so the model runs at twice the sample rate: the input is interpolated
+//! and the magnetization decimated by a half-band filter, removing
+//! harmonics between the original and the doubled Nyquist frequency before
+//! they fold back. The filters delay the wet signal by `Tape::LATENCY` and
+//! the dry signal is delayed to match. Increasing strength raises the drive
+//! and the coercivity and blends the saturated signal in.
+//!
+//! The effect is benchmarked on the host, see `benches/tape.rs`, and
+//! measured on the target by the `bench_tape` binary of the firmware.
+
+use super::Effect;
+
+/// Oversampling factor of the model.
+pub const OVERSAMPLING: usize = 2;
+
+/// Number of non-zero coefficients on each side of the half-band filter.
+const TAPS: usize = 8;
+
+/// Non-zero coefficients of the half-band filter, a Kaiser windowed sinc
+/// with beta of 7 and 31 taps, from the center outwards. Scaled by two so
+/// they sum up to a half on each side. The response is flat up to 19 kHz
+/// and attenuates by more than 70 dB above 31 kHz.
+const HALF_BAND: [f32; TAPS] = [
+    0.627_439_2,
+    -0.186_170_47,
+    0.087_972_94,
+    -0.043_181_34,
+    0.019_607_046,
+    -0.007_544_514,
+    0.002_128_886,
+    -0.000_251_708,
+];
+
+/// Drive into the tape at full strength.
+const MAX_DRIVE: f32 = 8.0;
+
+/// Half of the width of the hysteresis loop at full strength, in units of
+/// the driven field.
+const MAX_COERCIVITY: f32 = 0.4;
+
+/// Portion of the magnetization that is reversible, as `c` of
+/// Jiles-Atherton.
+const REVERSIBLE: f32 = 0.4;
+
+/// Sample half way between the two in the middle of the history,
+/// interpolated by the half-band filter.
+fn half_band(history: &[f32; 2 * TAPS]) -> f32 {
+    let (before, after) = history.split_at(TAPS);
+    let pairs = before.iter().rev().zip(after);
+    HALF_BAND
+        .iter()
+        .zip(pairs)
+        .map(|(coefficient, (a, b))| coefficient * (a + b))
+        .sum()
+}
+
+/// Rational approximation of `tanh`, clamped where it reaches exactly 1.0.
+fn saturate(x: f32) -> f32 {
+    let x = x.clamp(-3.0, 3.0);
+    let x2 = x * x;
+    x * (27.0 + x2) / (27.0 + 9.0 * x2)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Tape {
+    strength: f32,
+    drive: f32,
+    coercivity: f32,
+    makeup: f32,
+    // State of the play operator, lagging behind the field.
+    play: f32,
+    // Recent input, the oldest first.
+    input: [f32; 2 * TAPS],
+    // Recent magnetization at the samples added by oversampling and at the
+    // original ones, the oldest first.
+    added: [f32; 2 * TAPS],
+    original: [f32; TAPS + 1],
+}
+
+impl Default for Tape {
+    fn default() -> Self {
+        let mut tape = Self {
+            strength: 0.0,
+            drive: 1.0,
+            coercivity: 0.0,
+            makeup: 1.0,
+            play: 0.0,
+            input: [0.0; 2 * TAPS],
+            added: [0.0; 2 * TAPS],
+            original: [0.0; TAPS + 1],
+        };
+        tape.set_strength(0.0);
+        tape
+    }
+}
+
+impl Tape {
+    /// Delay of the processed signal in samples.
+    pub const LATENCY: usize = 2 * TAPS - 1;
+
+    /// Magnetization of the tape for the given input sample.
+    fn magnetize(&mut self, x: f32) -> f32 {
+        let field = x * self.drive;
+        // Unlike `clamp`, this cannot panic on a NaN input.
+        self.play = self
+            .play
+            .max(field - self.coercivity)
+            .min(field + self.coercivity);
+        let irreversible = saturate(self.play);
+        let reversible = saturate(field);
+        (irreversible + (reversible - irreversible) * REVERSIBLE) * self.makeup
+    }
+}
+
+impl Effect for Tape {
+    fn set_strength(&mut self, strength: f32) {
+        self.strength = strength.clamp(0.0, 1.0);
+        self.drive = 1.0 + (MAX_DRIVE - 1.0) * self.strength;
+        self.coercivity = MAX_COERCIVITY * self.strength;
+        // Keeps loudness roughly even while the drive rises.
+        self.makeup = 1.0 / libm::sqrtf(self.drive);
+    }
+
+    fn process(&mut self, block: &mut [f32]) {
+        for x in block.iter_mut() {
+            push(&mut self.input, *x);
+
+            // Magnetize the interpolated sample, then the original one it
+            // precedes.
+            let added = self.magnetize(half_band(&self.input));
+            let original = self.magnetize(self.input[TAPS]);
+            push(&mut self.added, added);
+            push(&mut self.original, original);
+
+            // Decimation keeps the original samples, filtering out what
+            // would fold back. The added ones contribute only through the
+            // odd coefficients of the filter.
+            let wet = 0.5 * (self.original[0] + half_band(&self.added));
+            let dry = self.input[0];
+            *x = dry + (wet - dry) * self.strength;
+        }
+    }
+
+    fn reset(&mut self) {
+        self.play = 0.0;
+        self.input = [0.0; 2 * TAPS];
+        self.added = [0.0; 2 * TAPS];
+        self.original = [0.0; TAPS + 1];
+    }
+}
+
+/// Append the sample to the history, dropping the oldest one.
+fn push<const N: usize>(history: &mut [f32; N], x: f32) {
+    history.copy_within(1.., 0);
+    history[N - 1] = x;
+}
+
+#[cfg(test)]
+mod tests {
+    use core::f32::consts::TAU;
+
+    use super::*;
+
+    const LATENCY: usize = Tape::LATENCY;
+
+    fn sine<const N: usize>(amplitude: f32, period: usize) -> [f32; N] {
+        core::array::from_fn(|i| amplitude * libm::sinf(TAU * i as f32 / period as f32))
+    }
+
+    #[test]
+    fn saturation_is_bounded_and_smooth() {
+        assert_eq!(saturate(0.0), 0.0);
+        assert!((saturate(3.0) - 1.0).abs() < 0.0001);
+        assert_eq!(saturate(10.0), saturate(3.0));
+        for x in [0.2, 0.5, 1.0, 2.0] {
+            assert!((saturate(x) - libm::tanhf(x)).abs() < 0.03);
+        }
+    }
+
+    /// Power of the given frequency in the signal, in cycles per sample.
+    fn power(signal: &[f32], frequency: f64) -> f64 {
+        let (mut re, mut im) = (0.0, 0.0);
+        for (i, x) in signal.iter().enumerate() {
+            let phase = core::f64::consts::TAU * frequency * i as f64;
+            re += *x as f64 * libm::cos(phase);
+            im += *x as f64 * libm::sin(phase);
+        }
+        (re * re + im * im) / (signal.len() * signal.len()) as f64
+    }
+
+    /// The previous oversampling, interpolating linearly and decimating
+    /// through a triangle, kept as the reference for aliasing.
+    fn process_linear(tape: &mut Tape, block: &mut [f32]) {
+        let (mut previous_input, mut previous_output) = (0.0, 0.0);
+        for x in block.iter_mut() {
+            let middle = tape.magnetize((previous_input + *x) * 0.5);
+            let current = tape.magnetize(*x);
+            let wet = 0.25 * previous_output + 0.5 * middle + 0.25 * current;
+            previous_input = *x;
+            previous_output = current;
+            *x = wet;
+        }
+    }
+
+    #[test]
+    fn zero_strength_delays_the_signal() {
+        let mut tape = Tape::default();
+        let mut block: [f32; 256] = sine(0.9, 64);
+        tape.process(&mut block);
+        assert_eq!(block[..LATENCY], [0.0; LATENCY]);
+        assert_eq!(block[LATENCY..], sine::<256>(0.9, 64)[..256 - LATENCY]);
+    }
+
+    #[test]
+    fn half_band_interpolates_smooth_signal() {
+        let signal: [f32; 2 * TAPS] = core::array::from_fn(|i| libm::sinf(0.2 * i as f32));
+        let expected = libm::sinf(0.2 * (TAPS as f32 - 0.5));
+        assert!((half_band(&signal) - expected).abs() < 0.001);
+    }
+
+    #[test]
+    fn harmonics_above_nyquist_do_not_fold_back() {
+        // At 48 kHz, a sine of 11 kHz has its third harmonic at 33 kHz,
+        // folding back to 15 kHz, and its fifth at 55 kHz, folding back
+        // from the oversampled rate to 7 kHz.
+        const LENGTH: usize = 4800;
+        const FUNDAMENTAL: f64 = 0.11 / 0.48;
+        const ALIASES: [f64; 2] = [0.15 / 0.48, 0.07 / 0.48];
+        let input: [f32; 2 * LENGTH] = core::array::from_fn(|i| {
+            0.5 * libm::sin(core::f64::consts::TAU * FUNDAMENTAL * i as f64) as f32
+        });
+        let aliasing = |signal: &[f32]| {
+            // Skip the transient of the hysteresis.
+            let signal = &signal[LENGTH..];
+            let aliases: f64 = ALIASES.iter().map(|f| power(signal, *f)).sum();
+            aliases / power(signal, FUNDAMENTAL)
+        };
+
+        let mut tape = Tape::default();
+        tape.set_strength(1.0);
+        let mut half_band = input;
+        tape.process(&mut half_band);
+
+        let mut tape = Tape::default();
+        tape.set_strength(1.0);
+        let mut linear = input;
+        process_linear(&mut tape, &mut linear);
+
+        let (half_band, linear) = (aliasing(&half_band), aliasing(&linear));
+        // Aliases are 80 dB below the fundamental, while the linear
+        // oversampling kept them only 26 dB below it.
+        assert!(half_band < 1e-8, "{half_band}");
+        assert!(linear > 1e-3, "{linear}");
+    }
+
+    #[test]
+    fn full_strength_compresses_peaks() {
+        let mut tape = Tape::default();
+        tape.set_strength(1.0);
+        let mut block: [f32; 1024] = sine(1.0, 64);
+        tape.process(&mut block);
+        let peak = block.iter().fold(0.0_f32, |peak, x| peak.max(x.abs()));
+        assert!(peak < 0.5);
+        assert!(peak > 0.1);
+    }
+
+    #[test]
+    fn magnetization_depends_on_history() {
+        let mut tape = Tape::default();
+        tape.set_strength(1.0);
+
+        // Zero field is reached once from above and once from below.
+        for x in [0.0, 0.1, 0.2, 0.3] {
+            tape.magnetize(x);
+        }
+        let rising = tape.magnetize(0.0);
+        for x in [-0.1, -0.2, -0.3, -0.1] {
+            tape.magnetize(x);
+        }
+        let falling = tape.magnetize(0.0);
+        assert!(rising > falling);
+    }
+
+    #[test]
+    fn reset_clears_the_history() {
+        let mut tape = Tape::default();
+        tape.set_strength(1.0);
+        let mut block: [f32; 64] = sine(1.0, 16);
+        tape.process(&mut block);
+        tape.reset();
+
+        let mut fresh = Tape::default();
+        fresh.set_strength(1.0);
+        let mut a: [f32; 64] = sine(0.5, 32);
+        let mut b = a;
+        tape.process(&mut a);
+        fresh.process(&mut b);
+        assert_eq!(a, b);
+    }
+}
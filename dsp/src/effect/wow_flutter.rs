@@ -0,0 +1,276 @@
+//! Wow and flutter, the wobbling speed of a worn tape transport.
+//!
+//! The playback of the track is passed through a short delay line with a
+//! modulated length. Changes of the length shift the pitch the same way an
+//! unsteady tape speed would. The modulation combines slow periodic wow,
+//! fast periodic flutter, and a random drift.
+//!
+//! Unlike varying the read speed of the paging buffer, the delay line
+//! leaves the position of the tape untouched. The length of the delay
+//! oscillates around a constant center of `WowFlutter::LATENCY` samples,
+//! independent of strength. Playback passed through the effect is read
+//! that far ahead of the recording position, so it wobbles around the
+//! recording position without drifting away from it, and overdubs stay
+//! aligned with what was recorded before.
+
+use core::f32::consts::TAU;
+
+use super::{Effect, STATE_SIZE};
+
+/// Sample rate the effect is tuned for.
+const SAMPLE_RATE: f32 = 48_000.0;
+
+/// Length of the delay line, must be a power of two.
+const LENGTH: usize = 256;
+
+/// Swing of the delay around its center at full strength, in samples. With
+/// wow at its rate, this corresponds to pitch deviation of about 0.5 %.
+const MAX_DEPTH: f32 = 80.0;
+
+const WOW_HZ: f32 = 0.6;
+const FLUTTER_HZ: f32 = 7.0;
+/// Rate at which the random drift picks a new target.
+const DRIFT_HZ: f32 = 2.0;
+
+/// Contributions of the components to the modulation, summing to 1.0 so
+/// the delay never leaves its range.
+const WOW: f32 = 0.6;
+const FLUTTER: f32 = 0.1;
+const DRIFT: f32 = 0.3;
+
+/// Weight of the target in smoothed parameters, per sample.
+const SMOOTHING: f32 = 0.001;
+
+const DEFAULT_SEED: u32 = 0x2545_f491;
+
+const _: () = assert!(LENGTH.is_power_of_two());
+const _: () = assert!(2.0 * MAX_DEPTH + 2.0 < LENGTH as f32);
+
+#[derive(Clone, Copy, Debug)]
+pub struct WowFlutter {
+    buffer: [f32; LENGTH],
+    write: usize,
+    target_depth: f32,
+    depth: f32,
+    wow_phase: f32,
+    flutter_phase: f32,
+    drift_phase: f32,
+    drift_target: f32,
+    drift: f32,
+    seed: u32,
+    random: u32,
+}
+
+impl Default for WowFlutter {
+    fn default() -> Self {
+        Self {
+            buffer: [0.0; LENGTH],
+            write: 0,
+            target_depth: 0.0,
+            depth: 0.0,
+            wow_phase: 0.0,
+            flutter_phase: 0.0,
+            drift_phase: 0.0,
+            drift_target: 0.0,
+            drift: 0.0,
+            seed: DEFAULT_SEED,
+            random: DEFAULT_SEED,
+        }
+    }
+}
+
+impl WowFlutter {
+    /// Center of the delay in samples, by which the input is expected to
+    /// be read ahead.
+    pub const LATENCY: usize = MAX_DEPTH as usize;
+
+    /// Random value ranging from -1.0 to 1.0, using xorshift.
+    fn next_random(&mut self) -> f32 {
+        let mut x = self.random;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.random = x;
+        x as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+
+    /// Modulation of the delay ranging from -1.0 to 1.0.
+    fn modulation(&mut self) -> f32 {
+        self.wow_phase = (self.wow_phase + WOW_HZ / SAMPLE_RATE) % 1.0;
+        self.flutter_phase = (self.flutter_phase + FLUTTER_HZ / SAMPLE_RATE) % 1.0;
+        self.drift_phase += DRIFT_HZ / SAMPLE_RATE;
+        if self.drift_phase >= 1.0 {
+            self.drift_phase -= 1.0;
+            self.drift_target = self.next_random();
+        }
+        self.drift += (self.drift_target - self.drift) * SMOOTHING;
+
+        libm::sinf(self.wow_phase * TAU) * WOW
+            + libm::sinf(self.flutter_phase * TAU) * FLUTTER
+            + self.drift * DRIFT
+    }
+
+    /// Length of the delay for the next sample, in samples.
+    fn delay(&mut self) -> f32 {
+        self.depth += (self.target_depth - self.depth) * SMOOTHING;
+        MAX_DEPTH + self.depth * self.modulation()
+    }
+}
+
+impl Effect for WowFlutter {
+    fn set_strength(&mut self, strength: f32) {
+        self.target_depth = MAX_DEPTH * strength.clamp(0.0, 1.0);
+    }
+
+    fn process(&mut self, block: &mut [f32]) {
+        for x in block.iter_mut() {
+            self.buffer[self.write] = *x;
+            let delay = self.delay();
+            let whole = delay as usize;
+            let fraction = delay - whole as f32;
+            let a = self.buffer[self.write.wrapping_sub(whole) & (LENGTH - 1)];
+            let b = self.buffer[self.write.wrapping_sub(whole + 1) & (LENGTH - 1)];
+            *x = a + (b - a) * fraction;
+            self.write = (self.write + 1) & (LENGTH - 1);
+        }
+    }
+
+    fn reset(&mut self) {
+        let seed = self.seed;
+        let target_depth = self.target_depth;
+        *self = Self::default();
+        self.target_depth = target_depth;
+        self.depth = target_depth;
+        self.seed = seed;
+        self.random = seed;
+    }
+
+    fn save(&self, state: &mut [u8; STATE_SIZE]) {
+        state[..4].copy_from_slice(&self.seed.to_le_bytes());
+    }
+
+    fn load(&mut self, state: &[u8; STATE_SIZE]) {
+        let seed = u32::from_le_bytes(state[..4].try_into().unwrap());
+        // Xorshift never leaves zero.
+        if seed != 0 {
+            self.seed = seed;
+            self.random = seed;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ramp<const N: usize>() -> [f32; N] {
+        core::array::from_fn(|i| i as f32)
+    }
+
+    #[test]
+    fn zero_strength_delays_by_latency() {
+        const LATENCY: usize = WowFlutter::LATENCY;
+        let mut effect = WowFlutter::default();
+        let mut block: [f32; 512] = ramp();
+        effect.process(&mut block);
+        assert_eq!(block[..LATENCY], [0.0; LATENCY]);
+        assert_eq!(block[LATENCY..], ramp::<512>()[..512 - LATENCY]);
+    }
+
+    #[test]
+    fn output_is_centered_on_the_input_position_at_any_strength() {
+        const BLOCK: usize = 480;
+        for strength in [0.0, 0.3, 0.7, 1.0] {
+            let mut effect = WowFlutter::default();
+            effect.set_strength(strength);
+            effect.reset();
+
+            // The input carries its position on the tape, read ahead by
+            // the latency. Ten seconds cover several periods of all
+            // components.
+            let mut sum = 0.0;
+            let blocks = 10 * SAMPLE_RATE as usize / BLOCK;
+            for b in 0..blocks {
+                let start = b * BLOCK;
+                let mut block: [f32; BLOCK] =
+                    core::array::from_fn(|i| (start + i + WowFlutter::LATENCY) as f32);
+                effect.process(&mut block);
+                if b > 0 {
+                    for (i, position) in block.iter().enumerate() {
+                        sum += (*position as f64) - (start + i) as f64;
+                    }
+                }
+            }
+            let mean = sum / ((blocks - 1) * BLOCK) as f64;
+            assert!(mean.abs() < 0.1 * MAX_DEPTH as f64, "{strength}: {mean}");
+        }
+    }
+
+    #[test]
+    fn delay_wobbles_around_constant_center() {
+        let mut effect = WowFlutter::default();
+        effect.set_strength(1.0);
+
+        // Ten seconds, covering several periods of all components.
+        let samples = 10 * SAMPLE_RATE as usize;
+        let mut sum = 0.0;
+        let mut min = f32::MAX;
+        let mut max = f32::MIN;
+        for _ in 0..SAMPLE_RATE as usize {
+            effect.delay();
+        }
+        for _ in 0..samples {
+            let delay = effect.delay();
+            sum += delay as f64;
+            min = min.min(delay);
+            max = max.max(delay);
+        }
+
+        assert!(min >= 0.0 && max <= 2.0 * MAX_DEPTH);
+        assert!(max - min > MAX_DEPTH);
+        let mean = (sum / samples as f64) as f32;
+        assert!((mean - MAX_DEPTH).abs() < 0.2 * MAX_DEPTH);
+    }
+
+    #[test]
+    fn output_follows_the_delayed_input() {
+        let mut effect = WowFlutter::default();
+        effect.set_strength(0.5);
+        let mut block: [f32; 4096] = ramp();
+        effect.process(&mut block);
+
+        // A ramp delayed by a varying length stays close behind the input.
+        for (i, y) in block.iter().enumerate().skip(LENGTH) {
+            let lag = i as f32 - y;
+            assert!((0.0..=2.0 * MAX_DEPTH + 1.0).contains(&lag));
+        }
+    }
+
+    #[test]
+    fn loaded_seed_reproduces_the_drift() {
+        let mut state = [0; STATE_SIZE];
+        let mut original = WowFlutter::default();
+        original.load(&[7; STATE_SIZE]);
+        original.save(&mut state);
+
+        let mut restored = WowFlutter::default();
+        restored.load(&state);
+        original.set_strength(1.0);
+        restored.set_strength(1.0);
+        let mut a: [f32; 2048] = ramp();
+        let mut b = a;
+        original.process(&mut a);
+        restored.process(&mut b);
+        assert_eq!(a, b);
+
+        let mut other = WowFlutter::default();
+        other.set_strength(1.0);
+        for _ in 0..SAMPLE_RATE as usize {
+            let (x, y) = (original.delay(), other.delay());
+            if x != y {
+                return;
+            }
+        }
+        panic!("Different seeds led to the same drift");
+    }
+}
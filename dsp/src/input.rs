@@ -0,0 +1,190 @@
+//! Routing of the stereo input to tracks and its monitoring.
+//!
+//! Each track is fed by the left or right input, by their sum, or by its
+//! side of the stereo pair. Tracks are paired as 1 with 2 and 3 with 4,
+//! the first of the pair taking the left input and the second the right
+//! one, so a stereo source can be recorded on two tracks at once.
+//!
+//! The input can be monitored on the output, letting the module serve as a
+//! live performance tool. It is monitored always, only while any track is
+//! armed for recording, or never. Monitoring passes the input as it is,
+//! attenuated by the monitor level, to the `Mixer`, which adds it to the
+//! tracks before saturating the output.
+
+use crate::mixer::CHANNELS;
+use crate::paging_buffer::TRACKS;
+
+/// Source of the signal recorded on a track.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Route {
+    Left,
+    Right,
+    /// Both inputs mixed into mono, each attenuated by a half.
+    Sum,
+    /// Left input for the first track of the pair, right for the second.
+    Stereo,
+}
+
+/// When the input is passed to the output.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Monitoring {
+    Always,
+    Armed,
+    Never,
+}
+
+impl Route {
+    pub(crate) fn to_u8(self) -> u8 {
+        match self {
+            Route::Left => 0,
+            Route::Right => 1,
+            Route::Sum => 2,
+            Route::Stereo => 3,
+        }
+    }
+
+    pub(crate) fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Route::Left),
+            1 => Some(Route::Right),
+            2 => Some(Route::Sum),
+            3 => Some(Route::Stereo),
+            _ => None,
+        }
+    }
+
+    /// Signal of the given track taken from the stereo frame.
+    pub fn select(self, track: usize, frame: [f32; CHANNELS]) -> f32 {
+        let [left, right] = frame;
+        match self {
+            Route::Left => left,
+            Route::Right => right,
+            Route::Sum => (left + right) * 0.5,
+            Route::Stereo if track.is_multiple_of(2) => left,
+            Route::Stereo => right,
+        }
+    }
+}
+
+impl Monitoring {
+    pub(crate) fn to_u8(self) -> u8 {
+        match self {
+            Monitoring::Always => 0,
+            Monitoring::Armed => 1,
+            Monitoring::Never => 2,
+        }
+    }
+
+    pub(crate) fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Monitoring::Always),
+            1 => Some(Monitoring::Armed),
+            2 => Some(Monitoring::Never),
+            _ => None,
+        }
+    }
+}
+
+/// Splits the input among tracks and monitors it.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct InputStage {
+    pub routes: [Route; TRACKS],
+    pub monitoring: Monitoring,
+    /// Gain of the monitored input.
+    pub monitor_level: f32,
+}
+
+impl Default for InputStage {
+    fn default() -> Self {
+        Self {
+            routes: [Route::Sum; TRACKS],
+            monitoring: Monitoring::Armed,
+            monitor_level: 1.0,
+        }
+    }
+}
+
+impl InputStage {
+    /// Feed each track with its routed input.
+    pub fn route(&self, input: &[[f32; CHANNELS]], tracks: &mut [[f32; TRACKS]]) {
+        for (frame, tracks) in input.iter().zip(tracks.iter_mut()) {
+            for (track, (sample, route)) in tracks.iter_mut().zip(self.routes).enumerate() {
+                *sample = route.select(track, *frame);
+            }
+        }
+    }
+
+    /// Write the monitored input into `monitor`, given which tracks are
+    /// armed for recording. It is silent while monitoring is off.
+    pub fn monitor(
+        &self,
+        input: &[[f32; CHANNELS]],
+        armed: [bool; TRACKS],
+        monitor: &mut [[f32; CHANNELS]],
+    ) {
+        let enabled = match self.monitoring {
+            Monitoring::Always => true,
+            Monitoring::Armed => armed.iter().any(|armed| *armed),
+            Monitoring::Never => false,
+        };
+        let level = if enabled { self.monitor_level } else { 0.0 };
+        for (input, monitor) in input.iter().zip(monitor.iter_mut()) {
+            *monitor = input.map(|x| x * level);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: [f32; CHANNELS] = [0.4, -0.2];
+
+    #[test]
+    fn tracks_are_fed_by_their_routes() {
+        let stage = InputStage {
+            routes: [Route::Left, Route::Right, Route::Sum, Route::Left],
+            ..InputStage::default()
+        };
+        let mut tracks = [[0.0; TRACKS]; 2];
+        stage.route(&[FRAME; 2], &mut tracks);
+        for frame in tracks {
+            assert_eq!(frame[0], 0.4);
+            assert_eq!(frame[1], -0.2);
+            assert!((frame[2] - 0.1).abs() < 0.0001);
+            assert_eq!(frame[3], 0.4);
+        }
+    }
+
+    #[test]
+    fn stereo_route_splits_the_pair() {
+        let stage = InputStage {
+            routes: [Route::Stereo; TRACKS],
+            ..InputStage::default()
+        };
+        let mut tracks = [[0.0; TRACKS]; 1];
+        stage.route(&[FRAME], &mut tracks);
+        assert_eq!(tracks[0], [0.4, -0.2, 0.4, -0.2]);
+    }
+
+    #[test]
+    fn monitoring_follows_its_mode() {
+        let monitored = |monitoring, armed| {
+            let stage = InputStage {
+                monitoring,
+                monitor_level: 0.5,
+                ..InputStage::default()
+            };
+            let mut monitor = [[0.1, 0.1]];
+            stage.monitor(&[FRAME], armed, &mut monitor);
+            monitor[0]
+        };
+        let idle = [false; TRACKS];
+        let armed = [false, false, true, false];
+
+        assert_eq!(monitored(Monitoring::Always, idle), [0.2, -0.1]);
+        assert_eq!(monitored(Monitoring::Armed, idle), [0.0, 0.0]);
+        assert_eq!(monitored(Monitoring::Armed, armed), [0.2, -0.1]);
+        assert_eq!(monitored(Monitoring::Never, armed), [0.0, 0.0]);
+    }
+}
@@ -1 +1,7 @@
-mod paging_buffer;
+#![cfg_attr(not(test), no_std)]
+
+pub mod effect;
+pub mod input;
+pub mod mixer;
+pub mod paging_buffer;
+pub mod save;
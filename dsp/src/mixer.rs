@@ -0,0 +1,245 @@
+//! Mixing of tracks into the stereo output.
+//!
+//! Each track is attenuated by its volume and placed in the stereo field
+//! following the equal-power pan law, so a track keeps its loudness while
+//! being panned. Gains are ramped linearly over the processed block
+//! whenever they change, avoiding zipper noise while turning the knobs.
+//!
+//! Tracks are summed with headroom for all of them playing at full volume.
+//! The monitored input is added to the sum, see `input`. Peaks exceeding
+//! the range of the output are softly saturated instead of being clipped.
+
+use core::f32::consts::FRAC_PI_2;
+
+use crate::paging_buffer::{Settings, TRACKS};
+
+/// Number of output channels, left and right.
+pub const CHANNELS: usize = 2;
+
+/// Attenuation of the sum of tracks. All tracks playing centered at full
+/// scale stay within the range of the saturation.
+const HEADROOM: f32 = 0.5;
+
+/// Level at which the soft saturation reaches full scale.
+const SATURATION: f32 = 1.5;
+
+/// Gain of a track given the position of its volume knob.
+///
+/// The knob follows a quadratic curve, approximating the logarithmic
+/// perception of loudness.
+pub fn volume_gain(volume: f32) -> f32 {
+    let volume = volume.clamp(0.0, 1.0);
+    volume * volume
+}
+
+/// Gains of the left and right channel given the position of the pan knob,
+/// 0.0 being fully left and 1.0 fully right.
+///
+/// The sum of squared gains is constant, so is the power of the track.
+pub fn pan_gains(pan: f32) -> [f32; CHANNELS] {
+    let angle = pan.clamp(0.0, 1.0) * FRAC_PI_2;
+    [libm::cosf(angle), libm::sinf(angle)]
+}
+
+/// Soft saturation, linear around zero and reaching full scale smoothly.
+fn saturate(x: f32) -> f32 {
+    let x = x.clamp(-SATURATION, SATURATION);
+    x - 4.0 / 27.0 * x * x * x
+}
+
+/// Sums tracks into the stereo output.
+#[derive(Debug)]
+pub struct Mixer {
+    gains: [[f32; CHANNELS]; TRACKS],
+    targets: [[f32; CHANNELS]; TRACKS],
+}
+
+impl Default for Mixer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mixer {
+    /// All tracks start muted, fading in to the first set volume.
+    pub fn new() -> Self {
+        Self {
+            gains: [[0.0; CHANNELS]; TRACKS],
+            targets: [[0.0; CHANNELS]; TRACKS],
+        }
+    }
+
+    /// Set volume and pan of the track, reached by the end of the next
+    /// processed block.
+    pub fn set(&mut self, track: usize, volume: f32, pan: f32) {
+        let volume = volume_gain(volume);
+        self.targets[track] = pan_gains(pan).map(|gain| gain * volume);
+    }
+
+    pub fn set_settings(&mut self, settings: &Settings) {
+        for (track, settings) in settings.tracks.iter().enumerate() {
+            self.set(track, settings.volume, settings.pan);
+        }
+    }
+
+    /// Jump to the set volume and pan right away, skipping the ramp.
+    pub fn settle(&mut self) {
+        self.gains = self.targets;
+    }
+
+    /// Mix the block of tracks together with the monitored input into the
+    /// stereo output.
+    pub fn process(
+        &mut self,
+        tracks: &[[f32; TRACKS]],
+        monitor: &[[f32; CHANNELS]],
+        output: &mut [[f32; CHANNELS]],
+    ) {
+        let length = tracks.len().min(monitor.len()).min(output.len());
+        let output = &mut output[..length];
+        self.sum(&tracks[..length], output);
+        for (out, monitor) in output.iter_mut().zip(monitor) {
+            for (out, monitor) in out.iter_mut().zip(monitor) {
+                *out = saturate(*out * HEADROOM + monitor);
+            }
+        }
+    }
+
+    /// Sum the block of tracks into stereo, neither attenuated for headroom
+    /// nor saturated, as needed for bouncing tracks into one.
+    pub fn sum(&mut self, tracks: &[[f32; TRACKS]], output: &mut [[f32; CHANNELS]]) {
+        let length = tracks.len().min(output.len());
+        let mut steps = [[0.0; CHANNELS]; TRACKS];
+        for (steps, (target, gain)) in steps.iter_mut().zip(self.targets.iter().zip(&self.gains)) {
+            for (step, (target, gain)) in steps.iter_mut().zip(target.iter().zip(gain)) {
+                *step = (target - gain) / length.max(1) as f32;
+            }
+        }
+
+        for (frame, out) in tracks.iter().zip(output.iter_mut()) {
+            *out = [0.0; CHANNELS];
+            for (track, sample) in frame.iter().enumerate() {
+                for (channel, out) in out.iter_mut().enumerate() {
+                    self.gains[track][channel] += steps[track][channel];
+                    *out += sample * self.gains[track][channel];
+                }
+            }
+        }
+
+        // Avoid accumulating rounding errors of the ramps.
+        self.gains = self.targets;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settled(volume: f32, pan: f32) -> Mixer {
+        let mut mixer = Mixer::new();
+        for track in 0..TRACKS {
+            mixer.set(track, volume, pan);
+        }
+        mixer.process(&[[0.0; TRACKS]], &[[0.0; CHANNELS]], &mut [[0.0; CHANNELS]]);
+        mixer
+    }
+
+    #[test]
+    fn volume_gain_follows_quadratic_curve() {
+        assert_eq!(volume_gain(0.0), 0.0);
+        assert_eq!(volume_gain(0.5), 0.25);
+        assert_eq!(volume_gain(1.0), 1.0);
+        assert_eq!(volume_gain(1.2), 1.0);
+    }
+
+    #[test]
+    fn pan_keeps_constant_power() {
+        for i in 0..=10 {
+            let [left, right] = pan_gains(i as f32 / 10.0);
+            assert!((left * left + right * right - 1.0).abs() < 0.0001);
+        }
+    }
+
+    #[test]
+    fn pan_ends_and_center() {
+        let [left, right] = pan_gains(0.0);
+        assert!((left - 1.0).abs() < 0.0001 && right.abs() < 0.0001);
+        let [left, right] = pan_gains(1.0);
+        assert!(left.abs() < 0.0001 && (right - 1.0).abs() < 0.0001);
+        let [left, right] = pan_gains(0.5);
+        assert!((left - right).abs() < 0.0001);
+        assert!((left - core::f32::consts::FRAC_1_SQRT_2).abs() < 0.0001);
+    }
+
+    #[test]
+    fn gain_changes_are_ramped_over_the_block() {
+        let mut mixer = settled(0.0, 0.0);
+        mixer.set(0, 1.0, 0.0);
+        let tracks = [[1.0, 0.0, 0.0, 0.0]; 4];
+        let mut output = [[0.0; CHANNELS]; 4];
+        mixer.process(&tracks, &[[0.0; CHANNELS]; 4], &mut output);
+
+        for pair in output.windows(2) {
+            assert!(pair[1][0] > pair[0][0]);
+        }
+        assert!(output[0][0] < output[3][0] / 2.0);
+        assert!((output[3][0] - saturate(HEADROOM)).abs() < 0.0001);
+    }
+
+    #[test]
+    fn settled_mixer_applies_constant_gain() {
+        let mut mixer = settled(1.0, 1.0);
+        let tracks = [[0.5, 0.0, 0.0, 0.0]; 4];
+        let mut output = [[0.0; CHANNELS]; 4];
+        mixer.process(&tracks, &[[0.0; CHANNELS]; 4], &mut output);
+
+        for frame in output {
+            assert!(frame[0].abs() < 0.0001);
+            assert_eq!(frame[1], output[0][1]);
+        }
+    }
+
+    #[test]
+    fn full_mix_saturates_softly_within_range() {
+        let mut mixer = settled(1.0, 0.0);
+        let mut output = [[0.0; CHANNELS]; 1];
+
+        mixer.process(&[[1.0; TRACKS]], &[[0.0; CHANNELS]], &mut output);
+        assert!((output[0][0] - 1.0).abs() < 0.0001);
+
+        mixer.process(&[[-1.0; TRACKS]], &[[0.0; CHANNELS]], &mut output);
+        assert!((output[0][0] + 1.0).abs() < 0.0001);
+
+        // Quiet signals pass nearly untouched.
+        mixer.process(&[[0.1, 0.0, 0.0, 0.0]], &[[0.0; CHANNELS]], &mut output);
+        assert!((output[0][0] - 0.1 * HEADROOM).abs() < 0.001);
+    }
+
+    #[test]
+    fn monitor_is_saturated_together_with_tracks() {
+        let mut mixer = settled(1.0, 0.0);
+        let mut output = [[0.0; CHANNELS]; 1];
+
+        mixer.process(&[[0.0; TRACKS]], &[[0.3, -0.3]], &mut output);
+        assert!((output[0][0] - saturate(0.3)).abs() < 0.0001);
+        assert!((output[0][1] + saturate(0.3)).abs() < 0.0001);
+
+        // Loud monitor on top of loud tracks is saturated softly, not
+        // clipped.
+        mixer.process(&[[0.5; TRACKS]], &[[0.4, 0.0]], &mut output);
+        assert_eq!(output[0][0], saturate(2.0 * HEADROOM + 0.4));
+        assert!(output[0][0] < 1.0);
+    }
+
+    #[test]
+    fn sum_is_neither_attenuated_nor_saturated() {
+        let mut mixer = Mixer::new();
+        for track in 0..TRACKS {
+            mixer.set(track, 1.0, 0.0);
+        }
+        mixer.settle();
+        let mut output = [[0.0; CHANNELS]; 1];
+        mixer.sum(&[[0.5; TRACKS]], &mut output);
+        assert_eq!(output[0], [2.0, 0.0]);
+    }
+}
@@ -0,0 +1,521 @@
+//! Storage laid out directly on blocks of the medium.
+//!
+//! The medium is split into fixed regions, one per file, so seeking to any
+//! page takes constant time. This is preferred over a file system, where
+//! walking cluster chains of long recordings would break RT guarantees.
+//!
+//! | Region         | Blocks                                      |
+//! | -------------- | ------------------------------------------- |
+//! | Table of sizes | 4                                           |
+//! | Reset journal  | 1                                           |
+//! | Configuration  | 16                                          |
+//! | Metadata       | 4 per cassette of the first bank            |
+//! | Settings       | 4 per cassette of the first bank            |
+//! | Sections       | rest of the medium split between positions  |
+//!
+//! Each position of the selector owns a section, holding the audio of its
+//! cassette in every bank, followed by metadata and settings of its
+//! cassettes in the other banks:
+//!
+//! | Region   | Blocks                                  |
+//! | -------- | --------------------------------------- |
+//! | Audio    | rest of the section split between banks |
+//! | Metadata | 4 per cassette of the other banks       |
+//! | Settings | 4 per cassette of the other banks       |
+//!
+//! Before banks were introduced, sections held the audio of a single
+//! cassette. Cassettes of cards formatted back then become the first bank,
+//! their files staying where they were. Cards with audio reaching beyond the
+//! now shorter region are refused rather than cut off.
+//!
+//! The table keeps the size of each file. Updating a size touches only a
+//! single block, so removal of a file is atomic. A medium without the table
+//! is left untouched until it is explicitly formatted.
+
+use super::cassette::{CassetteId, BANKS, CASSETTES, CASSETTES_PER_BANK};
+use super::storage::{Error, File, Storage};
+
+pub const BLOCK_SIZE: usize = 512;
+
+const MAGIC: u32 = 0x5442_5452;
+const TABLE_BLOCKS: u32 = 4;
+const TABLE_ENTRIES: usize = TABLE_BLOCKS as usize * ENTRIES_PER_BLOCK;
+const ENTRIES_PER_BLOCK: usize = BLOCK_SIZE / 4;
+const JOURNAL_BLOCKS: u32 = 1;
+const CONFIG_BLOCKS: u32 = 16;
+// Metadata and settings of a cassette share a single slot, keeping the
+// layout of cards formatted before settings were introduced.
+const METADATA_BLOCKS: u32 = 4;
+const SETTINGS_BLOCKS: u32 = 4;
+const SLOT_BLOCKS: u32 = METADATA_BLOCKS + SETTINGS_BLOCKS;
+const JOURNAL_START: u32 = TABLE_BLOCKS;
+const CONFIG_START: u32 = JOURNAL_START + JOURNAL_BLOCKS;
+const SLOTS_START: u32 = CONFIG_START + CONFIG_BLOCKS;
+const SECTIONS_START: u32 = SLOTS_START + SLOT_BLOCKS * CASSETTES_PER_BANK as u32;
+/// Entries of cassettes of the first bank, kept from before banks were
+/// introduced, followed by entries of the other banks.
+const FIRST_BANK_ENTRIES: usize = 3 * CASSETTES_PER_BANK;
+
+/// Medium accessible by blocks, e.g. an SD card.
+pub trait BlockDevice {
+    fn read_block(&mut self, index: u32, block: &mut [u8; BLOCK_SIZE]) -> Result<(), Error>;
+    fn write_block(&mut self, index: u32, block: &[u8; BLOCK_SIZE]) -> Result<(), Error>;
+    fn blocks(&mut self) -> u32;
+}
+
+/// Implementation of `Storage` on top of a `BlockDevice`.
+pub struct BlockStorage<D> {
+    device: D,
+    sizes: [u32; TABLE_ENTRIES],
+    section_blocks: u32,
+    audio_blocks: u32,
+    block: [u8; BLOCK_SIZE],
+}
+
+/// Continuous range of blocks reserved for a file.
+#[derive(Clone, Copy)]
+struct Region {
+    start: u32,
+    blocks: u32,
+}
+
+impl Region {
+    fn len(&self) -> usize {
+        self.blocks as usize * BLOCK_SIZE
+    }
+}
+
+impl<D: BlockDevice> BlockStorage<D> {
+    /// Load the table of sizes from the device.
+    ///
+    /// A device that was not formatted yet is refused with
+    /// `Error::Unformatted`, one formatted before banks were introduced with
+    /// recordings too long to fit into the first bank with
+    /// `Error::Incompatible`. The device is handed back with the error, so it
+    /// can be formatted once the user confirms it.
+    pub fn new(device: D) -> Result<Self, (Error, D)> {
+        let mut storage = Self::with_layout(device);
+        for table_block in 0..TABLE_BLOCKS {
+            if let Err(error) = storage.device.read_block(table_block, &mut storage.block) {
+                return Err((error, storage.device));
+            }
+            let first_entry = table_block as usize * ENTRIES_PER_BLOCK;
+            for (i, bytes) in storage.block.chunks_exact(4).enumerate() {
+                storage.sizes[first_entry + i] = u32::from_le_bytes(bytes.try_into().unwrap());
+            }
+        }
+        if storage.sizes[0] != MAGIC {
+            return Err((Error::Unformatted, storage.device));
+        }
+        let fits = (0..CASSETTES_PER_BANK).all(|position| {
+            let file = File::Audio(CassetteId::from_bank(0, position));
+            storage.sizes[storage.entry(file)] as usize <= storage.region(file).len()
+        });
+        if !fits {
+            return Err((Error::Incompatible, storage.device));
+        }
+        Ok(storage)
+    }
+
+    /// Write an empty table of sizes to the device, dropping all the files
+    /// stored on it.
+    pub fn format(device: D) -> Result<Self, Error> {
+        let mut storage = Self::with_layout(device);
+        storage.sizes[0] = MAGIC;
+        for table_block in 0..TABLE_BLOCKS {
+            storage.store_table_block(table_block)?;
+        }
+        Ok(storage)
+    }
+
+    fn with_layout(mut device: D) -> Self {
+        let section_blocks =
+            device.blocks().saturating_sub(SECTIONS_START) / CASSETTES_PER_BANK as u32;
+        let audio_blocks =
+            section_blocks.saturating_sub(SLOT_BLOCKS * (BANKS as u32 - 1)) / BANKS as u32;
+        Self {
+            device,
+            sizes: [0; TABLE_ENTRIES],
+            section_blocks,
+            audio_blocks,
+            block: [0; BLOCK_SIZE],
+        }
+    }
+
+    fn entry(&self, file: File) -> usize {
+        // The first entry is occupied by the magic number.
+        match file {
+            File::ResetJournal => 1,
+            File::Config => 2,
+            File::Metadata(id) if id.bank() == 0 => 3 + 2 * id.index(),
+            File::Audio(id) if id.bank() == 0 => 4 + 2 * id.index(),
+            File::Settings(id) if id.bank() == 0 => 3 + 2 * CASSETTES_PER_BANK + id.index(),
+            File::Metadata(id) => 3 + FIRST_BANK_ENTRIES + 3 * (id.index() - CASSETTES_PER_BANK),
+            File::Audio(id) => 4 + FIRST_BANK_ENTRIES + 3 * (id.index() - CASSETTES_PER_BANK),
+            File::Settings(id) => 5 + FIRST_BANK_ENTRIES + 3 * (id.index() - CASSETTES_PER_BANK),
+        }
+    }
+
+    /// First block of the slot keeping metadata and settings of the
+    /// cassette.
+    fn slot_start(&self, id: CassetteId) -> u32 {
+        if id.bank() == 0 {
+            return SLOTS_START + SLOT_BLOCKS * id.position() as u32;
+        }
+        self.section_start(id)
+            + self.audio_blocks * BANKS as u32
+            + SLOT_BLOCKS * (id.bank() - 1) as u32
+    }
+
+    fn section_start(&self, id: CassetteId) -> u32 {
+        SECTIONS_START + self.section_blocks * id.position() as u32
+    }
+
+    fn region(&self, file: File) -> Region {
+        match file {
+            File::ResetJournal => Region {
+                start: JOURNAL_START,
+                blocks: JOURNAL_BLOCKS,
+            },
+            File::Config => Region {
+                start: CONFIG_START,
+                blocks: CONFIG_BLOCKS,
+            },
+            File::Metadata(id) => Region {
+                start: self.slot_start(id),
+                blocks: METADATA_BLOCKS,
+            },
+            File::Settings(id) => Region {
+                start: self.slot_start(id) + METADATA_BLOCKS,
+                blocks: SETTINGS_BLOCKS,
+            },
+            File::Audio(id) => Region {
+                start: self.section_start(id) + self.audio_blocks * id.bank() as u32,
+                blocks: self.audio_blocks,
+            },
+        }
+    }
+
+    fn set_size(&mut self, file: File, size: usize) -> Result<(), Error> {
+        let entry = self.entry(file);
+        self.sizes[entry] = size as u32;
+        self.store_table_block((entry / ENTRIES_PER_BLOCK) as u32)
+    }
+
+    fn store_table_block(&mut self, table_block: u32) -> Result<(), Error> {
+        let first_entry = table_block as usize * ENTRIES_PER_BLOCK;
+        for (i, bytes) in self.block.chunks_exact_mut(4).enumerate() {
+            bytes.copy_from_slice(&self.sizes[first_entry + i].to_le_bytes());
+        }
+        self.device.write_block(table_block, &self.block)
+    }
+}
+
+impl<D: BlockDevice> Storage for BlockStorage<D> {
+    fn read(&mut self, file: File, offset: usize, buffer: &mut [u8]) -> Result<(), Error> {
+        if offset + buffer.len() > self.size(file) {
+            return Err(Error::OutOfBounds);
+        }
+        let region = self.region(file);
+
+        let mut done = 0;
+        while done < buffer.len() {
+            let position = offset + done;
+            let block_offset = position % BLOCK_SIZE;
+            let chunk = (BLOCK_SIZE - block_offset).min(buffer.len() - done);
+            self.device.read_block(
+                region.start + (position / BLOCK_SIZE) as u32,
+                &mut self.block,
+            )?;
+            buffer[done..done + chunk]
+                .copy_from_slice(&self.block[block_offset..block_offset + chunk]);
+            done += chunk;
+        }
+
+        Ok(())
+    }
+
+    fn write(&mut self, file: File, offset: usize, data: &[u8]) -> Result<(), Error> {
+        let region = self.region(file);
+        if offset + data.len() > region.len() {
+            return Err(Error::OutOfBounds);
+        }
+        let size = self.size(file);
+
+        let mut done = 0;
+        while done < data.len() {
+            let position = offset + done;
+            let block_index = region.start + (position / BLOCK_SIZE) as u32;
+            let block_offset = position % BLOCK_SIZE;
+            let chunk = (BLOCK_SIZE - block_offset).min(data.len() - done);
+            let partial = chunk < BLOCK_SIZE;
+            let block_start = position - block_offset;
+            if partial && block_start < size {
+                self.device.read_block(block_index, &mut self.block)?;
+            } else if partial {
+                self.block = [0; BLOCK_SIZE];
+            }
+            self.block[block_offset..block_offset + chunk]
+                .copy_from_slice(&data[done..done + chunk]);
+            self.device.write_block(block_index, &self.block)?;
+            done += chunk;
+        }
+
+        if offset + data.len() > size {
+            self.set_size(file, offset + data.len())?;
+        }
+
+        Ok(())
+    }
+
+    fn remove(&mut self, file: File) -> Result<(), Error> {
+        if self.size(file) == 0 {
+            return Ok(());
+        }
+        self.set_size(file, 0)
+    }
+
+    fn size(&mut self, file: File) -> usize {
+        self.sizes[self.entry(file)] as usize
+    }
+
+    fn capacity(&mut self) -> usize {
+        self.audio_blocks as usize * CASSETTES * BLOCK_SIZE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::cassette::CassetteId;
+    use super::*;
+
+    struct MemoryDevice {
+        blocks: Vec<[u8; BLOCK_SIZE]>,
+    }
+
+    impl MemoryDevice {
+        fn new(blocks: usize, fill: u8) -> Self {
+            Self {
+                blocks: vec![[fill; BLOCK_SIZE]; blocks],
+            }
+        }
+    }
+
+    impl BlockDevice for MemoryDevice {
+        fn read_block(&mut self, index: u32, block: &mut [u8; BLOCK_SIZE]) -> Result<(), Error> {
+            *block = self.blocks[index as usize];
+            Ok(())
+        }
+
+        fn write_block(&mut self, index: u32, block: &[u8; BLOCK_SIZE]) -> Result<(), Error> {
+            self.blocks[index as usize] = *block;
+            Ok(())
+        }
+
+        fn blocks(&mut self) -> u32 {
+            self.blocks.len() as u32
+        }
+    }
+
+    const DEVICE_BLOCKS: usize = 10_000;
+
+    fn formatted_storage() -> BlockStorage<MemoryDevice> {
+        BlockStorage::format(MemoryDevice::new(DEVICE_BLOCKS, 0)).unwrap()
+    }
+
+    #[test]
+    fn unformatted_device_is_refused_untouched() {
+        let Err((error, device)) = BlockStorage::new(MemoryDevice::new(DEVICE_BLOCKS, 0xAB)) else {
+            panic!("Unformatted device was accepted");
+        };
+
+        assert_eq!(error, Error::Unformatted);
+        assert!(device.blocks.iter().flatten().all(|x| *x == 0xAB));
+    }
+
+    #[test]
+    fn formatted_device_appears_empty() {
+        let storage = BlockStorage::format(MemoryDevice::new(DEVICE_BLOCKS, 0xAB)).unwrap();
+
+        let mut storage = BlockStorage::new(storage.device).ok().unwrap();
+        assert_eq!(storage.size(File::Config), 0);
+        assert_eq!(storage.size(File::Audio(CassetteId::new(3))), 0);
+    }
+
+    /// Layout with a single bank of cassettes, audio taking the rest of the
+    /// medium. The first cassette holds the given amount of audio.
+    fn card_formatted_before_banks(first_audio_size: usize) -> MemoryDevice {
+        let mut device = MemoryDevice::new(DEVICE_BLOCKS, 0);
+        let audio_start = SLOTS_START as usize + 8 * SLOT_BLOCKS as usize;
+        let audio_blocks = (DEVICE_BLOCKS - audio_start) / 8;
+        let mut table = [0; TABLE_ENTRIES];
+        table[0] = MAGIC;
+        table[2] = 7;
+        device.blocks[CONFIG_START as usize][..7].copy_from_slice(&[1; 7]);
+        for i in 0..8 {
+            let slot = SLOTS_START as usize + i * SLOT_BLOCKS as usize;
+            table[3 + 2 * i] = 10;
+            device.blocks[slot][..10].copy_from_slice(&[i as u8 + 10; 10]);
+            table[19 + i] = 20;
+            device.blocks[slot + 4][..20].copy_from_slice(&[i as u8 + 20; 20]);
+            table[4 + 2 * i] = BLOCK_SIZE as u32;
+            device.blocks[audio_start + i * audio_blocks] = [i as u8 + 30; BLOCK_SIZE];
+        }
+        table[4] = first_audio_size as u32;
+        for (block, entries) in table.chunks_exact(ENTRIES_PER_BLOCK).enumerate() {
+            for (bytes, entry) in device.blocks[block].chunks_exact_mut(4).zip(entries) {
+                bytes.copy_from_slice(&entry.to_le_bytes());
+            }
+        }
+        device
+    }
+
+    #[test]
+    fn cassettes_of_card_formatted_before_banks_become_first_bank() {
+        let mut storage = BlockStorage::new(card_formatted_before_banks(BLOCK_SIZE))
+            .ok()
+            .unwrap();
+
+        let mut config = [0; 7];
+        storage.read(File::Config, 0, &mut config).unwrap();
+        assert_eq!(config, [1; 7]);
+        for position in 0..CASSETTES_PER_BANK {
+            let id = CassetteId::from_bank(0, position);
+            let mut metadata = [0; 10];
+            storage.read(File::Metadata(id), 0, &mut metadata).unwrap();
+            assert_eq!(metadata, [position as u8 + 10; 10]);
+            let mut settings = [0; 20];
+            storage.read(File::Settings(id), 0, &mut settings).unwrap();
+            assert_eq!(settings, [position as u8 + 20; 20]);
+            let mut audio = [0; BLOCK_SIZE];
+            storage.read(File::Audio(id), 0, &mut audio).unwrap();
+            assert_eq!(audio, [position as u8 + 30; BLOCK_SIZE]);
+        }
+        for index in CASSETTES_PER_BANK..CASSETTES {
+            let id = CassetteId::new(index);
+            assert_eq!(storage.size(File::Metadata(id)), 0);
+            assert_eq!(storage.size(File::Audio(id)), 0);
+            assert_eq!(storage.size(File::Settings(id)), 0);
+        }
+    }
+
+    #[test]
+    fn card_formatted_before_banks_with_longer_audio_is_refused_untouched() {
+        let audio_start = SLOTS_START as usize + 8 * SLOT_BLOCKS as usize;
+        let audio_blocks = (DEVICE_BLOCKS - audio_start) / 8;
+        let device = card_formatted_before_banks(audio_blocks * BLOCK_SIZE);
+        let blocks = device.blocks.clone();
+
+        let Err((error, device)) = BlockStorage::new(device) else {
+            panic!("Card with longer audio was accepted");
+        };
+
+        assert_eq!(error, Error::Incompatible);
+        assert!(device.blocks == blocks);
+    }
+
+    #[test]
+    fn every_file_has_its_own_size() {
+        let mut storage = formatted_storage();
+        let mut files = vec![File::Config, File::ResetJournal];
+        for index in 0..CASSETTES {
+            files.push(File::Metadata(CassetteId::new(index)));
+            files.push(File::Audio(CassetteId::new(index)));
+            files.push(File::Settings(CassetteId::new(index)));
+        }
+
+        for (i, file) in files.iter().enumerate() {
+            storage.write(*file, 0, &vec![0; i + 1]).unwrap();
+        }
+        for (i, file) in files.iter().enumerate() {
+            assert_eq!(storage.size(*file), i + 1, "{:?}", file);
+        }
+    }
+
+    #[test]
+    fn read_back_written_data_across_blocks() {
+        let mut storage = formatted_storage();
+        let file = File::Audio(CassetteId::new(2));
+        let data: Vec<u8> = (0..1500).map(|i| i as u8).collect();
+
+        storage.write(file, 100, &data).unwrap();
+        assert_eq!(storage.size(file), 1600);
+
+        let mut buffer = vec![0; 1500];
+        storage.read(file, 100, &mut buffer).unwrap();
+        assert_eq!(buffer, data);
+    }
+
+    #[test]
+    fn overwrite_in_the_middle_keeps_surrounding_data() {
+        let mut storage = formatted_storage();
+        let file = File::Config;
+
+        storage.write(file, 0, &[1; 1024]).unwrap();
+        storage.write(file, 500, &[2; 20]).unwrap();
+        assert_eq!(storage.size(file), 1024);
+
+        let mut buffer = [0; 1024];
+        storage.read(file, 0, &mut buffer).unwrap();
+        assert!(buffer[..500].iter().all(|x| *x == 1));
+        assert!(buffer[500..520].iter().all(|x| *x == 2));
+        assert!(buffer[520..].iter().all(|x| *x == 1));
+    }
+
+    #[test]
+    fn files_do_not_overlap() {
+        let mut storage = formatted_storage();
+        let mut files = vec![File::Config, File::ResetJournal];
+        for index in 0..CASSETTES {
+            files.push(File::Metadata(CassetteId::new(index)));
+            files.push(File::Audio(CassetteId::new(index)));
+            files.push(File::Settings(CassetteId::new(index)));
+        }
+
+        for (i, file) in files.iter().enumerate() {
+            storage.write(*file, 0, &[i as u8; BLOCK_SIZE]).unwrap();
+        }
+        for (i, file) in files.iter().enumerate() {
+            let mut buffer = [0; BLOCK_SIZE];
+            storage.read(*file, 0, &mut buffer).unwrap();
+            assert!(buffer.iter().all(|x| *x == i as u8), "{:?}", file);
+        }
+    }
+
+    #[test]
+    fn sizes_persist_across_initializations() {
+        let mut storage = formatted_storage();
+        let file = File::Metadata(CassetteId::new(1));
+        storage.write(file, 0, &[7; 12]).unwrap();
+
+        let mut storage = BlockStorage::new(storage.device).ok().unwrap();
+        assert_eq!(storage.size(file), 12);
+        let mut buffer = [0; 12];
+        storage.read(file, 0, &mut buffer).unwrap();
+        assert_eq!(buffer, [7; 12]);
+    }
+
+    #[test]
+    fn removed_file_is_empty() {
+        let mut storage = formatted_storage();
+        storage.write(File::Config, 0, &[1; 10]).unwrap();
+
+        storage.remove(File::Config).unwrap();
+
+        assert_eq!(storage.size(File::Config), 0);
+        assert_eq!(
+            storage.read(File::Config, 0, &mut [0; 10]),
+            Err(Error::OutOfBounds)
+        );
+    }
+
+    #[test]
+    fn refuse_to_write_beyond_the_region() {
+        let mut storage = formatted_storage();
+
+        assert_eq!(
+            storage.write(File::Metadata(CassetteId::new(0)), 4 * BLOCK_SIZE, &[0]),
+            Err(Error::OutOfBounds)
+        );
+    }
+}
@@ -1,7 +1,7 @@
 //! Backend of the paging buffer.
 
-use super::cassette::Cassette;
-use super::page::{PageId, PageRequest};
+use super::cassette::{Cassette, TRACKS};
+use super::page::{PageId, PageRequest, PAGE_LENGTH};
 use super::pool::Handle;
 
 /// Internal component responsible for recording and playback.
@@ -12,7 +12,7 @@ pub(crate) struct Buffer {
     active_page: Option<Handle>,
     pointer: usize,
     cassette: Cassette,
-    pub recording: bool,
+    pub recording: [bool; TRACKS],
 }
 
 impl Buffer {
@@ -21,13 +21,11 @@ impl Buffer {
             active_page: None,
             pointer: 0,
             cassette,
-            recording: false,
+            recording: [false; TRACKS],
         }
     }
 
     pub(crate) fn next_page(&self) -> PageRequest {
-        // TODO: Take this from Page module
-        const PAGE_LENGTH: usize = 512;
         let next_index = if let Some(active_page) = self.active_page.as_ref() {
             active_page.page_ref().index() + 1
         } else {
@@ -49,25 +47,45 @@ impl Buffer {
         self.active_page = Some(handle);
     }
 
-    pub(crate) fn process(&mut self, block: &[f32]) {
-        if self.has_page() {
-            for x in block.iter() {
-                let relative_index = self.pointer % 512; // TODO Use constant
-                self.active_page.as_mut().unwrap().page_mut().data[relative_index] = *x;
-                self.pointer += 1;
-            }
-            if self.pointer > self.cassette.length {
-                self.cassette.length = self.pointer;
-            }
-            if self.recording {
-                self.active_page.as_ref().unwrap().page_mut().mark_dirty();
+    /// Play all the tracks into `output` while recording `input` into the
+    /// armed ones. The output contains what was on the tape before.
+    ///
+    /// Processing stops at the end of the active page, so the tape stays
+    /// aligned to pages. Returns the number of processed samples, the rest
+    /// is to be processed once the page is swapped. Without a page, the
+    /// whole output is silenced.
+    pub(crate) fn process(
+        &mut self,
+        input: &[[f32; TRACKS]],
+        output: &mut [[f32; TRACKS]],
+    ) -> usize {
+        let Some(active_page) = self.active_page.as_ref() else {
+            output.fill([0.0; TRACKS]);
+            return input.len();
+        };
+        let page = active_page.page_mut();
+
+        let start = self.pointer % PAGE_LENGTH;
+        let length = input.len().min(PAGE_LENGTH - start);
+        for (i, (x, y)) in input[..length].iter().zip(output.iter_mut()).enumerate() {
+            for (track, data) in page.data.iter_mut().enumerate() {
+                y[track] = data[start + i];
+                if self.recording[track] {
+                    data[start + i] = x[track];
+                }
             }
         }
+        self.pointer += length;
+        if self.pointer > self.cassette.length {
+            self.cassette.length = self.pointer;
+        }
+        if self.recording.iter().any(|armed| *armed) {
+            page.mark_dirty();
+        }
+        length
     }
 
     pub(crate) fn has_full_page(&self) -> bool {
-        // TODO: Take this from Page module
-        const PAGE_LENGTH: usize = 512;
         self.active_page.is_some() && self.pointer % PAGE_LENGTH == 0
     }
 
@@ -79,3 +97,61 @@ impl Buffer {
         self.pointer = 0;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::pool::Pool;
+    use super::*;
+
+    #[test]
+    fn play_recorded_tracks_while_overdubbing_armed_ones() {
+        static mut POOL: Pool = Pool::new();
+        let pool = unsafe { &mut POOL };
+        let mut buffer = Buffer::from_cassette(Cassette::new(0));
+        buffer.set_page(pool.new_page(PageId::new(Cassette::new(0).id, 0)));
+        let mut output = [[1.0; TRACKS]; 32];
+
+        buffer.recording = [true, false, false, false];
+        buffer.process(&[[0.3; TRACKS]; 32], &mut output);
+        assert_eq!(output, [[0.0; TRACKS]; 32]);
+
+        buffer.reset_position();
+        buffer.recording = [false, true, false, false];
+        buffer.process(&[[0.7; TRACKS]; 32], &mut output);
+        assert_eq!(output, [[0.3, 0.0, 0.0, 0.0]; 32]);
+
+        buffer.reset_position();
+        buffer.recording = [false; TRACKS];
+        buffer.process(&[[0.9; TRACKS]; 32], &mut output);
+        assert_eq!(output, [[0.3, 0.7, 0.0, 0.0]; 32]);
+    }
+
+    #[test]
+    fn page_is_dirty_only_when_a_track_is_armed() {
+        static mut POOL: Pool = Pool::new();
+        let pool = unsafe { &mut POOL };
+        let mut buffer = Buffer::from_cassette(Cassette::new(0));
+        buffer.set_page(pool.new_page(PageId::new(Cassette::new(0).id, 0)));
+        let mut output = [[0.0; TRACKS]; 32];
+
+        buffer.process(&[[0.3; TRACKS]; 32], &mut output);
+        assert!(!buffer.take_page().page_ref().is_dirty());
+    }
+
+    #[test]
+    fn stop_processing_at_the_end_of_the_page() {
+        static mut POOL: Pool = Pool::new();
+        let pool = unsafe { &mut POOL };
+        let mut buffer = Buffer::from_cassette(Cassette::new(0));
+        buffer.set_page(pool.new_page(PageId::new(Cassette::new(0).id, 0)));
+        let mut output = [[0.0; TRACKS]; 32];
+
+        assert_eq!(buffer.process(&[[0.0; TRACKS]; 13], &mut output), 13);
+        assert!(!buffer.has_full_page());
+        for _ in 0..PAGE_LENGTH / 32 - 1 {
+            assert_eq!(buffer.process(&[[0.0; TRACKS]; 32], &mut output), 32);
+        }
+        assert_eq!(buffer.process(&[[0.0; TRACKS]; 32], &mut output), 32 - 13);
+        assert!(buffer.has_full_page());
+    }
+}
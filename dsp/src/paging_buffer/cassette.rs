@@ -1,9 +1,25 @@
 //! Virtual cassette representation.
 
+use super::settings::Settings;
+
+/// Number of cassettes reachable without changing the bank, one for each
+/// position of the selector.
+pub const CASSETTES_PER_BANK: usize = 8;
+
+/// Number of banks of cassettes.
+pub const BANKS: usize = 8;
+
+/// Number of cassettes available to the user.
+pub const CASSETTES: usize = BANKS * CASSETTES_PER_BANK;
+
+/// Number of tracks recorded on each cassette.
+pub const TRACKS: usize = 4;
+
 /// Represents a cassete with its recorded tracks and samples.
 pub(crate) struct Cassette {
     pub id: CassetteId,
     pub length: usize,
+    pub settings: Settings,
 }
 
 impl Cassette {
@@ -11,18 +27,61 @@ impl Cassette {
         Self {
             id: CassetteId::new(index),
             length: 0,
+            settings: Settings::default(),
         }
     }
 }
 
 /// Unique identificator of the given `Cassette`.
 #[derive(PartialEq, Clone, Copy, Debug)]
-pub(crate) struct CassetteId {
+pub struct CassetteId {
     index: usize,
 }
 
 impl CassetteId {
-    pub(crate) fn new(index: usize) -> Self {
+    pub fn new(index: usize) -> Self {
         Self { index }
     }
+
+    pub fn from_bank(bank: usize, position: usize) -> Self {
+        debug_assert!(bank < BANKS && position < CASSETTES_PER_BANK);
+        Self {
+            index: bank * CASSETTES_PER_BANK + position,
+        }
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn bank(&self) -> usize {
+        self.index / CASSETTES_PER_BANK
+    }
+
+    /// Position of the selector pointing to this cassette within its bank.
+    pub fn position(&self) -> usize {
+        self.index % CASSETTES_PER_BANK
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cassettes_of_all_banks_are_unique() {
+        let mut indices = Vec::new();
+        for bank in 0..BANKS {
+            for position in 0..CASSETTES_PER_BANK {
+                let id = CassetteId::from_bank(bank, position);
+                assert_eq!(id.bank(), bank);
+                assert_eq!(id.position(), position);
+                indices.push(id.index());
+            }
+        }
+        indices.sort();
+        indices.dedup();
+        assert_eq!(indices.len(), CASSETTES);
+        assert_eq!(indices.last(), Some(&(CASSETTES - 1)));
+    }
 }
@@ -1,6 +1,9 @@
 //! Runtime configuration.
 
+use super::cassette::TRACKS;
+
 /// Runtime configuration of paging buffer.
 pub(crate) struct Config {
-    pub recording: bool,
+    /// Tracks armed for recording.
+    pub recording: [bool; TRACKS],
 }
@@ -0,0 +1,252 @@
+//! Routines of the paging buffer, as run by the firmware.
+//!
+//! The `Deck` runs in the audio routine, moving the tape through the
+//! `Manager`. The `Library` serves its pages from the storage routine, which
+//! must run often enough to keep up with the tape. Settings of cassettes are
+//! saved by the control routine through the `SettingsQueue`, ahead of the
+//! cassette switch that follows them.
+
+use heapless::spsc::{Consumer, Producer};
+
+use super::cassette::{Cassette, CassetteId, TRACKS};
+use super::config::Config;
+use super::format::SampleFormat;
+use super::manager::Manager;
+use super::pool::Pool;
+use super::queues::Queues;
+use super::settings::Settings;
+use super::storage::Storage;
+use super::store::{self, Diagnostics, JobError, JobStatus, Scan, Store};
+use super::transport::{self, Transport};
+use super::usage::{Budget, Usage};
+
+/// Split the paging buffer into its routines. New cassettes are recorded in
+/// `format`.
+///
+/// The deck starts with no cassette, one has to be selected through
+/// `Transport::switch_cassette`.
+pub fn split<'a, S: Storage>(
+    queues: &'a mut Queues,
+    pool: &'a mut Pool,
+    storage: S,
+    format: SampleFormat,
+) -> (Deck<'a>, Library<'a, S>, SettingsQueue<'a>) {
+    let ends = queues.split();
+    let deck = Deck::new(
+        Manager::new(),
+        ends.transport,
+        ends.config_consumer,
+        ends.switch_request_producer,
+        ends.switch_response_consumer,
+    );
+    let library = Library {
+        store: Store::new(storage, format),
+        pool,
+        queues: ends.store,
+    };
+    let settings_queue = SettingsQueue {
+        producer: ends.settings_save_request_producer,
+    };
+    (deck, library, settings_queue)
+}
+
+/// Tape driven by the `Transport`, running in the audio routine.
+pub struct Deck<'a> {
+    manager: Manager,
+    queues: transport::Queues<'a>,
+    config_consumer: Consumer<'a, Config, 4>,
+    switch_request_producer: Producer<'a, CassetteId, 4>,
+    switch_response_consumer: Consumer<'a, Cassette, 4>,
+}
+
+impl<'a> Deck<'a> {
+    pub(crate) fn new(
+        manager: Manager,
+        queues: transport::Queues<'a>,
+        config_consumer: Consumer<'a, Config, 4>,
+        switch_request_producer: Producer<'a, CassetteId, 4>,
+        switch_response_consumer: Consumer<'a, Cassette, 4>,
+    ) -> Self {
+        Self {
+            manager,
+            queues,
+            config_consumer,
+            switch_request_producer,
+            switch_response_consumer,
+        }
+    }
+
+    /// Run a block of the tape through the transport, see
+    /// `Transport::process`.
+    ///
+    /// A requested switch of cassettes starts before the block. The tape
+    /// stays silent until the outgoing cassette is backed up and the first
+    /// page of the new one is loaded.
+    pub fn process(
+        &mut self,
+        transport: &mut Transport,
+        input: &[[f32; TRACKS]],
+        output: &mut [[f32; TRACKS]],
+    ) {
+        self.manager.process_switch_responses(
+            &mut self.switch_response_consumer,
+            &mut self.switch_request_producer,
+            &mut self.queues.load_request_producer,
+        );
+        if let Some(cassette_id) = transport.take_switch() {
+            self.manager.start_switching(
+                cassette_id,
+                &mut self.queues.save_request_producer,
+                &mut self.switch_request_producer,
+            );
+        }
+        transport.drive(&mut self.manager, &mut self.queues);
+        self.manager
+            .process_configuration_updates(&mut self.config_consumer);
+        transport.process(&mut self.manager, &mut self.queues, input, output);
+    }
+
+    /// Take settings recovered with the newly loaded cassette.
+    pub fn take_settings(&mut self) -> Option<Settings> {
+        self.manager.take_settings()
+    }
+}
+
+/// Cassettes kept on the storage, served from the storage routine.
+pub struct Library<'a, S> {
+    store: Store<S>,
+    pool: &'a mut Pool,
+    queues: store::Queues<'a>,
+}
+
+impl<S: Storage> Library<'_, S> {
+    /// Serve all pending requests of the `Deck` and advance the background
+    /// job, if any.
+    pub fn process(&mut self) {
+        self.store.process(self.pool, &mut self.queues);
+    }
+
+    /// Space occupied by the given cassette.
+    pub fn usage(&mut self, cassette_id: CassetteId) -> Usage {
+        self.store.usage(cassette_id)
+    }
+
+    pub fn set_budget(&mut self, budget: Budget) {
+        self.store.set_budget(budget);
+    }
+
+    /// Start copying one cassette to another in the background.
+    pub fn start_copy(&mut self, source: CassetteId, target: CassetteId) -> Result<(), JobError> {
+        self.store.start_copy(source, target)
+    }
+
+    /// Start bouncing tracks of the cassette into its `target_track` in the
+    /// background.
+    pub fn start_bounce(
+        &mut self,
+        cassette_id: CassetteId,
+        sources: [bool; TRACKS],
+        target_track: usize,
+    ) -> Result<(), JobError> {
+        self.store.start_bounce(cassette_id, sources, target_track)
+    }
+
+    pub fn cancel_job(&mut self) {
+        self.store.cancel_job();
+    }
+
+    pub fn job_status(&self) -> JobStatus {
+        self.store.job_status()
+    }
+
+    /// Verify checksums of all the pages of the given cassette.
+    pub fn scan(&mut self, cassette_id: CassetteId) -> Scan {
+        self.store.scan(cassette_id)
+    }
+
+    pub fn diagnostics(&self) -> Diagnostics {
+        self.store.diagnostics
+    }
+
+    /// Access the storage directly, e.g. to persist the configuration.
+    pub fn storage_mut(&mut self) -> &mut S {
+        self.store.storage_mut()
+    }
+}
+
+/// Saves settings of cassettes, used by the control routine.
+pub struct SettingsQueue<'a> {
+    producer: Producer<'a, (CassetteId, Settings), 4>,
+}
+
+impl SettingsQueue<'_> {
+    /// Queue the settings to be saved. Returns `false` if the queue is full.
+    pub fn save(&mut self, cassette_id: CassetteId, settings: Settings) -> bool {
+        self.producer.enqueue((cassette_id, settings)).is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::page::PAGE_LENGTH;
+    use super::super::storage::memory::MemoryStorage;
+    use super::*;
+
+    const BLOCK: usize = 32;
+
+    /// Run blocks of constant input, the storage routine running between
+    /// them. Returns the last frame of the output.
+    fn run(
+        deck: &mut Deck,
+        library: &mut Library<MemoryStorage>,
+        transport: &mut Transport,
+        input: f32,
+        blocks: usize,
+    ) -> [f32; TRACKS] {
+        let mut output = [[0.0; TRACKS]; BLOCK];
+        for _ in 0..blocks {
+            deck.process(transport, &[[input; TRACKS]; BLOCK], &mut output);
+            library.process();
+        }
+        output[BLOCK - 1]
+    }
+
+    #[test]
+    fn recording_and_settings_are_recovered_after_switching_back() {
+        let mut queues = Queues::new();
+        let mut pool = Pool::new();
+        let (mut deck, mut library, mut settings_queue) = split(
+            &mut queues,
+            &mut pool,
+            MemoryStorage::default(),
+            SampleFormat::F32,
+        );
+        let mut transport = Transport::new();
+        let (first, second) = (CassetteId::new(0), CassetteId::new(1));
+
+        transport.switch_cassette(first);
+        run(&mut deck, &mut library, &mut transport, 0.0, 2);
+        assert_eq!(deck.take_settings(), Some(Settings::default()));
+        transport.toggle_play();
+        transport.set_recording(0, true);
+        let blocks = 3 * PAGE_LENGTH / BLOCK;
+        run(&mut deck, &mut library, &mut transport, 0.5, blocks);
+
+        let mut settings = Settings::default();
+        settings.tracks[1].volume = 0.25;
+        assert!(settings_queue.save(first, settings));
+        transport.stop_recording();
+        transport.switch_cassette(second);
+        let output = run(&mut deck, &mut library, &mut transport, 0.0, 4);
+        assert_eq!(output, [0.0; TRACKS]);
+        assert_eq!(deck.take_settings(), Some(Settings::default()));
+
+        transport.switch_cassette(first);
+        transport.play_from_beginning();
+        let output = run(&mut deck, &mut library, &mut transport, 0.0, 4);
+        assert_eq!(output[0], 0.5);
+        assert_eq!(deck.take_settings(), Some(settings));
+        assert!(library.usage(first).used >= 3 * PAGE_LENGTH * TRACKS * 4);
+        assert!(library.usage(second).is_empty());
+    }
+}
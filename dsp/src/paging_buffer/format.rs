@@ -0,0 +1,186 @@
+//! Encoding of samples on the persistent medium.
+//!
+//! Samples are always kept as `f32` in memory. Cassettes may however store
+//! them in a more compact integer form, saving bandwidth and space of the SD
+//! card. Quantization to integers is dithered to avoid correlated distortion
+//! of quiet recordings.
+
+/// Encoding of samples stored on a cassette.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum SampleFormat {
+    F32,
+    I24,
+    I16,
+}
+
+/// Size of the largest supported sample encoding in bytes.
+pub(crate) const MAX_SAMPLE_SIZE: usize = 4;
+
+impl SampleFormat {
+    pub(crate) fn sample_size(self) -> usize {
+        match self {
+            SampleFormat::F32 => 4,
+            SampleFormat::I24 => 3,
+            SampleFormat::I16 => 2,
+        }
+    }
+
+    pub(crate) fn to_byte(self) -> u8 {
+        match self {
+            SampleFormat::F32 => 0,
+            SampleFormat::I24 => 1,
+            SampleFormat::I16 => 2,
+        }
+    }
+
+    pub(crate) fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(SampleFormat::F32),
+            1 => Some(SampleFormat::I24),
+            2 => Some(SampleFormat::I16),
+            _ => None,
+        }
+    }
+
+    /// Write the sample into given bytes, the slice must be of `sample_size`.
+    pub(crate) fn encode(self, sample: f32, dither: &mut Dither, bytes: &mut [u8]) {
+        match self {
+            SampleFormat::F32 => bytes.copy_from_slice(&sample.to_le_bytes()),
+            SampleFormat::I24 => {
+                let value = quantize(sample, I24_MAX, dither);
+                bytes.copy_from_slice(&value.to_le_bytes()[..3]);
+            }
+            SampleFormat::I16 => {
+                let value = quantize(sample, I16_MAX, dither);
+                bytes.copy_from_slice(&value.to_le_bytes()[..2]);
+            }
+        }
+    }
+
+    /// Read a sample from given bytes, the slice must be of `sample_size`.
+    pub(crate) fn decode(self, bytes: &[u8]) -> f32 {
+        match self {
+            SampleFormat::F32 => f32::from_le_bytes(bytes.try_into().unwrap()),
+            SampleFormat::I24 => {
+                // Place the value to the top of i32 and shift it back to extend the sign.
+                let value = i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8;
+                value as f32 / I24_MAX as f32
+            }
+            SampleFormat::I16 => {
+                let value = i16::from_le_bytes(bytes.try_into().unwrap());
+                f32::from(value) / I16_MAX as f32
+            }
+        }
+    }
+}
+
+const I24_MAX: i32 = (1 << 23) - 1;
+const I16_MAX: i32 = (1 << 15) - 1;
+
+fn quantize(sample: f32, max: i32, dither: &mut Dither) -> i32 {
+    let scaled = sample.clamp(-1.0, 1.0) * max as f32 + dither.pop();
+    // Rounding to the nearest integer, `as` would truncate towards zero.
+    let rounded = if scaled < 0.0 {
+        scaled - 0.5
+    } else {
+        scaled + 0.5
+    } as i32;
+    rounded.clamp(-max, max)
+}
+
+/// Source of triangular probability density noise spanning ±1 LSB.
+pub(crate) struct Dither {
+    state: u32,
+}
+
+impl Dither {
+    pub(crate) fn new() -> Self {
+        Self { state: 0x9E37_79B9 }
+    }
+
+    pub(crate) fn pop(&mut self) -> f32 {
+        self.uniform() - self.uniform()
+    }
+
+    // Xorshift32 mapped to [0.0, 1.0).
+    fn uniform(&mut self) -> f32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        (self.state >> 8) as f32 / (1 << 24) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(format: SampleFormat, sample: f32) -> f32 {
+        let mut dither = Dither::new();
+        let mut bytes = [0; MAX_SAMPLE_SIZE];
+        let bytes = &mut bytes[..format.sample_size()];
+        format.encode(sample, &mut dither, bytes);
+        format.decode(bytes)
+    }
+
+    #[test]
+    fn f32_is_lossless() {
+        for sample in [-1.0, -0.123_456_79, 0.0, 0.5, 1.0] {
+            assert_eq!(roundtrip(SampleFormat::F32, sample), sample);
+        }
+    }
+
+    #[test]
+    fn i24_stays_within_two_lsb() {
+        for sample in [-1.0, -0.123_456_79, 0.0, 0.5, 1.0] {
+            let error = (roundtrip(SampleFormat::I24, sample) - sample).abs();
+            assert!(error <= 2.0 / I24_MAX as f32, "{} {}", sample, error);
+        }
+    }
+
+    #[test]
+    fn i16_stays_within_two_lsb() {
+        for sample in [-1.0, -0.123_456_79, 0.0, 0.5, 1.0] {
+            let error = (roundtrip(SampleFormat::I16, sample) - sample).abs();
+            assert!(error <= 2.0 / I16_MAX as f32, "{} {}", sample, error);
+        }
+    }
+
+    #[test]
+    fn out_of_range_samples_are_clipped() {
+        assert!((roundtrip(SampleFormat::I16, 2.0) - 1.0).abs() < 0.001);
+        assert!((roundtrip(SampleFormat::I16, -2.0) + 1.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn dithering_does_not_introduce_bias() {
+        let mut dither = Dither::new();
+        let mut bytes = [0; 2];
+        // A constant value sitting between two quantization steps.
+        let sample = 0.25 / I16_MAX as f32;
+        let mut sum = 0.0;
+        for _ in 0..10_000 {
+            SampleFormat::I16.encode(sample, &mut dither, &mut bytes);
+            sum += SampleFormat::I16.decode(&bytes);
+        }
+        let mean = sum / 10_000.0;
+        assert!((mean - sample).abs() < 0.05 / I16_MAX as f32);
+    }
+
+    #[test]
+    fn triangular_noise_stays_within_one_lsb() {
+        let mut dither = Dither::new();
+        for _ in 0..10_000 {
+            let noise = dither.pop();
+            assert!((-1.0..=1.0).contains(&noise));
+        }
+    }
+
+    #[test]
+    fn format_survives_serialization() {
+        for format in [SampleFormat::F32, SampleFormat::I24, SampleFormat::I16] {
+            assert_eq!(SampleFormat::from_byte(format.to_byte()), Some(format));
+        }
+        assert_eq!(SampleFormat::from_byte(42), None);
+    }
+}
@@ -1,17 +1,39 @@
 //! Non-blocking public interface.
 
 use heapless::spsc::{Consumer, Producer};
+use heapless::Vec;
 
 use super::buffer::Buffer;
-use super::cassette::Cassette;
+use super::cassette::{Cassette, CassetteId, TRACKS};
 use super::config::Config;
-use super::page::{Page, PageId, PageRequest};
+use super::page::{Page, PageRequest};
 use super::pool::Handle;
+use super::settings::Settings;
 
 /// Manager is a non-blocking public interface to paging buffer.
 pub(crate) struct Manager {
     buffer: Option<Buffer>,
     page_1_cache: Option<Handle>,
+    status: Status,
+    // Cassette of a switch request that was not answered by the storage yet.
+    switch_in_flight: Option<CassetteId>,
+    // Settings of the newly opened cassette, waiting to be applied.
+    settings: Option<Settings>,
+    // Pages that are not needed anymore, waiting to be passed back to the
+    // storage routine. There cannot be more of them than pages in the pool.
+    released: Vec<Handle, 4>,
+}
+
+/// Progress of switching between cassettes.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub(crate) enum Status {
+    /// The cassette is available for playback and recording.
+    Ready,
+    /// Pages of the outgoing cassette are being saved, the given cassette
+    /// will be loaded next.
+    BackingUp(CassetteId),
+    /// Waiting for the first page of the given cassette.
+    Loading(CassetteId),
 }
 
 impl Manager {
@@ -19,13 +41,97 @@ impl Manager {
         Self {
             buffer: None,
             page_1_cache: None,
+            status: Status::Ready,
+            switch_in_flight: None,
+            settings: None,
+            released: Vec::new(),
         }
     }
 
+    pub(crate) fn status(&self) -> Status {
+        self.status
+    }
+
+    /// Start using the given cassette right away.
+    ///
+    /// Pages held for the previous cassette are dropped without being saved,
+    /// `start_switching` should be used once a cassette is in use.
     pub(crate) fn set_cassette(&mut self, cassette: Cassette) {
         self.buffer = Some(Buffer::from_cassette(cassette));
     }
 
+    /// Back up the current cassette and request loading of another one.
+    ///
+    /// All the pages held by the manager are passed to the save queue. The
+    /// switch request then serves as a fence, the storage routine answers it
+    /// only after all of the preceding saves are done.
+    ///
+    /// Switching can be requested at any time, even while another cassette is
+    /// still being loaded. Its loading is then abandoned. Only a single switch
+    /// request is kept in flight, later requests just replace its target.
+    pub(crate) fn start_switching(
+        &mut self,
+        cassette_id: CassetteId,
+        save_request_producer: &mut Producer<Handle, 4>,
+        switch_request_producer: &mut Producer<CassetteId, 4>,
+    ) {
+        self.status = Status::BackingUp(cassette_id);
+
+        self.release_pages(save_request_producer);
+        if let Some(mut buffer) = self.buffer.take() {
+            if buffer.has_page() {
+                save_request_producer
+                    .enqueue(buffer.take_page())
+                    .ok()
+                    .unwrap();
+            }
+        }
+        if let Some(handle) = self.page_1_cache.take() {
+            save_request_producer.enqueue(handle).ok().unwrap();
+        }
+
+        if self.switch_in_flight.is_none() {
+            switch_request_producer.enqueue(cassette_id).ok().unwrap();
+            self.switch_in_flight = Some(cassette_id);
+        }
+    }
+
+    /// Start using the loaded cassette once its backup is done.
+    ///
+    /// If another cassette was selected in the meantime, the answered one is
+    /// skipped and a switch to the latest selection is requested instead.
+    pub(crate) fn process_switch_responses(
+        &mut self,
+        switch_response_consumer: &mut Consumer<Cassette, 4>,
+        switch_request_producer: &mut Producer<CassetteId, 4>,
+        load_request_producer: &mut Producer<PageRequest, 4>,
+    ) {
+        while let Some(cassette) = switch_response_consumer.dequeue() {
+            self.switch_in_flight = None;
+            match self.status {
+                Status::BackingUp(cassette_id) if cassette_id == cassette.id => {
+                    self.status = Status::Loading(cassette.id);
+                    self.settings = Some(cassette.settings);
+                    self.set_cassette(cassette);
+                    self.start_loading_next_page(load_request_producer);
+                }
+                Status::BackingUp(cassette_id) => {
+                    switch_request_producer.enqueue(cassette_id).ok().unwrap();
+                    self.switch_in_flight = Some(cassette_id);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Take settings recovered with the newly opened cassette.
+    ///
+    /// Pending settings of the outgoing cassette are expected to be queued
+    /// for saving before `start_switching` is called.
+    pub(crate) fn take_settings(&mut self) -> Option<Settings> {
+        self.settings.take()
+    }
+
     pub(crate) fn start_loading_next_page(
         &mut self,
         load_request_producer: &mut Producer<PageRequest, 4>,
@@ -42,17 +148,32 @@ impl Manager {
         &mut self,
         config_consumer: &mut Consumer<Config, 4>,
     ) {
-        let buffer = self.buffer.as_mut().unwrap();
+        // Updates are kept in the queue until there is a cassette to apply them on.
+        let Some(buffer) = self.buffer.as_mut() else {
+            return;
+        };
         while let Some(config) = config_consumer.dequeue() {
             buffer.recording = config.recording;
         }
     }
 
+    /// Arm tracks right away, bypassing the configuration queue.
+    pub(crate) fn set_recording(&mut self, recording: [bool; TRACKS]) {
+        if let Some(buffer) = self.buffer.as_mut() {
+            buffer.recording = recording;
+        }
+    }
+
     pub(crate) fn is_waiting_for_page(&self) -> bool {
-        let buffer = self.buffer.as_ref().unwrap();
-        !buffer.has_page()
+        self.buffer
+            .as_ref()
+            .is_some_and(|buffer| !buffer.has_page())
     }
 
+    /// Pass the awaited page to the buffer, if it was already loaded.
+    ///
+    /// Pages that are not expected anymore, e.g. those requested for the
+    /// previous cassette, are kept to be released, see `release_pages`.
     pub(crate) fn try_fetching_next_page(
         &mut self,
         load_response_consumer: &mut Consumer<Handle, 4>,
@@ -62,6 +183,7 @@ impl Manager {
         if buffer.next_page().page_id().page_index() == 0 {
             if let Some(handle) = self.page_1_cache.take() {
                 buffer.set_page(handle);
+                self.status = Status::Ready;
                 return true;
             }
         }
@@ -69,21 +191,48 @@ impl Manager {
         while let Some(handle) = load_response_consumer.dequeue() {
             if handle.page_ref().id() == buffer.next_page().page_id() {
                 buffer.set_page(handle);
+                self.status = Status::Ready;
                 return true;
+            } else {
+                self.released.push(handle).ok().unwrap();
             }
         }
 
         false
     }
 
-    pub(crate) fn process(&mut self, block: &[f32]) {
-        let buffer = self.buffer.as_mut().unwrap();
-        buffer.process(block);
+    /// Pass pages that are not needed anymore to the save queue, so the
+    /// storage routine can release them from the pool.
+    ///
+    /// Pages that do not fit into the queue are kept for the next call.
+    pub(crate) fn release_pages(&mut self, save_request_producer: &mut Producer<Handle, 4>) {
+        while let Some(handle) = self.released.pop() {
+            if let Err(handle) = save_request_producer.enqueue(handle) {
+                self.released.push(handle).ok().unwrap();
+                break;
+            }
+        }
+    }
+
+    /// Run the tape up to the end of the active page, see `Buffer::process`.
+    pub(crate) fn process(
+        &mut self,
+        input: &[[f32; TRACKS]],
+        output: &mut [[f32; TRACKS]],
+    ) -> usize {
+        match self.buffer.as_mut() {
+            Some(buffer) => buffer.process(input, output),
+            None => {
+                output.fill([0.0; TRACKS]);
+                input.len()
+            }
+        }
     }
 
     pub(crate) fn has_full_page(&self) -> bool {
-        let buffer = self.buffer.as_ref().unwrap();
-        buffer.has_full_page()
+        self.buffer
+            .as_ref()
+            .is_some_and(|buffer| buffer.has_full_page())
     }
 
     pub(crate) fn start_saving(
@@ -96,21 +245,42 @@ impl Manager {
         let page = buffer.take_page();
 
         // TODO: Check cassette ID too
-        if page.page_ref().is_dirty() {
-            if page.page_ref().index() == 0 {
+        if page.page_ref().index() == 0 {
+            if page.page_ref().is_dirty() {
                 save_request_first_page_producer
                     .enqueue(page.page_clone())
                     .ok()
                     .unwrap();
-                self.page_1_cache = Some(page);
-            } else {
-                save_request_producer.enqueue(page).ok().unwrap();
+                page.page_mut().mark_clean();
+            }
+            if let Some(previous) = self.page_1_cache.replace(page) {
+                self.released.push(previous).ok().unwrap();
             }
+        } else if page.page_ref().is_dirty() {
+            save_request_producer.enqueue(page).ok().unwrap();
+        } else {
+            self.released.push(page).ok().unwrap();
         }
     }
 
     pub(crate) fn reset_position(&mut self) {
-        let buffer = self.buffer.as_mut().unwrap();
-        buffer.reset_position();
+        if let Some(buffer) = self.buffer.as_mut() {
+            buffer.reset_position();
+        }
+    }
+
+    /// Return the active page and move to the start of the cassette.
+    ///
+    /// The first page is then served from the cache, a page that is still
+    /// being loaded gets released once it arrives.
+    pub(crate) fn rewind(
+        &mut self,
+        save_request_producer: &mut Producer<Handle, 4>,
+        save_request_first_page_producer: &mut Producer<Page, 4>,
+    ) {
+        if self.buffer.as_ref().is_some_and(|buffer| buffer.has_page()) {
+            self.start_saving(save_request_producer, save_request_first_page_producer);
+        }
+        self.reset_position();
     }
 }
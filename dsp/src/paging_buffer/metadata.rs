@@ -0,0 +1,92 @@
+//! Description of a cassette stored next to its audio.
+
+use crc::{Crc, CRC_32_ISO_HDLC};
+
+use super::cassette::TRACKS;
+use super::format::SampleFormat;
+
+const MAGIC: [u8; 4] = *b"TBTR";
+// Version 1 did not store the number of tracks, cassettes were mono.
+const VERSION: u8 = 2;
+pub(crate) const METADATA_SIZE: usize = 12;
+
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// Attributes of a cassette that are needed to interpret its audio file.
+///
+/// Cassettes recorded before metadata were introduced have none stored. Those
+/// are treated as `legacy`.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub(crate) struct Metadata {
+    pub format: SampleFormat,
+    /// Number of tracks stored in each record, the rest of them is silent.
+    pub tracks: usize,
+}
+
+impl Metadata {
+    pub(crate) fn new(format: SampleFormat) -> Self {
+        Self {
+            format,
+            tracks: TRACKS,
+        }
+    }
+
+    pub(crate) fn legacy() -> Self {
+        Self {
+            format: SampleFormat::F32,
+            tracks: 1,
+        }
+    }
+
+    pub(crate) fn to_bytes(self) -> [u8; METADATA_SIZE] {
+        let mut bytes = [0; METADATA_SIZE];
+        bytes[..4].copy_from_slice(&MAGIC);
+        bytes[4] = VERSION;
+        bytes[5] = self.format.to_byte();
+        bytes[6] = self.tracks as u8;
+        let checksum = CRC.checksum(&bytes[..8]);
+        bytes[8..].copy_from_slice(&checksum.to_le_bytes());
+        bytes
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8; METADATA_SIZE]) -> Option<Self> {
+        let checksum = u32::from_le_bytes(bytes[8..].try_into().unwrap());
+        if bytes[..4] != MAGIC || CRC.checksum(&bytes[..8]) != checksum {
+            return None;
+        }
+        let format = SampleFormat::from_byte(bytes[5])?;
+        let tracks = match bytes[4] {
+            1 => 1,
+            VERSION => bytes[6] as usize,
+            _ => return None,
+        };
+        if tracks == 0 || tracks > TRACKS {
+            return None;
+        }
+        Some(Self { format, tracks })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata_survive_serialization() {
+        let metadata = Metadata::new(SampleFormat::I24);
+        assert_eq!(Metadata::from_bytes(&metadata.to_bytes()), Some(metadata));
+    }
+
+    #[test]
+    fn metadata_of_version_1_describe_mono_cassette() {
+        let mut bytes = Metadata::new(SampleFormat::I16).to_bytes();
+        bytes[4] = 1;
+        bytes[6] = 0;
+        let checksum = CRC.checksum(&bytes[..8]);
+        bytes[8..].copy_from_slice(&checksum.to_le_bytes());
+
+        let metadata = Metadata::from_bytes(&bytes).unwrap();
+        assert_eq!(metadata.format, SampleFormat::I16);
+        assert_eq!(metadata.tracks, 1);
+    }
+}
@@ -42,7 +42,7 @@
 //! 13. The loaded page is then passed to buffer instead of empty pages used before.
 //! 14. At some point, midway through the sample, recording stops.
 //! 15. Any new samples will be returned like before, except now they will not
-//!     be dirty and thus just thrown away.
+//!     be dirty and thus the storage routine only releases them.
 //!
 //! # Flow starting from a loaded sample
 //!
@@ -51,18 +51,63 @@
 //! 3. The caller passes the first page to the buffer.
 //! 4. Business as usual.
 //!
+//! # Switching between cassettes
+//!
+//! 1. The caller asks the manager to switch to another cassette.
+//! 2. The manager passes its active page and the cached first page to the save
+//!    queue and stops playback. It then sends a switch request.
+//! 3. The storage routine finishes all saves queued before the switch request,
+//!    drops load requests of the outgoing cassette and opens the new one.
+//! 4. The manager receives the opened cassette together with its settings and
+//!    requests its first page.
+//! 5. Once the first page arrives, playback resumes. The progress can be
+//!    observed through the manager's status.
+//!
 //! # Working with samples shorter than a single page
 //!
 //! 1. Buffer gets an inpulse to reset midway through the first page.
 //! 2. The caller takes page from the buffer, clones it for save queue, clones it for
 //!    its own cache and passes it back to the buffer.
 
+mod block_storage;
 mod buffer;
 mod cassette;
 mod config;
+mod deck;
+mod format;
 mod manager;
+mod metadata;
 mod page;
 mod pool;
+mod queues;
+mod reset;
+mod selector;
+mod settings;
+mod storage;
+mod store;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod transport;
+mod usage;
+
+pub use block_storage::{BlockDevice, BlockStorage, BLOCK_SIZE};
+pub use cassette::{CassetteId, BANKS, CASSETTES, CASSETTES_PER_BANK, TRACKS};
+pub use deck::{split, Deck, Library, SettingsQueue};
+pub use format::SampleFormat;
+pub use pool::Pool;
+pub use queues::Queues;
+pub use reset::{factory_reset, resume_interrupted_reset};
+pub use selector::Selector;
+pub use settings::{
+    Debounce, Pickup, PickupMode, PickupModes, Settings, SettingsPickup, TrackSettings,
+};
+pub use storage::{Error, File, Storage};
+pub use store::{Diagnostics, JobError, JobStatus, Scan};
+pub use transport::{Cue, Transport, MAX_CUES};
+pub use usage::{Budget, Usage};
+
+#[cfg(test)]
+pub(crate) use storage::memory;
 
 #[cfg(test)]
 mod tests {
@@ -121,7 +166,9 @@ mod tests {
 
         // Control loop issues request for recording.
         config_producer
-            .enqueue(Config { recording: true })
+            .enqueue(Config {
+                recording: [true, false, false, false],
+            })
             .ok()
             .unwrap();
 
@@ -137,7 +184,7 @@ mod tests {
                 }
             }
 
-            manager.process(&mut [0.1; 32]);
+            manager.process(&[[0.1; TRACKS]; 32], &mut [[0.0; TRACKS]; 32]);
 
             if manager.has_full_page() {
                 manager.start_saving(
@@ -179,7 +226,7 @@ mod tests {
                 }
             }
 
-            manager.process(&mut [0.2; 32]);
+            manager.process(&[[0.2; TRACKS]; 32], &mut [[0.0; TRACKS]; 32]);
 
             if manager.has_full_page() {
                 manager.start_saving(
@@ -227,7 +274,7 @@ mod tests {
                     }
                 }
 
-                manager.process(&mut [0.3; 32]);
+                manager.process(&[[0.3; TRACKS]; 32], &mut [[0.0; TRACKS]; 32]);
             }
 
             manager.start_saving(
@@ -279,7 +326,7 @@ mod tests {
                 }
             }
 
-            manager.process(&mut [0.4; 32]);
+            manager.process(&[[0.4; TRACKS]; 32], &mut [[0.0; TRACKS]; 32]);
 
             if manager.has_full_page() {
                 manager.start_saving(
@@ -313,7 +360,9 @@ mod tests {
         // Control loop issues request for recording.
         {
             config_producer
-                .enqueue(Config { recording: false })
+                .enqueue(Config {
+                    recording: [false; TRACKS],
+                })
                 .ok()
                 .unwrap();
         }
@@ -330,7 +379,7 @@ mod tests {
                 }
             }
 
-            manager.process(&mut [0.5; 32]);
+            manager.process(&[[0.5; TRACKS]; 32], &mut [[0.0; TRACKS]; 32]);
 
             if manager.has_full_page() {
                 manager.start_saving(
@@ -362,8 +411,283 @@ mod tests {
         }
     }
 
+    #[test]
+    fn switch_cassette_after_backing_up_the_current_one() {
+        use cassette::Cassette;
+        use config::Config;
+        use format::SampleFormat;
+        use manager::{Manager, Status};
+        use page::{PageId, PageRequest, PAGE_LENGTH};
+        use pool::Pool;
+        use storage::memory::MemoryStorage;
+        use store::Store;
+
+        let mut queues = queues::Queues::new();
+        let mut ends = queues.split();
+
+        static mut POOL: Pool = Pool::new();
+        let pool = unsafe { &mut POOL };
+        let mut store = Store::new(MemoryStorage::default(), SampleFormat::F32);
+        let mut manager = Manager::new();
+
+        manager.set_cassette(Cassette::new(0));
+        manager.start_loading_next_page(&mut ends.transport.load_request_producer);
+        store.process(pool, &mut ends.store);
+        ends.transport
+            .config_producer
+            .enqueue(Config {
+                recording: [true, false, false, false],
+            })
+            .ok()
+            .unwrap();
+
+        // Record one and a half of a page, with the storage routine running
+        // between DSP ticks.
+        for _ in 0..PAGE_LENGTH * 3 / 2 / 32 {
+            manager.release_pages(&mut ends.transport.save_request_producer);
+            manager.process_configuration_updates(&mut ends.config_consumer);
+            if manager.is_waiting_for_page()
+                && manager.try_fetching_next_page(&mut ends.transport.load_response_consumer)
+            {
+                manager.start_loading_next_page(&mut ends.transport.load_request_producer);
+            }
+            manager.process(&[[0.1; TRACKS]; 32], &mut [[0.0; TRACKS]; 32]);
+            if manager.has_full_page() {
+                manager.start_saving(
+                    &mut ends.transport.save_request_producer,
+                    &mut ends.transport.save_request_first_page_producer,
+                );
+            }
+            store.process(pool, &mut ends.store);
+        }
+
+        // Settings of the outgoing cassette waiting for debounce are flushed
+        // before the switch.
+        let mut custom_settings = Settings::default();
+        custom_settings.tracks[2].effect = 3;
+        custom_settings.pitch = 0.8;
+        let mut settings_debounce = Debounce::new(100);
+        settings_debounce.set(custom_settings);
+        if let Some(settings) = settings_debounce.flush() {
+            ends.settings_save_request_producer
+                .enqueue((CassetteId::new(0), settings))
+                .ok()
+                .unwrap();
+        }
+
+        // Nothing is loaded until the backup is confirmed by the storage routine.
+        let cassette_id = CassetteId::new(1);
+        manager.start_switching(
+            cassette_id,
+            &mut ends.transport.save_request_producer,
+            &mut ends.switch_request_producer,
+        );
+        assert_eq!(manager.status(), Status::BackingUp(cassette_id));
+        manager.process_switch_responses(
+            &mut ends.switch_response_consumer,
+            &mut ends.switch_request_producer,
+            &mut ends.transport.load_request_producer,
+        );
+        assert_eq!(manager.status(), Status::BackingUp(cassette_id));
+        assert!(!manager.is_waiting_for_page());
+
+        store.process(pool, &mut ends.store);
+        manager.process_switch_responses(
+            &mut ends.switch_response_consumer,
+            &mut ends.switch_request_producer,
+            &mut ends.transport.load_request_producer,
+        );
+        assert_eq!(manager.status(), Status::Loading(cassette_id));
+        assert!(manager.is_waiting_for_page());
+
+        store.process(pool, &mut ends.store);
+        assert!(manager.try_fetching_next_page(&mut ends.transport.load_response_consumer));
+        assert_eq!(manager.status(), Status::Ready);
+        assert_eq!(manager.take_settings(), Some(Settings::default()));
+        assert_eq!(manager.take_settings(), None);
+
+        // Both pages of the outgoing cassette were persisted, including the
+        // partially recorded one.
+        let outgoing = CassetteId::new(0);
+        for page_index in 0..2 {
+            let handle =
+                store.handle_request(PageRequest::Load(PageId::new(outgoing, page_index)), pool);
+            assert_eq!(handle.page_ref().data[0][0], 0.1);
+            pool.take_page(handle);
+        }
+        assert_eq!(store.open_cassette(outgoing).length, 2 * PAGE_LENGTH);
+        assert_eq!(store.diagnostics.failed_saves, 0);
+        assert_eq!(store.open_cassette(outgoing).settings, custom_settings);
+    }
+
+    #[test]
+    fn pages_not_needed_anymore_are_passed_back_to_be_released() {
+        use cassette::Cassette;
+        use manager::Manager;
+        use page::{PageId, PAGE_LENGTH};
+        use pool::Pool;
+
+        let mut queues = queues::Queues::new();
+        let mut ends = queues.split();
+
+        static mut POOL: Pool = Pool::new();
+        let pool = unsafe { &mut POOL };
+        let mut manager = Manager::new();
+        let cassette_id = CassetteId::new(1);
+        manager.set_cassette(Cassette::new(1));
+
+        // A page requested for another cassette arrives before the awaited one.
+        for page_id in [
+            PageId::new(CassetteId::new(2), 0),
+            PageId::new(cassette_id, 0),
+            PageId::new(cassette_id, 1),
+        ] {
+            let handle = pool.new_page(page_id);
+            ends.store
+                .load_response_producer
+                .enqueue(handle)
+                .ok()
+                .unwrap();
+        }
+        assert!(manager.try_fetching_next_page(&mut ends.transport.load_response_consumer));
+
+        // Pages played without recording are not saved, the first one stays
+        // cached for rewinding.
+        for _ in 0..2 {
+            manager.process(
+                &[[0.0; TRACKS]; PAGE_LENGTH],
+                &mut [[0.0; TRACKS]; PAGE_LENGTH],
+            );
+            manager.start_saving(
+                &mut ends.transport.save_request_producer,
+                &mut ends.transport.save_request_first_page_producer,
+            );
+            manager.try_fetching_next_page(&mut ends.transport.load_response_consumer);
+        }
+        assert!(ends.store.save_request_consumer.dequeue().is_none());
+        assert!(ends
+            .store
+            .save_request_first_page_consumer
+            .dequeue()
+            .is_none());
+
+        manager.release_pages(&mut ends.transport.save_request_producer);
+        assert_and_handle_release_request(
+            PageId::new(cassette_id, 1),
+            pool,
+            &mut ends.store.save_request_consumer,
+        );
+        assert_and_handle_release_request(
+            PageId::new(CassetteId::new(2), 0),
+            pool,
+            &mut ends.store.save_request_consumer,
+        );
+        assert!(ends.store.save_request_consumer.dequeue().is_none());
+        assert_eq!(pool.stored(), 1);
+    }
+
+    #[test]
+    fn rapid_sweep_across_cassettes_neither_corrupts_nor_leaks_pages() {
+        use cassette::Cassette;
+        use config::Config;
+        use format::SampleFormat;
+        use manager::{Manager, Status};
+        use page::{PageId, PageRequest, PAGE_LENGTH};
+        use pool::Pool;
+        use storage::memory::MemoryStorage;
+        use store::Store;
+
+        let mut queues = queues::Queues::new();
+        let mut ends = queues.split();
+
+        static mut POOL: Pool = Pool::new();
+        let pool = unsafe { &mut POOL };
+        let mut store = Store::new(MemoryStorage::default(), SampleFormat::F32);
+        let mut manager = Manager::new();
+
+        manager.set_cassette(Cassette::new(0));
+        manager.start_loading_next_page(&mut ends.transport.load_request_producer);
+        store.process(pool, &mut ends.store);
+        ends.transport
+            .config_producer
+            .enqueue(Config {
+                recording: [true, false, false, false],
+            })
+            .ok()
+            .unwrap();
+
+        // Record one and a half of a page into the first cassette, then sweep
+        // the selector back and forth. The storage routine is slower than the
+        // knob, so most of the switches and loads are abandoned midway.
+        let mut custom_settings = Settings::default();
+        custom_settings.tracks[0].volume = 0.3;
+        ends.settings_save_request_producer
+            .enqueue((CassetteId::new(0), custom_settings))
+            .ok()
+            .unwrap();
+        let sweep = [1, 2, 3, 4, 5, 6, 7, 6, 5, 4, 3];
+        let recording_ticks = PAGE_LENGTH * 3 / 2 / 32;
+        for tick in 0..recording_ticks + sweep.len() + 12 {
+            if tick >= recording_ticks && tick < recording_ticks + sweep.len() {
+                manager.start_switching(
+                    CassetteId::new(sweep[tick - recording_ticks]),
+                    &mut ends.transport.save_request_producer,
+                    &mut ends.switch_request_producer,
+                );
+            }
+
+            manager.process_switch_responses(
+                &mut ends.switch_response_consumer,
+                &mut ends.switch_request_producer,
+                &mut ends.transport.load_request_producer,
+            );
+            manager.release_pages(&mut ends.transport.save_request_producer);
+            manager.process_configuration_updates(&mut ends.config_consumer);
+            if manager.is_waiting_for_page()
+                && manager.try_fetching_next_page(&mut ends.transport.load_response_consumer)
+            {
+                manager.start_loading_next_page(&mut ends.transport.load_request_producer);
+            }
+            manager.process(&[[0.1; TRACKS]; 32], &mut [[0.0; TRACKS]; 32]);
+            if manager.has_full_page() {
+                manager.start_saving(
+                    &mut ends.transport.save_request_producer,
+                    &mut ends.transport.save_request_first_page_producer,
+                );
+            }
+
+            if tick < recording_ticks || tick % 3 == 0 {
+                store.process(pool, &mut ends.store);
+            }
+        }
+        assert_eq!(manager.status(), Status::Ready);
+        assert_eq!(manager.take_settings(), Some(Settings::default()));
+
+        // Only the active page of the last selected cassette and its prefetched
+        // successor are held.
+        assert_eq!(pool.stored(), 2);
+
+        // The recording survived intact and none of the visited cassettes
+        // was written to.
+        let recorded = CassetteId::new(0);
+        let scan = store.scan(recorded);
+        assert!(scan.is_intact());
+        assert_eq!(scan.pages, 2);
+        for page_index in 0..2 {
+            let handle =
+                store.handle_request(PageRequest::Load(PageId::new(recorded, page_index)), pool);
+            assert_eq!(handle.page_ref().data[0][0], 0.1);
+            pool.take_page(handle);
+        }
+        for index in 1..CASSETTES {
+            assert_eq!(store.open_cassette(CassetteId::new(index)).length, 0);
+        }
+        assert_eq!(store.diagnostics.failed_saves, 0);
+        assert_eq!(store.open_cassette(recorded).settings, custom_settings);
+    }
+
     fn assert_recorded(page_index: usize, value: f32, sd: &mut [Option<page::Page>; 4]) {
-        let first_sample = sd[page_index].as_ref().unwrap().data[0];
+        let first_sample = sd[page_index].as_ref().unwrap().data[0][0];
         assert_eq!(
             first_sample, value,
             "First sample of the given page has an unexpected value"
@@ -394,6 +718,23 @@ mod tests {
         }
     }
 
+    fn assert_and_handle_release_request(
+        expected_page_id: page::PageId,
+        pool: &mut pool::Pool,
+        save_request_consumer: &mut Consumer<pool::Handle, 4>,
+    ) {
+        let handle = save_request_consumer
+            .dequeue()
+            .expect("No release request was received");
+        assert_eq!(
+            handle.page_ref().id(),
+            expected_page_id,
+            "Unexpected release request"
+        );
+        assert!(!handle.page_ref().is_dirty(), "Released page is dirty");
+        pool.take_page(handle);
+    }
+
     fn assert_and_handle_page_save_request(
         expected_first_page_save_request: Option<page::PageId>,
         sd: &mut [Option<page::Page>; 4],
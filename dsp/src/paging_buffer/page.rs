@@ -1,14 +1,16 @@
 //! Blobs of data.
 
-use super::cassette::CassetteId;
+use super::cassette::{CassetteId, TRACKS};
 
-/// Blob of data containing a part of audio sample.
+/// Number of samples stored in a single page.
+pub(crate) const PAGE_LENGTH: usize = 512;
+
+/// Blob of data containing a part of audio sample, for each of the tracks.
 #[derive(Clone)]
 pub(crate) struct Page {
     id: PageId,
     dirty: bool,
-    // TODO: Use constants
-    pub data: [f32; 512],
+    pub data: [[f32; PAGE_LENGTH]; TRACKS],
 }
 
 impl Page {
@@ -16,7 +18,7 @@ impl Page {
         Self {
             id,
             dirty: false,
-            data: [0.0; 512],
+            data: [[0.0; PAGE_LENGTH]; TRACKS],
         }
     }
 
@@ -32,6 +34,10 @@ impl Page {
         self.dirty = true;
     }
 
+    pub(crate) fn mark_clean(&mut self) {
+        self.dirty = false;
+    }
+
     pub(crate) fn is_dirty(&self) -> bool {
         self.dirty
     }
@@ -56,17 +62,25 @@ impl PageRequest {
 /// Unique identificator of a page.
 #[derive(PartialEq, Clone, Copy, Debug)]
 pub(crate) struct PageId {
+    cassette_id: CassetteId,
     page_index: usize,
 }
 
 impl PageId {
+    pub(crate) fn cassette_id(&self) -> CassetteId {
+        self.cassette_id
+    }
+
     pub(crate) fn page_index(&self) -> usize {
         self.page_index
     }
 }
 
 impl PageId {
-    pub(crate) fn new(_cassette_id: CassetteId, page_index: usize) -> Self {
-        Self { page_index }
+    pub(crate) fn new(cassette_id: CassetteId, page_index: usize) -> Self {
+        Self {
+            cassette_id,
+            page_index,
+        }
     }
 }
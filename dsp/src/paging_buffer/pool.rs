@@ -4,12 +4,18 @@ use super::page::{Page, PageId};
 
 /// Memory pool that could be used as a global singleton to avoid copying
 /// of `Page` blobs.
-pub(crate) struct Pool {
+pub struct Pool {
     store: [Option<Page>; 4],
 }
 
+impl Default for Pool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Pool {
-    pub(crate) const fn new() -> Pool {
+    pub const fn new() -> Pool {
         Pool {
             store: [None, None, None, None],
         }
@@ -35,7 +41,7 @@ impl Pool {
         self.store[handle.pool_index] = None;
     }
 
-    fn stored(&self) -> usize {
+    pub(crate) fn stored(&self) -> usize {
         self.store.iter().filter(|x| x.is_some()).count()
     }
 
@@ -50,6 +56,10 @@ pub(crate) struct Handle {
     address: *mut Option<Page>,
 }
 
+// SAFETY: Each handle points to a distinct slot of the pool and is not
+// cloned, so the page is accessed only by the routine holding its handle.
+unsafe impl Send for Handle {}
+
 impl Handle {
     pub(crate) fn page_ref(&self) -> &Page {
         unsafe { &*self.address }.as_ref().unwrap()
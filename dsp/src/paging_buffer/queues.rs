@@ -0,0 +1,90 @@
+//! Queues connecting the audio routine with the storage routine.
+
+use heapless::spsc::{Consumer, Producer, Queue};
+
+use super::cassette::{Cassette, CassetteId};
+use super::config::Config;
+use super::page::{Page, PageRequest};
+use super::pool::Handle;
+use super::settings::Settings;
+use super::{store, transport};
+
+/// All the queues of the paging buffer.
+///
+/// They must outlive both routines of the buffer, see `deck::split`.
+pub struct Queues {
+    config: Queue<Config, 4>,
+    save_request: Queue<Handle, 4>,
+    save_request_first_page: Queue<Page, 4>,
+    load_request: Queue<PageRequest, 4>,
+    load_response: Queue<Handle, 4>,
+    switch_request: Queue<CassetteId, 4>,
+    switch_response: Queue<Cassette, 4>,
+    settings_save_request: Queue<(CassetteId, Settings), 4>,
+}
+
+/// Ends of all the queues, grouped by their users.
+pub(crate) struct Ends<'a> {
+    pub transport: transport::Queues<'a>,
+    pub config_consumer: Consumer<'a, Config, 4>,
+    pub switch_request_producer: Producer<'a, CassetteId, 4>,
+    pub switch_response_consumer: Consumer<'a, Cassette, 4>,
+    pub settings_save_request_producer: Producer<'a, (CassetteId, Settings), 4>,
+    pub store: store::Queues<'a>,
+}
+
+impl Default for Queues {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Queues {
+    pub const fn new() -> Self {
+        Self {
+            config: Queue::new(),
+            save_request: Queue::new(),
+            save_request_first_page: Queue::new(),
+            load_request: Queue::new(),
+            load_response: Queue::new(),
+            switch_request: Queue::new(),
+            switch_response: Queue::new(),
+            settings_save_request: Queue::new(),
+        }
+    }
+
+    pub(crate) fn split(&mut self) -> Ends<'_> {
+        let (config_producer, config_consumer) = self.config.split();
+        let (save_request_producer, save_request_consumer) = self.save_request.split();
+        let (save_request_first_page_producer, save_request_first_page_consumer) =
+            self.save_request_first_page.split();
+        let (load_request_producer, load_request_consumer) = self.load_request.split();
+        let (load_response_producer, load_response_consumer) = self.load_response.split();
+        let (switch_request_producer, switch_request_consumer) = self.switch_request.split();
+        let (switch_response_producer, switch_response_consumer) = self.switch_response.split();
+        let (settings_save_request_producer, settings_save_request_consumer) =
+            self.settings_save_request.split();
+        Ends {
+            transport: transport::Queues {
+                config_producer,
+                save_request_producer,
+                save_request_first_page_producer,
+                load_request_producer,
+                load_response_consumer,
+            },
+            config_consumer,
+            switch_request_producer,
+            switch_response_consumer,
+            settings_save_request_producer,
+            store: store::Queues {
+                save_request_consumer,
+                save_request_first_page_consumer,
+                load_request_consumer,
+                load_response_producer,
+                switch_request_consumer,
+                switch_response_producer,
+                settings_save_request_consumer,
+            },
+        }
+    }
+}
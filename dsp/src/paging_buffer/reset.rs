@@ -0,0 +1,117 @@
+//! Factory reset of all persisted data.
+//!
+//! The reset is journaled. A marker is written before anything gets removed
+//! and it is deleted only after all the files are gone. If the power is lost
+//! midway, the reset is resumed on the next start, so the medium never ends
+//! up with a mix of wiped and preserved data.
+//!
+//! Defaults do not need to be written back. Missing files are interpreted
+//! as blank cassettes and default configuration.
+
+use super::cassette::{CassetteId, CASSETTES};
+use super::storage::{Error, File, Storage};
+
+const JOURNAL: &[u8] = b"RESET";
+
+/// Erase all recordings and configuration.
+pub fn factory_reset<S: Storage>(storage: &mut S) -> Result<(), Error> {
+    storage.write(File::ResetJournal, 0, JOURNAL)?;
+    wipe(storage)?;
+    storage.remove(File::ResetJournal)
+}
+
+/// Finish a factory reset that was interrupted by a power loss.
+///
+/// Returns `true` if there was an interrupted reset to finish.
+pub fn resume_interrupted_reset<S: Storage>(storage: &mut S) -> Result<bool, Error> {
+    if storage.size(File::ResetJournal) == 0 {
+        return Ok(false);
+    }
+    wipe(storage)?;
+    storage.remove(File::ResetJournal)?;
+    Ok(true)
+}
+
+fn wipe<S: Storage>(storage: &mut S) -> Result<(), Error> {
+    for index in 0..CASSETTES {
+        let id = CassetteId::new(index);
+        storage.remove(File::Audio(id))?;
+        storage.remove(File::Metadata(id))?;
+        storage.remove(File::Settings(id))?;
+    }
+    storage.remove(File::Config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::storage::memory::MemoryStorage;
+    use super::*;
+
+    fn populated_storage() -> MemoryStorage {
+        let mut storage = MemoryStorage::default();
+        for index in 0..CASSETTES {
+            let id = CassetteId::new(index);
+            storage.write(File::Audio(id), 0, &[1; 64]).unwrap();
+            storage.write(File::Metadata(id), 0, &[2; 8]).unwrap();
+            storage.write(File::Settings(id), 0, &[4; 8]).unwrap();
+        }
+        storage.write(File::Config, 0, &[3; 16]).unwrap();
+        storage
+    }
+
+    fn all_files() -> Vec<File> {
+        let mut files = vec![File::Config, File::ResetJournal];
+        for index in 0..CASSETTES {
+            files.push(File::Audio(CassetteId::new(index)));
+            files.push(File::Metadata(CassetteId::new(index)));
+            files.push(File::Settings(CassetteId::new(index)));
+        }
+        files
+    }
+
+    #[test]
+    fn erase_all_recordings_and_configuration() {
+        let mut storage = populated_storage();
+
+        factory_reset(&mut storage).unwrap();
+
+        for file in all_files() {
+            assert!(!storage.exists(file), "{:?} was not removed", file);
+        }
+    }
+
+    #[test]
+    fn do_not_resume_when_no_reset_was_started() {
+        let mut storage = populated_storage();
+
+        assert!(!resume_interrupted_reset(&mut storage).unwrap());
+        assert!(storage.exists(File::Config));
+    }
+
+    #[test]
+    fn power_loss_at_any_point_leaves_the_medium_consistent() {
+        let modifications = 3 * CASSETTES + 3;
+        for cut_after in 0..modifications {
+            let mut storage = populated_storage();
+
+            storage.cut_power_after(cut_after);
+            assert!(factory_reset(&mut storage).is_err());
+            storage.restore_power();
+
+            resume_interrupted_reset(&mut storage).unwrap();
+
+            if cut_after == 0 {
+                assert!(storage.exists(File::Config), "Nothing should be erased");
+            } else {
+                for file in all_files() {
+                    assert!(
+                        !storage.exists(file),
+                        "{:?} was not removed after cut at {}",
+                        file,
+                        cut_after
+                    );
+                }
+            }
+        }
+    }
+}
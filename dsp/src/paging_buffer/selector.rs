@@ -0,0 +1,116 @@
+//! Selection of a cassette using the rotary switch and a bank modifier.
+//!
+//! Turning the switch selects a cassette within the current bank. Turning it
+//! while the bank button is held selects the bank instead, together with the
+//! cassette on the position the switch points at, so the selected cassette
+//! always matches the switch. Either way, the resulting cassette is switched
+//! to through the usual backup-then-load flow of the `Manager`.
+
+use super::cassette::{CassetteId, BANKS};
+
+/// Translates movements of the rotary switch into selected cassettes.
+pub struct Selector {
+    selected: CassetteId,
+    last_position: Option<usize>,
+    bank_held: bool,
+}
+
+impl Selector {
+    pub fn new(selected: CassetteId) -> Self {
+        Self {
+            selected,
+            last_position: None,
+            bank_held: false,
+        }
+    }
+
+    pub fn selected(&self) -> CassetteId {
+        self.selected
+    }
+
+    /// Update with the current position of the switch and the state of the
+    /// bank button. Returns the newly selected cassette, if it changed.
+    ///
+    /// Only movements of the switch are taken into account. The first
+    /// reading and the readings following a bank change therefore do not
+    /// select a cassette by themselves.
+    pub fn update(&mut self, position: usize, bank_held: bool) -> Option<CassetteId> {
+        self.bank_held = bank_held;
+        let moved = self
+            .last_position
+            .is_some_and(|last_position| last_position != position);
+        self.last_position = Some(position);
+        if !moved {
+            return None;
+        }
+
+        let selected = if bank_held {
+            CassetteId::from_bank(position % BANKS, position)
+        } else {
+            CassetteId::from_bank(self.selected.bank(), position)
+        };
+        if selected == self.selected {
+            return None;
+        }
+        self.selected = selected;
+        Some(selected)
+    }
+
+    /// LEDs indicating the selected bank, shown while the bank button is
+    /// held. `None` otherwise, leaving the display to other indicators.
+    pub fn bank_leds(&self) -> Option<[bool; BANKS]> {
+        if !self.bank_held {
+            return None;
+        }
+        let mut leds = [false; BANKS];
+        leds[self.selected.bank()] = true;
+        Some(leds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn turning_the_switch_selects_cassette_within_bank() {
+        let mut selector = Selector::new(CassetteId::from_bank(2, 0));
+
+        assert_eq!(selector.update(0, false), None);
+        assert_eq!(selector.update(3, false), Some(CassetteId::from_bank(2, 3)));
+        assert_eq!(selector.update(3, false), None);
+    }
+
+    #[test]
+    fn turning_the_switch_while_holding_the_button_selects_bank() {
+        let mut selector = Selector::new(CassetteId::from_bank(0, 5));
+        selector.update(5, false);
+
+        assert_eq!(selector.update(6, true), Some(CassetteId::from_bank(6, 6)));
+        assert_eq!(selector.update(1, true), Some(CassetteId::from_bank(1, 1)));
+    }
+
+    #[test]
+    fn releasing_the_button_does_not_select_cassette() {
+        let mut selector = Selector::new(CassetteId::from_bank(0, 5));
+        selector.update(5, false);
+        selector.update(2, true);
+
+        assert_eq!(selector.update(2, false), None);
+        assert_eq!(selector.selected(), CassetteId::from_bank(2, 2));
+        assert_eq!(selector.update(4, false), Some(CassetteId::from_bank(2, 4)));
+    }
+
+    #[test]
+    fn bank_is_displayed_only_while_the_button_is_held() {
+        let mut selector = Selector::new(CassetteId::from_bank(3, 0));
+
+        selector.update(0, false);
+        assert_eq!(selector.bank_leds(), None);
+
+        selector.update(0, true);
+        let mut expected = [false; BANKS];
+        expected[3] = true;
+        assert_eq!(selector.bank_leds(), Some(expected));
+    }
+}
@@ -0,0 +1,512 @@
+//! Mix and effect settings stored with each cassette.
+//!
+//! Settings are persisted next to the audio of the cassette and recovered
+//! together with it when the cassette gets opened. Since the physical knobs
+//! stay where they were, recovered values are reconciled with them, see
+//! `Pickup`.
+
+use crc::{Crc, CRC_32_ISO_HDLC};
+
+use super::cassette::{CassetteId, TRACKS};
+use super::storage::{Error, File, Storage};
+
+const MAGIC: [u8; 4] = *b"TBST";
+const VERSION: u8 = 1;
+const TRACK_SIZE: usize = 13;
+pub(crate) const SETTINGS_SIZE: usize = 8 + TRACKS * TRACK_SIZE + 4 + 4;
+
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// Settings of the mix recovered with a cassette.
+///
+/// All continuous values are positions of their knobs, ranging from 0.0
+/// to 1.0.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Settings {
+    pub tracks: [TrackSettings; TRACKS],
+    pub pitch: f32,
+}
+
+/// Settings of a single track.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct TrackSettings {
+    pub volume: f32,
+    pub pan: f32,
+    pub effect: u8,
+    pub strength: f32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            tracks: [TrackSettings::default(); TRACKS],
+            pitch: 0.5,
+        }
+    }
+}
+
+impl Default for TrackSettings {
+    fn default() -> Self {
+        Self {
+            volume: 1.0,
+            pan: 0.5,
+            effect: 0,
+            strength: 0.0,
+        }
+    }
+}
+
+impl Settings {
+    /// Recover settings stored with the cassette. Defaults are used for
+    /// cassettes with no or corrupted settings.
+    pub fn load(storage: &mut impl Storage, cassette_id: CassetteId) -> Self {
+        let mut bytes = [0; SETTINGS_SIZE];
+        storage
+            .read(File::Settings(cassette_id), 0, &mut bytes)
+            .ok()
+            .and_then(|_| Settings::from_bytes(&bytes))
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, storage: &mut impl Storage, cassette_id: CassetteId) -> Result<(), Error> {
+        storage.write(File::Settings(cassette_id), 0, &self.to_bytes())
+    }
+
+    pub(crate) fn to_bytes(self) -> [u8; SETTINGS_SIZE] {
+        let mut bytes = [0; SETTINGS_SIZE];
+        bytes[..4].copy_from_slice(&MAGIC);
+        bytes[4] = VERSION;
+        for (track, chunk) in self
+            .tracks
+            .iter()
+            .zip(bytes[8..8 + TRACKS * TRACK_SIZE].chunks_exact_mut(TRACK_SIZE))
+        {
+            chunk[..4].copy_from_slice(&track.volume.to_le_bytes());
+            chunk[4..8].copy_from_slice(&track.pan.to_le_bytes());
+            chunk[8] = track.effect;
+            chunk[9..].copy_from_slice(&track.strength.to_le_bytes());
+        }
+        let pitch_start = SETTINGS_SIZE - 8;
+        bytes[pitch_start..pitch_start + 4].copy_from_slice(&self.pitch.to_le_bytes());
+        let checksum = CRC.checksum(&bytes[..SETTINGS_SIZE - 4]);
+        bytes[SETTINGS_SIZE - 4..].copy_from_slice(&checksum.to_le_bytes());
+        bytes
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8; SETTINGS_SIZE]) -> Option<Self> {
+        let checksum = u32::from_le_bytes(bytes[SETTINGS_SIZE - 4..].try_into().unwrap());
+        if bytes[..4] != MAGIC
+            || bytes[4] != VERSION
+            || CRC.checksum(&bytes[..SETTINGS_SIZE - 4]) != checksum
+        {
+            return None;
+        }
+
+        let mut settings = Settings::default();
+        for (track, chunk) in settings
+            .tracks
+            .iter_mut()
+            .zip(bytes[8..8 + TRACKS * TRACK_SIZE].chunks_exact(TRACK_SIZE))
+        {
+            track.volume = f32::from_le_bytes(chunk[..4].try_into().unwrap());
+            track.pan = f32::from_le_bytes(chunk[4..8].try_into().unwrap());
+            track.effect = chunk[8];
+            track.strength = f32::from_le_bytes(chunk[9..].try_into().unwrap());
+        }
+        let pitch_start = SETTINGS_SIZE - 8;
+        settings.pitch =
+            f32::from_le_bytes(bytes[pitch_start..pitch_start + 4].try_into().unwrap());
+        Some(settings)
+    }
+}
+
+/// How a knob takes over a parameter recovered from settings.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PickupMode {
+    /// The parameter jumps to the knob right away.
+    Jump,
+    /// The recovered value is kept until the knob passes through it.
+    PassThrough,
+    /// The parameter follows the knob in proportion to the range left in
+    /// the direction it is turned, meeting it at the end of the range at
+    /// the latest.
+    Scaled,
+}
+
+/// Pickup modes of each kind of parameter.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PickupModes {
+    pub volume: PickupMode,
+    pub pan: PickupMode,
+    pub strength: PickupMode,
+    pub pitch: PickupMode,
+}
+
+impl Default for PickupModes {
+    fn default() -> Self {
+        Self {
+            volume: PickupMode::PassThrough,
+            pan: PickupMode::PassThrough,
+            strength: PickupMode::PassThrough,
+            pitch: PickupMode::PassThrough,
+        }
+    }
+}
+
+impl PickupMode {
+    pub(crate) fn to_u8(self) -> u8 {
+        match self {
+            PickupMode::Jump => 0,
+            PickupMode::PassThrough => 1,
+            PickupMode::Scaled => 2,
+        }
+    }
+
+    pub(crate) fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(PickupMode::Jump),
+            1 => Some(PickupMode::PassThrough),
+            2 => Some(PickupMode::Scaled),
+            _ => None,
+        }
+    }
+}
+
+/// Parameter recovered from settings, taken over by its knob as configured
+/// by its `PickupMode`.
+///
+/// This prevents jumps of volume or effect strength right after a cassette
+/// is switched.
+#[derive(Clone, Copy, Debug)]
+pub struct Pickup {
+    mode: PickupMode,
+    value: f32,
+    last_knob: Option<f32>,
+    caught: bool,
+}
+
+/// Distance from the recovered value at which the knob is considered to
+/// match it even without crossing it.
+const PICKUP_TOLERANCE: f32 = 0.02;
+
+impl Pickup {
+    /// Keep the given value until the knob passes through it.
+    pub fn new(value: f32) -> Self {
+        Self::with_mode(value, PickupMode::PassThrough)
+    }
+
+    pub fn with_mode(value: f32, mode: PickupMode) -> Self {
+        Self {
+            mode,
+            value,
+            last_knob: None,
+            caught: mode == PickupMode::Jump,
+        }
+    }
+
+    /// Update with the current position of the knob and return the value
+    /// of the parameter.
+    pub fn update(&mut self, knob: f32) -> f32 {
+        if !self.caught {
+            let previous = self.value;
+            if let (PickupMode::Scaled, Some(last_knob)) = (self.mode, self.last_knob) {
+                let end = if knob > last_knob { 1.0 } else { 0.0 };
+                let remaining = end - last_knob;
+                if remaining.abs() > f32::EPSILON {
+                    self.value += (knob - last_knob) * (end - self.value) / remaining;
+                }
+            }
+            let crossed = self
+                .last_knob
+                .is_some_and(|last_knob| (last_knob - previous) * (knob - self.value) <= 0.0);
+            let close = (knob - self.value).abs() <= PICKUP_TOLERANCE;
+            self.caught = crossed || close;
+            self.last_knob = Some(knob);
+        }
+        if self.caught {
+            self.value = knob;
+        }
+        self.value
+    }
+
+    /// Hand the parameter over to the knob on the next update.
+    pub fn release(&mut self) {
+        self.caught = true;
+    }
+
+    pub fn is_caught(&self) -> bool {
+        self.caught
+    }
+}
+
+/// Settings recovered from a cassette, reconciled with the physical knobs.
+#[derive(Debug)]
+pub struct SettingsPickup {
+    settings: Settings,
+    volume: [Pickup; TRACKS],
+    pan: [Pickup; TRACKS],
+    strength: [Pickup; TRACKS],
+    pitch: Pickup,
+}
+
+impl SettingsPickup {
+    pub fn new(settings: Settings, modes: &PickupModes) -> Self {
+        Self {
+            settings,
+            volume: settings
+                .tracks
+                .map(|track| Pickup::with_mode(track.volume, modes.volume)),
+            pan: settings
+                .tracks
+                .map(|track| Pickup::with_mode(track.pan, modes.pan)),
+            strength: settings
+                .tracks
+                .map(|track| Pickup::with_mode(track.strength, modes.strength)),
+            pitch: Pickup::with_mode(settings.pitch, modes.pitch),
+        }
+    }
+
+    /// Update with current positions of the knobs and return the settings
+    /// to be used.
+    ///
+    /// Effect selection is not controlled by a knob directly, so it is
+    /// always kept as recovered or as set through `select_effect`.
+    pub fn update(&mut self, knobs: &Settings) -> Settings {
+        for (i, track) in self.settings.tracks.iter_mut().enumerate() {
+            track.volume = self.volume[i].update(knobs.tracks[i].volume);
+            track.pan = self.pan[i].update(knobs.tracks[i].pan);
+            track.strength = self.strength[i].update(knobs.tracks[i].strength);
+        }
+        self.settings.pitch = self.pitch.update(knobs.pitch);
+        self.settings
+    }
+
+    /// Select the effect of the track. Its strength is taken over by the
+    /// knob right away, the recovered strength belonged to another effect.
+    pub fn select_effect(&mut self, track: usize, effect: u8) {
+        self.settings.tracks[track].effect = effect;
+        self.strength[track].release();
+    }
+
+    pub fn settings(&self) -> Settings {
+        self.settings
+    }
+
+    /// Tracks with a parameter still waiting for its knob to pick it up.
+    pub fn waiting_tracks(&self) -> [bool; TRACKS] {
+        core::array::from_fn(|i| {
+            !(self.volume[i].is_caught() && self.pan[i].is_caught() && self.strength[i].is_caught())
+        })
+    }
+
+    pub fn is_pitch_waiting(&self) -> bool {
+        !self.pitch.is_caught()
+    }
+}
+
+/// Delays persisting of a value until it stops changing.
+///
+/// Knobs move continuously, writing each of their changes would wear the
+/// medium out and block the storage routine.
+pub struct Debounce<T> {
+    pending: Option<T>,
+    remaining: u32,
+    delay: u32,
+}
+
+impl<T> Debounce<T> {
+    /// The value is released after `delay` ticks with no change.
+    pub fn new(delay: u32) -> Self {
+        Self {
+            pending: None,
+            remaining: 0,
+            delay,
+        }
+    }
+
+    pub fn set(&mut self, value: T) {
+        self.pending = Some(value);
+        self.remaining = self.delay;
+    }
+
+    /// Advance the time, returns the value once it is due to be persisted.
+    pub fn tick(&mut self) -> Option<T> {
+        self.pending.as_ref()?;
+        self.remaining = self.remaining.saturating_sub(1);
+        if self.remaining == 0 {
+            self.pending.take()
+        } else {
+            None
+        }
+    }
+
+    /// Return the pending value right away, e.g. before the cassette gets
+    /// switched.
+    pub fn flush(&mut self) -> Option<T> {
+        self.pending.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::storage::memory::MemoryStorage;
+    use super::*;
+
+    fn custom_settings() -> Settings {
+        let mut settings = Settings::default();
+        for (i, track) in settings.tracks.iter_mut().enumerate() {
+            track.volume = 0.1 * i as f32;
+            track.pan = 0.2 + 0.1 * i as f32;
+            track.effect = i as u8;
+            track.strength = 0.9 - 0.1 * i as f32;
+        }
+        settings.pitch = 0.75;
+        settings
+    }
+
+    #[test]
+    fn settings_survive_serialization() {
+        let settings = custom_settings();
+        assert_eq!(Settings::from_bytes(&settings.to_bytes()), Some(settings));
+    }
+
+    #[test]
+    fn reject_corrupted_settings() {
+        let mut bytes = custom_settings().to_bytes();
+        bytes[20] ^= 0x01;
+        assert_eq!(Settings::from_bytes(&bytes), None);
+    }
+
+    #[test]
+    fn pickup_keeps_value_until_the_knob_crosses_it() {
+        let mut pickup = Pickup::new(0.5);
+
+        assert_eq!(pickup.update(0.1), 0.5);
+        assert_eq!(pickup.update(0.3), 0.5);
+        assert!(!pickup.is_caught());
+
+        assert_eq!(pickup.update(0.6), 0.6);
+        assert!(pickup.is_caught());
+        assert_eq!(pickup.update(0.2), 0.2);
+    }
+
+    #[test]
+    fn pickup_is_caught_when_the_knob_already_matches() {
+        let mut pickup = Pickup::new(0.5);
+        assert_eq!(pickup.update(0.51), 0.51);
+        assert!(pickup.is_caught());
+    }
+
+    #[test]
+    fn jump_takes_the_knob_right_away() {
+        let mut pickup = Pickup::with_mode(0.5, PickupMode::Jump);
+        assert!(pickup.is_caught());
+        assert_eq!(pickup.update(0.1), 0.1);
+    }
+
+    #[test]
+    fn scaled_pickup_catches_up_with_the_knob() {
+        let mut pickup = Pickup::with_mode(0.8, PickupMode::Scaled);
+        assert_eq!(pickup.update(0.2), 0.8);
+
+        // Half of the remaining range up moves the value half way up too.
+        let value = pickup.update(0.6);
+        assert!((value - 0.9).abs() < 0.001);
+        assert!(!pickup.is_caught());
+
+        // Turning down maps the value's range to the knob's range below.
+        let value = pickup.update(0.3);
+        assert!((value - 0.45).abs() < 0.001);
+
+        assert_eq!(pickup.update(0.0), 0.0);
+        assert!(pickup.is_caught());
+        assert_eq!(pickup.update(0.4), 0.4);
+    }
+
+    #[test]
+    fn settings_pickup_reconciles_each_parameter_independently() {
+        let mut pickup = SettingsPickup::new(custom_settings(), &PickupModes::default());
+        let mut knobs = custom_settings();
+        knobs.tracks[0].volume = 0.8;
+        knobs.tracks[1].pan = 0.9;
+        knobs.tracks[2].effect = 7;
+
+        let settings = pickup.update(&knobs);
+        assert_eq!(
+            settings.tracks[0].volume,
+            custom_settings().tracks[0].volume
+        );
+        assert_eq!(settings.tracks[1].pan, custom_settings().tracks[1].pan);
+        assert_eq!(
+            settings.tracks[2].effect,
+            custom_settings().tracks[2].effect
+        );
+        assert_eq!(settings.tracks[3], custom_settings().tracks[3]);
+        assert_eq!(pickup.waiting_tracks(), [true, true, false, false]);
+    }
+
+    #[test]
+    fn settings_pickup_applies_modes_per_parameter() {
+        let modes = PickupModes {
+            volume: PickupMode::Jump,
+            ..PickupModes::default()
+        };
+        let mut pickup = SettingsPickup::new(custom_settings(), &modes);
+        let mut knobs = custom_settings();
+        knobs.tracks[1].volume = 0.9;
+        knobs.tracks[1].pan = 0.9;
+
+        let settings = pickup.update(&knobs);
+        assert_eq!(settings.tracks[1].volume, 0.9);
+        assert_eq!(settings.tracks[1].pan, custom_settings().tracks[1].pan);
+    }
+
+    #[test]
+    fn selected_effect_reflects_the_knob_immediately() {
+        let mut pickup = SettingsPickup::new(custom_settings(), &PickupModes::default());
+        let mut knobs = custom_settings();
+        knobs.tracks[0].strength = 0.1;
+        assert_eq!(
+            pickup.update(&knobs).tracks[0].strength,
+            custom_settings().tracks[0].strength
+        );
+
+        pickup.select_effect(0, 3);
+        let settings = pickup.update(&knobs);
+        assert_eq!(settings.tracks[0].effect, 3);
+        assert_eq!(settings.tracks[0].strength, 0.1);
+    }
+
+    #[test]
+    fn settings_are_loaded_as_saved_with_their_cassette() {
+        let mut storage = MemoryStorage::default();
+        let (saved, other) = (CassetteId::new(3), CassetteId::new(4));
+
+        custom_settings().save(&mut storage, saved).unwrap();
+
+        assert_eq!(Settings::load(&mut storage, saved), custom_settings());
+        assert_eq!(Settings::load(&mut storage, other), Settings::default());
+    }
+
+    #[test]
+    fn debounce_releases_value_after_it_settles() {
+        let mut debounce = Debounce::new(3);
+
+        debounce.set(1);
+        assert_eq!(debounce.tick(), None);
+        debounce.set(2);
+        assert_eq!(debounce.tick(), None);
+        assert_eq!(debounce.tick(), None);
+        assert_eq!(debounce.tick(), Some(2));
+        assert_eq!(debounce.tick(), None);
+    }
+
+    #[test]
+    fn flush_releases_pending_value_immediately() {
+        let mut debounce = Debounce::new(100);
+
+        debounce.set(1);
+        assert_eq!(debounce.flush(), Some(1));
+        assert_eq!(debounce.tick(), None);
+    }
+}
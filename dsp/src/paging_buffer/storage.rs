@@ -0,0 +1,153 @@
+//! Abstraction of the persistent medium.
+
+use super::cassette::CassetteId;
+
+/// Files kept on the medium.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum File {
+    /// Pages recorded on the given cassette.
+    Audio(CassetteId),
+    /// Description of the given cassette, e.g. its sample format.
+    Metadata(CassetteId),
+    /// Mix and effect settings of the given cassette.
+    Settings(CassetteId),
+    /// Global configuration of the module.
+    Config,
+    /// Marker of a factory reset in progress.
+    ResetJournal,
+}
+
+/// Failures reported by the medium.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Error {
+    /// The requested range does not fit into the file.
+    OutOfBounds,
+    /// The medium failed to serve the request.
+    Io,
+    /// The stored data do not match their checksum.
+    Corrupted,
+    /// The medium was not formatted yet.
+    Unformatted,
+    /// The medium holds data that do not fit into the current layout.
+    Incompatible,
+}
+
+/// Persistent medium, e.g. a file system on an SD card.
+///
+/// Implementations may block. They must be only accessed from the storage
+/// routine, never from the audio loop.
+///
+/// Files that were never written are treated as empty. Removing them is
+/// not an error.
+pub trait Storage {
+    fn read(&mut self, file: File, offset: usize, buffer: &mut [u8]) -> Result<(), Error>;
+    fn write(&mut self, file: File, offset: usize, data: &[u8]) -> Result<(), Error>;
+    fn remove(&mut self, file: File) -> Result<(), Error>;
+    fn size(&mut self, file: File) -> usize;
+    fn capacity(&mut self) -> usize;
+}
+
+#[cfg(test)]
+pub(crate) mod memory {
+    use super::*;
+
+    /// In-memory medium to be used in tests.
+    pub(crate) struct MemoryStorage {
+        files: Vec<(File, Vec<u8>)>,
+        capacity: usize,
+        power: Option<usize>,
+    }
+
+    impl Default for MemoryStorage {
+        fn default() -> Self {
+            Self::with_capacity(32 * 1024 * 1024)
+        }
+    }
+
+    impl MemoryStorage {
+        pub(crate) fn with_capacity(capacity: usize) -> Self {
+            Self {
+                files: Vec::new(),
+                capacity,
+                power: None,
+            }
+        }
+
+        /// Simulate power loss after the given number of modifications.
+        pub(crate) fn cut_power_after(&mut self, modifications: usize) {
+            self.power = Some(modifications);
+        }
+
+        pub(crate) fn restore_power(&mut self) {
+            self.power = None;
+        }
+
+        pub(crate) fn exists(&self, file: File) -> bool {
+            self.files.iter().any(|(f, _)| *f == file)
+        }
+
+        fn consume_power(&mut self) -> Result<(), Error> {
+            match self.power.as_mut() {
+                Some(0) => Err(Error::Io),
+                Some(left) => {
+                    *left -= 1;
+                    Ok(())
+                }
+                None => Ok(()),
+            }
+        }
+
+        pub(crate) fn file_mut(&mut self, file: File) -> &mut Vec<u8> {
+            let position = match self.files.iter().position(|(f, _)| *f == file) {
+                Some(position) => position,
+                None => {
+                    self.files.push((file, Vec::new()));
+                    self.files.len() - 1
+                }
+            };
+            &mut self.files[position].1
+        }
+    }
+
+    impl Storage for MemoryStorage {
+        fn read(&mut self, file: File, offset: usize, buffer: &mut [u8]) -> Result<(), Error> {
+            let data = self
+                .files
+                .iter()
+                .find(|(f, _)| *f == file)
+                .map_or(&[][..], |(_, data)| &data[..]);
+            if offset + buffer.len() > data.len() {
+                return Err(Error::OutOfBounds);
+            }
+            buffer.copy_from_slice(&data[offset..offset + buffer.len()]);
+            Ok(())
+        }
+
+        fn write(&mut self, file: File, offset: usize, data: &[u8]) -> Result<(), Error> {
+            self.consume_power()?;
+            let stored = self.file_mut(file);
+            if stored.len() < offset + data.len() {
+                stored.resize(offset + data.len(), 0);
+            }
+            stored[offset..offset + data.len()].copy_from_slice(data);
+            Ok(())
+        }
+
+        fn remove(&mut self, file: File) -> Result<(), Error> {
+            self.consume_power()?;
+            self.files.retain(|(f, _)| *f != file);
+            Ok(())
+        }
+
+        fn size(&mut self, file: File) -> usize {
+            self.files
+                .iter()
+                .find(|(f, _)| *f == file)
+                .map_or(0, |(_, data)| data.len())
+        }
+
+        fn capacity(&mut self) -> usize {
+            self.capacity
+        }
+    }
+}
@@ -0,0 +1,1306 @@
+//! Persistence of pages, running in the storage routine.
+//!
+//! Each page is stored as a record of samples of all its tracks followed by
+//! a CRC32 of them. The checksum is verified on every load, so a misbehaving SD card
+//! results in silence rather than in full-scale noise.
+//!
+//! Samples are encoded in the format recorded in the cassette's metadata.
+//! New cassettes use the format the store was configured with, cassettes
+//! recorded before metadata were introduced are read as mono `f32`. Only the
+//! first track of mono cassettes gets persisted.
+//!
+//! Space occupied by each cassette is cached, so it can be frequently polled
+//! by the display without querying the medium.
+//!
+//! Mix settings of a cassette are read when the cassette is opened and passed
+//! with it to the `Manager`, so audio and its settings always change together.
+//!
+//! Cassettes can be copied, and their tracks bounced into one, in the
+//! background. Bounced tracks are played through the track mixer, with
+//! their volume, pan and effects, and folded down into the mono target.
+//! Such a job advances by a single page after all the requests of the
+//! `Manager` are served, so it never delays the playback. Pages recorded on
+//! the source after they were copied are not reflected in the copy.
+//! Switching to the cassette a job writes cancels the job. A copy over the
+//! cassette in use by the `Manager` is refused, a bounce of it waits until
+//! the `Manager` switches to the cassette again, backing all its pages up.
+//! The bounce is then finished before the cassette is reopened.
+
+use core::f32::consts::FRAC_1_SQRT_2;
+
+use crc::{Crc, CRC_32_ISO_HDLC};
+use heapless::spsc::{Consumer, Producer};
+
+use crate::effect::Rack;
+use crate::mixer::{Mixer, CHANNELS};
+
+use super::cassette::{Cassette, CassetteId, CASSETTES, TRACKS};
+use super::format::{Dither, SampleFormat, MAX_SAMPLE_SIZE};
+use super::metadata::{Metadata, METADATA_SIZE};
+use super::page::{Page, PageId, PageRequest, PAGE_LENGTH};
+use super::pool::{Handle, Pool};
+use super::settings::{Settings, TrackSettings};
+use super::storage::{Error, File, Storage};
+use super::usage::{Budget, Usage};
+
+const CHECKSUM_SIZE: usize = 4;
+const MAX_RECORD_SIZE: usize = TRACKS * PAGE_LENGTH * MAX_SAMPLE_SIZE + CHECKSUM_SIZE;
+
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+pub(crate) fn record_size(metadata: Metadata) -> usize {
+    data_size(metadata) + CHECKSUM_SIZE
+}
+
+fn data_size(metadata: Metadata) -> usize {
+    metadata.tracks * PAGE_LENGTH * metadata.format.sample_size()
+}
+
+/// Serves page requests of the `Manager` using the given `Storage`.
+pub(crate) struct Store<S> {
+    storage: S,
+    format: SampleFormat,
+    opened: Option<Opened>,
+    dither: Dither,
+    record: [u8; MAX_RECORD_SIZE],
+    budget: Budget,
+    capacity: Option<usize>,
+    occupied: [Option<Occupied>; CASSETTES],
+    active: Option<CassetteId>,
+    job: Option<Job>,
+    job_status: JobStatus,
+    render: Render,
+    pub diagnostics: Diagnostics,
+}
+
+/// Cached sizes of files of a cassette.
+#[derive(Clone, Copy)]
+struct Occupied {
+    audio: usize,
+    metadata: usize,
+}
+
+/// Ends of the queues connecting the storage routine with the `Manager`.
+pub(crate) struct Queues<'a> {
+    pub save_request_consumer: Consumer<'a, Handle, 4>,
+    pub save_request_first_page_consumer: Consumer<'a, Page, 4>,
+    pub load_request_consumer: Consumer<'a, PageRequest, 4>,
+    pub load_response_producer: Producer<'a, Handle, 4>,
+    pub switch_request_consumer: Consumer<'a, CassetteId, 4>,
+    pub switch_response_producer: Producer<'a, Cassette, 4>,
+    pub settings_save_request_consumer: Consumer<'a, (CassetteId, Settings), 4>,
+}
+
+/// Cached metadata of the last accessed cassette.
+#[derive(Clone, Copy)]
+struct Opened {
+    id: CassetteId,
+    metadata: Metadata,
+    persisted: bool,
+}
+
+/// Background job in progress, writing to `cassette`.
+#[derive(Clone, Copy)]
+struct Job {
+    kind: JobKind,
+    cassette: CassetteId,
+    pages: usize,
+    next_page: usize,
+    prepared: bool,
+    /// The cassette is in use, the job waits for its pages to be backed up.
+    awaits_backup: bool,
+}
+
+#[derive(Clone, Copy)]
+enum JobKind {
+    /// Copy of the `source` cassette.
+    Copy {
+        source: CassetteId,
+        metadata: Metadata,
+    },
+    /// Mix of tracks of the cassette rendered into one of its tracks.
+    Bounce(Bounce),
+}
+
+/// Parameters of a bounce of tracks.
+#[derive(Clone, Copy)]
+struct Bounce {
+    sources: [bool; TRACKS],
+    target_track: usize,
+}
+
+/// Frames of a page rendered at once by a bounce.
+const RENDER_BLOCK: usize = 32;
+
+/// Track mixer the bounced tracks are played through. Effects carry their
+/// state from one page to the next.
+#[derive(Default)]
+struct Render {
+    rack: Rack,
+    mixer: Mixer,
+}
+
+impl Render {
+    /// Tracks other than the sources are muted.
+    fn new(settings: &Settings, sources: [bool; TRACKS]) -> Self {
+        let mut mixer = Mixer::new();
+        for (track, settings) in settings.tracks.iter().enumerate() {
+            let volume = if sources[track] { settings.volume } else { 0.0 };
+            mixer.set(track, volume, settings.pan);
+        }
+        mixer.settle();
+        Self {
+            rack: Rack::with_settings(settings),
+            mixer,
+        }
+    }
+
+    /// Mix the sources into the target track and free the remaining
+    /// sources.
+    ///
+    /// The stereo mix is folded down so that centered sources keep their
+    /// loudness once the target is played centered at full volume.
+    fn render(&mut self, bounce: &Bounce, page: &mut Page) {
+        for start in (0..PAGE_LENGTH).step_by(RENDER_BLOCK) {
+            let length = RENDER_BLOCK.min(PAGE_LENGTH - start);
+            let mut frames = [[0.0; TRACKS]; RENDER_BLOCK];
+            let frames = &mut frames[..length];
+            for (i, frame) in frames.iter_mut().enumerate() {
+                for (sample, track) in frame.iter_mut().zip(page.data.iter()) {
+                    *sample = track[start + i];
+                }
+            }
+            self.rack.process(frames);
+            let mut mix = [[0.0; CHANNELS]; RENDER_BLOCK];
+            let mix = &mut mix[..length];
+            self.mixer.sum(frames, mix);
+
+            let target = &mut page.data[bounce.target_track][start..start + length];
+            for (x, [left, right]) in target.iter_mut().zip(mix.iter()) {
+                *x = (left + right) * FRAC_1_SQRT_2;
+            }
+        }
+        for (i, track) in page.data.iter_mut().enumerate() {
+            if bounce.sources[i] && i != bounce.target_track {
+                track.fill(0.0);
+            }
+        }
+    }
+}
+
+/// Progress of the last requested background job.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum JobStatus {
+    Idle,
+    Running {
+        cassette: CassetteId,
+        done: usize,
+        total: usize,
+    },
+    Finished(CassetteId),
+    Cancelled(CassetteId),
+    Failed(CassetteId),
+}
+
+/// Reasons for a background job to be refused.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum JobError {
+    /// Another job is still in progress.
+    Busy,
+    SameCassette,
+    /// The cassette is used by the `Manager`, its pages could be overwritten.
+    CassetteInUse,
+    /// The cassette does not store the requested track.
+    NoSuchTrack,
+}
+
+/// Counters of failures observed while accessing the storage.
+#[derive(Default, Clone, Copy, PartialEq, Debug)]
+pub struct Diagnostics {
+    /// Loaded pages that did not match their checksum.
+    pub corrupted_pages: u32,
+    /// Pages that could not be read from the medium.
+    pub failed_loads: u32,
+    pub failed_saves: u32,
+}
+
+/// Result of integrity check of a whole cassette.
+#[derive(Default, Clone, Copy, PartialEq, Debug)]
+pub struct Scan {
+    pub pages: usize,
+    pub corrupted_pages: usize,
+    pub first_corrupted_page: Option<usize>,
+}
+
+impl Scan {
+    pub fn is_intact(&self) -> bool {
+        self.corrupted_pages == 0
+    }
+}
+
+impl<S: Storage> Store<S> {
+    /// Initialize the store, new cassettes will be recorded in `format`.
+    pub(crate) fn new(storage: S, format: SampleFormat) -> Self {
+        Self {
+            storage,
+            format,
+            opened: None,
+            dither: Dither::new(),
+            record: [0; MAX_RECORD_SIZE],
+            budget: Budget::Card,
+            capacity: None,
+            occupied: [None; CASSETTES],
+            active: None,
+            job: None,
+            job_status: JobStatus::Idle,
+            render: Render::default(),
+            diagnostics: Diagnostics::default(),
+        }
+    }
+
+    pub(crate) fn set_budget(&mut self, budget: Budget) {
+        self.budget = budget;
+    }
+
+    /// Serve all pending requests of the `Manager`.
+    ///
+    /// Requests to switch a cassette act as a fence. All the saves queued
+    /// before them are finished first and load requests of the outgoing
+    /// cassette are dropped. Only then is the new cassette opened, after a
+    /// bounce waiting for its backup is finished.
+    pub(crate) fn process(&mut self, pool: &mut Pool, queues: &mut Queues) {
+        while let Some(cassette_id) = queues.switch_request_consumer.dequeue() {
+            self.process_save_requests(pool, queues);
+            while queues.load_request_consumer.dequeue().is_some() {}
+            if let Some(job) = self.job.as_mut() {
+                let awaited_backup = job.awaits_backup;
+                job.awaits_backup = false;
+                if job.cassette == cassette_id {
+                    if awaited_backup {
+                        while self.job.is_some() {
+                            self.step_job();
+                        }
+                    } else {
+                        self.cancel_job();
+                    }
+                }
+            }
+            self.active = Some(cassette_id);
+            let cassette = self.open_cassette(cassette_id);
+            queues
+                .switch_response_producer
+                .enqueue(cassette)
+                .ok()
+                .unwrap();
+        }
+
+        self.process_save_requests(pool, queues);
+
+        while let Some(request) = queues.load_request_consumer.dequeue() {
+            let handle = self.handle_request(request, pool);
+            queues.load_response_producer.enqueue(handle).ok().unwrap();
+        }
+
+        self.step_job();
+    }
+
+    /// Start copying audio and settings of one cassette to another.
+    ///
+    /// Anything stored on the target is replaced.
+    pub(crate) fn start_copy(
+        &mut self,
+        source: CassetteId,
+        target: CassetteId,
+    ) -> Result<(), JobError> {
+        if self.job.is_some() {
+            return Err(JobError::Busy);
+        }
+        if source == target {
+            return Err(JobError::SameCassette);
+        }
+        if self.active == Some(target) {
+            return Err(JobError::CassetteInUse);
+        }
+
+        let metadata = self.metadata(source);
+        let pages = self.storage.size(File::Audio(source)) / record_size(metadata);
+        self.start_job(Job {
+            kind: JobKind::Copy { source, metadata },
+            cassette: target,
+            pages,
+            next_page: 0,
+            prepared: false,
+            awaits_backup: false,
+        });
+        Ok(())
+    }
+
+    /// Start rendering the `sources` tracks of the cassette into its
+    /// `target_track`.
+    ///
+    /// Each source is played through its effect and mixed in with the
+    /// volume and pan stored in the settings of the cassette. The target
+    /// track ends up holding the mix, its volume, pan and effect reset once
+    /// finished, while the other sources are silenced to be recorded over.
+    ///
+    /// A bounce of the cassette in use starts only once the `Manager`
+    /// switches to it again, see the module documentation.
+    pub(crate) fn start_bounce(
+        &mut self,
+        cassette_id: CassetteId,
+        sources: [bool; TRACKS],
+        target_track: usize,
+    ) -> Result<(), JobError> {
+        if self.job.is_some() {
+            return Err(JobError::Busy);
+        }
+        let metadata = self.metadata(cassette_id);
+        if target_track >= metadata.tracks {
+            return Err(JobError::NoSuchTrack);
+        }
+
+        let settings = self.settings(cassette_id);
+        self.render = Render::new(&settings, sources);
+        let pages = self.storage.size(File::Audio(cassette_id)) / record_size(metadata);
+        self.start_job(Job {
+            kind: JobKind::Bounce(Bounce {
+                sources,
+                target_track,
+            }),
+            cassette: cassette_id,
+            pages,
+            next_page: 0,
+            prepared: true,
+            awaits_backup: self.active == Some(cassette_id),
+        });
+        Ok(())
+    }
+
+    fn start_job(&mut self, job: Job) {
+        self.job = Some(job);
+        self.job_status = JobStatus::Running {
+            cassette: job.cassette,
+            done: 0,
+            total: job.pages,
+        };
+    }
+
+    /// Stop the job in progress. The target of a copy is left blank, pages
+    /// already bounced are kept.
+    pub(crate) fn cancel_job(&mut self) {
+        let Some(job) = self.job.take() else {
+            return;
+        };
+        let result = match job.kind {
+            JobKind::Copy { .. } => self.wipe(job.cassette),
+            JobKind::Bounce(_) => Ok(()),
+        };
+        self.job_status = match result {
+            Ok(()) => JobStatus::Cancelled(job.cassette),
+            Err(_) => JobStatus::Failed(job.cassette),
+        };
+    }
+
+    pub(crate) fn job_status(&self) -> JobStatus {
+        self.job_status
+    }
+
+    fn step_job(&mut self) {
+        let Some(mut job) = self.job.filter(|job| !job.awaits_backup) else {
+            return;
+        };
+
+        let result = if !job.prepared {
+            job.prepared = true;
+            self.prepare_job(&job)
+        } else {
+            let result = match job.kind {
+                JobKind::Copy { source, metadata } => self.copy_page(&job, source, metadata),
+                JobKind::Bounce(bounce) => self.bounce_page(&job, &bounce),
+            };
+            job.next_page += 1;
+            result
+        };
+
+        if result.is_err() {
+            #[cfg(feature = "defmt")]
+            defmt::error!("Background job on cassette {} failed", job.cassette.index());
+            self.job = None;
+            self.job_status = JobStatus::Failed(job.cassette);
+        } else if job.prepared && job.next_page >= job.pages {
+            self.job = None;
+            self.job_status = match self.finish_job(&job) {
+                Ok(()) => JobStatus::Finished(job.cassette),
+                Err(_) => JobStatus::Failed(job.cassette),
+            };
+        } else {
+            self.job = Some(job);
+            self.job_status = JobStatus::Running {
+                cassette: job.cassette,
+                done: job.next_page,
+                total: job.pages,
+            };
+        }
+    }
+
+    fn prepare_job(&mut self, job: &Job) -> Result<(), Error> {
+        let JobKind::Copy { source, metadata } = job.kind else {
+            return Ok(());
+        };
+        self.wipe(job.cassette)?;
+        self.storage
+            .write(File::Metadata(job.cassette), 0, &metadata.to_bytes())?;
+        let settings = self.settings(source);
+        self.save_settings(job.cassette, &settings)
+    }
+
+    // The mix is applied by the bounce, leaving it in place would apply it
+    // twice.
+    fn finish_job(&mut self, job: &Job) -> Result<(), Error> {
+        let JobKind::Bounce(bounce) = job.kind else {
+            return Ok(());
+        };
+        let mut settings = self.settings(job.cassette);
+        let target = &mut settings.tracks[bounce.target_track];
+        *target = TrackSettings {
+            strength: target.strength,
+            ..TrackSettings::default()
+        };
+        self.save_settings(job.cassette, &settings)
+    }
+
+    // Records are copied as they are, including their checksums.
+    fn copy_page(
+        &mut self,
+        job: &Job,
+        source: CassetteId,
+        metadata: Metadata,
+    ) -> Result<(), Error> {
+        let size = record_size(metadata);
+        let offset = job.next_page * size;
+        let record = &mut self.record[..size];
+        self.storage.read(File::Audio(source), offset, record)?;
+        self.storage
+            .write(File::Audio(job.cassette), offset, record)?;
+        self.occupied[job.cassette.index()] = None;
+        Ok(())
+    }
+
+    // Corrupted pages are left as they are rather than replaced by a mix
+    // of silence.
+    fn bounce_page(&mut self, job: &Job, bounce: &Bounce) -> Result<(), Error> {
+        let mut page = Page::new(PageId::new(job.cassette, job.next_page));
+        match self.load(page.id(), &mut page) {
+            Ok(()) => (),
+            Err(Error::Corrupted) => {
+                self.diagnostics.corrupted_pages += 1;
+                return Ok(());
+            }
+            Err(error) => return Err(error),
+        }
+        self.render.render(bounce, &mut page);
+        self.save(&page)
+    }
+
+    fn wipe(&mut self, cassette_id: CassetteId) -> Result<(), Error> {
+        self.occupied[cassette_id.index()] = None;
+        if self.opened.is_some_and(|opened| opened.id == cassette_id) {
+            self.opened = None;
+        }
+        self.storage.remove(File::Audio(cassette_id))?;
+        self.storage.remove(File::Metadata(cassette_id))?;
+        self.storage.remove(File::Settings(cassette_id))
+    }
+
+    fn process_save_requests(&mut self, pool: &mut Pool, queues: &mut Queues) {
+        while let Some((cassette_id, settings)) = queues.settings_save_request_consumer.dequeue() {
+            if self.save_settings(cassette_id, &settings).is_err() {
+                self.diagnostics.failed_saves += 1;
+                #[cfg(feature = "defmt")]
+                defmt::error!(
+                    "Failed to save settings of cassette {}",
+                    cassette_id.index()
+                );
+            }
+        }
+        while let Some(page) = queues.save_request_first_page_consumer.dequeue() {
+            self.save_logged(&page);
+        }
+        while let Some(handle) = queues.save_request_consumer.dequeue() {
+            let page = pool.take_page(handle);
+            if page.is_dirty() {
+                self.save_logged(&page);
+            }
+        }
+    }
+
+    fn save_logged(&mut self, page: &Page) {
+        if self.save(page).is_err() {
+            self.diagnostics.failed_saves += 1;
+            #[cfg(feature = "defmt")]
+            defmt::error!(
+                "Failed to save page {} of cassette {}",
+                page.index(),
+                page.id().cassette_id().index()
+            );
+        }
+    }
+
+    /// Read attributes of the given cassette needed to start its playback.
+    pub(crate) fn open_cassette(&mut self, cassette_id: CassetteId) -> Cassette {
+        let metadata = self.metadata(cassette_id);
+        let pages = self.storage.size(File::Audio(cassette_id)) / record_size(metadata);
+        Cassette {
+            id: cassette_id,
+            length: pages * PAGE_LENGTH,
+            settings: self.settings(cassette_id),
+        }
+    }
+
+    /// Read settings of the given cassette, defaults are used if none were
+    /// stored or if they got corrupted.
+    pub(crate) fn settings(&mut self, cassette_id: CassetteId) -> Settings {
+        Settings::load(&mut self.storage, cassette_id)
+    }
+
+    pub(crate) fn save_settings(
+        &mut self,
+        cassette_id: CassetteId,
+        settings: &Settings,
+    ) -> Result<(), Error> {
+        settings.save(&mut self.storage, cassette_id)
+    }
+
+    /// Allocate a page in the pool and populate it based on the request.
+    ///
+    /// Pages that fail to load or do not pass the checksum verification
+    /// are replaced by silence.
+    pub(crate) fn handle_request(&mut self, request: PageRequest, pool: &mut Pool) -> Handle {
+        let handle = pool.new_page(request.page_id());
+        if let PageRequest::Load(id) = request {
+            match self.load(id, handle.page_mut()) {
+                Ok(()) => (),
+                Err(Error::Corrupted) => {
+                    self.diagnostics.corrupted_pages += 1;
+                    #[cfg(feature = "defmt")]
+                    defmt::warn!(
+                        "Page {} of cassette {} is corrupted, replacing it with silence",
+                        id.page_index(),
+                        id.cassette_id().index()
+                    );
+                }
+                Err(_) => {
+                    self.diagnostics.failed_loads += 1;
+                    #[cfg(feature = "defmt")]
+                    defmt::warn!(
+                        "Failed to load page {} of cassette {}, replacing it with silence",
+                        id.page_index(),
+                        id.cassette_id().index()
+                    );
+                }
+            }
+        }
+        handle
+    }
+
+    pub(crate) fn save(&mut self, page: &Page) -> Result<(), Error> {
+        let id = page.id();
+        let opened = self.open(id.cassette_id());
+        if !opened.persisted {
+            self.storage
+                .write(File::Metadata(opened.id), 0, &opened.metadata.to_bytes())?;
+            self.opened = Some(Opened {
+                persisted: true,
+                ..opened
+            });
+            if let Some(occupied) = self.occupied[opened.id.index()].as_mut() {
+                occupied.metadata = METADATA_SIZE;
+            }
+        }
+
+        let metadata = opened.metadata;
+        let format = metadata.format;
+        let data_size = data_size(metadata);
+        for (sample, bytes) in page.data[..metadata.tracks]
+            .iter()
+            .flatten()
+            .zip(self.record[..data_size].chunks_exact_mut(format.sample_size()))
+        {
+            format.encode(*sample, &mut self.dither, bytes);
+        }
+        let checksum = CRC.checksum(&self.record[..data_size]);
+        self.record[data_size..data_size + CHECKSUM_SIZE].copy_from_slice(&checksum.to_le_bytes());
+        let record_size = record_size(metadata);
+        let offset = id.page_index() * record_size;
+        self.storage.write(
+            File::Audio(id.cassette_id()),
+            offset,
+            &self.record[..record_size],
+        )?;
+        if let Some(occupied) = self.occupied[id.cassette_id().index()].as_mut() {
+            occupied.audio = occupied.audio.max(offset + record_size);
+        }
+        Ok(())
+    }
+
+    /// Space occupied by the given cassette, relative to the configured budget.
+    pub(crate) fn usage(&mut self, cassette_id: CassetteId) -> Usage {
+        let occupied = match self.occupied[cassette_id.index()] {
+            Some(occupied) => occupied,
+            None => {
+                let occupied = Occupied {
+                    audio: self.storage.size(File::Audio(cassette_id)),
+                    metadata: self.storage.size(File::Metadata(cassette_id)),
+                };
+                self.occupied[cassette_id.index()] = Some(occupied);
+                occupied
+            }
+        };
+        let capacity = *self.capacity.get_or_insert_with(|| self.storage.capacity());
+        let budget = match self.budget {
+            Budget::Card => capacity,
+            Budget::PerCassette => capacity / CASSETTES,
+        };
+        Usage {
+            used: occupied.audio + occupied.metadata,
+            budget,
+        }
+    }
+
+    /// Read metadata of the given cassette.
+    pub(crate) fn metadata(&mut self, cassette_id: CassetteId) -> Metadata {
+        self.open(cassette_id).metadata
+    }
+
+    /// Verify checksums of all the pages stored on the given cassette.
+    pub(crate) fn scan(&mut self, cassette_id: CassetteId) -> Scan {
+        let metadata = self.metadata(cassette_id);
+        let pages = self.storage.size(File::Audio(cassette_id)) / record_size(metadata);
+        let mut scan = Scan {
+            pages,
+            ..Scan::default()
+        };
+        for page_index in 0..pages {
+            if self
+                .read_record(PageId::new(cassette_id, page_index), metadata)
+                .is_err()
+            {
+                scan.corrupted_pages += 1;
+                scan.first_corrupted_page.get_or_insert(page_index);
+            }
+        }
+        scan
+    }
+
+    fn open(&mut self, cassette_id: CassetteId) -> Opened {
+        if let Some(opened) = self.opened {
+            if opened.id == cassette_id {
+                return opened;
+            }
+        }
+
+        let mut bytes = [0; METADATA_SIZE];
+        let stored = self
+            .storage
+            .read(File::Metadata(cassette_id), 0, &mut bytes)
+            .ok()
+            .and_then(|_| Metadata::from_bytes(&bytes));
+        let opened = if let Some(metadata) = stored {
+            Opened {
+                id: cassette_id,
+                metadata,
+                persisted: true,
+            }
+        } else if self.storage.size(File::Audio(cassette_id)) > 0 {
+            Opened {
+                id: cassette_id,
+                metadata: Metadata::legacy(),
+                persisted: true,
+            }
+        } else {
+            Opened {
+                id: cassette_id,
+                metadata: Metadata::new(self.format),
+                persisted: false,
+            }
+        };
+
+        self.opened = Some(opened);
+        opened
+    }
+
+    fn load(&mut self, id: PageId, page: &mut Page) -> Result<(), Error> {
+        let metadata = self.metadata(id.cassette_id());
+        let format = metadata.format;
+        let data_size = self.read_record(id, metadata)?;
+        for (sample, bytes) in page.data[..metadata.tracks]
+            .iter_mut()
+            .flatten()
+            .zip(self.record[..data_size].chunks_exact(format.sample_size()))
+        {
+            *sample = format.decode(bytes);
+        }
+        Ok(())
+    }
+
+    fn read_record(&mut self, id: PageId, metadata: Metadata) -> Result<usize, Error> {
+        let data_size = data_size(metadata);
+        let record = &mut self.record[..record_size(metadata)];
+        self.storage.read(
+            File::Audio(id.cassette_id()),
+            id.page_index() * record.len(),
+            record,
+        )?;
+        let stored = u32::from_le_bytes(record[data_size..].try_into().unwrap());
+        if CRC.checksum(&record[..data_size]) == stored {
+            Ok(data_size)
+        } else {
+            Err(Error::Corrupted)
+        }
+    }
+
+    pub(crate) fn storage_mut(&mut self) -> &mut S {
+        &mut self.storage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::queues;
+    use super::super::storage::memory::MemoryStorage;
+    use super::*;
+    use crate::mixer::volume_gain;
+
+    fn recorded_page(cassette_id: CassetteId, page_index: usize, value: f32) -> Page {
+        let mut page = Page::new(PageId::new(cassette_id, page_index));
+        page.data = [[value; PAGE_LENGTH]; TRACKS];
+        page
+    }
+
+    /// Queues connecting the store with a `Manager` simulated by the test.
+    #[test]
+    fn load_previously_saved_page() {
+        static mut POOL: Pool = Pool::new();
+        let pool = unsafe { &mut POOL };
+        let mut store = Store::new(MemoryStorage::default(), SampleFormat::F32);
+        let cassette_id = CassetteId::new(1);
+
+        store.save(&recorded_page(cassette_id, 0, 0.1)).unwrap();
+        store.save(&recorded_page(cassette_id, 1, 0.2)).unwrap();
+
+        let handle = store.handle_request(PageRequest::Load(PageId::new(cassette_id, 1)), pool);
+        assert_eq!(handle.page_ref().data, [[0.2; PAGE_LENGTH]; TRACKS]);
+        assert_eq!(store.diagnostics.corrupted_pages, 0);
+    }
+
+    #[test]
+    fn replace_corrupted_page_with_silence() {
+        static mut POOL: Pool = Pool::new();
+        let pool = unsafe { &mut POOL };
+        let mut store = Store::new(MemoryStorage::default(), SampleFormat::F32);
+        let cassette_id = CassetteId::new(1);
+
+        store.save(&recorded_page(cassette_id, 0, 0.1)).unwrap();
+        store.storage_mut().file_mut(File::Audio(cassette_id))[10] ^= 0xFF;
+
+        let handle = store.handle_request(PageRequest::Load(PageId::new(cassette_id, 0)), pool);
+        assert_eq!(handle.page_ref().data, [[0.0; PAGE_LENGTH]; TRACKS]);
+        assert_eq!(store.diagnostics.corrupted_pages, 1);
+        assert_eq!(store.diagnostics.failed_loads, 0);
+    }
+
+    #[test]
+    fn replace_missing_page_with_silence() {
+        static mut POOL: Pool = Pool::new();
+        let pool = unsafe { &mut POOL };
+        let mut store = Store::new(MemoryStorage::default(), SampleFormat::F32);
+
+        let handle =
+            store.handle_request(PageRequest::Load(PageId::new(CassetteId::new(1), 3)), pool);
+        assert_eq!(handle.page_ref().data, [[0.0; PAGE_LENGTH]; TRACKS]);
+        assert_eq!(store.diagnostics.failed_loads, 1);
+        assert_eq!(store.diagnostics.corrupted_pages, 0);
+    }
+
+    #[test]
+    fn scan_cassette_for_corrupted_pages() {
+        let mut store = Store::new(MemoryStorage::default(), SampleFormat::F32);
+        let cassette_id = CassetteId::new(1);
+
+        for page_index in 0..4 {
+            store
+                .save(&recorded_page(cassette_id, page_index, 0.1))
+                .unwrap();
+        }
+        assert!(store.scan(cassette_id).is_intact());
+
+        store.storage_mut().file_mut(File::Audio(cassette_id))
+            [2 * record_size(Metadata::new(SampleFormat::F32)) + 1] ^= 0xFF;
+        store.storage_mut().file_mut(File::Audio(cassette_id))
+            [3 * record_size(Metadata::new(SampleFormat::F32)) + 1] ^= 0xFF;
+        assert_eq!(
+            store.scan(cassette_id),
+            Scan {
+                pages: 4,
+                corrupted_pages: 2,
+                first_corrupted_page: Some(2),
+            }
+        );
+    }
+
+    #[test]
+    fn scan_is_limited_to_the_given_cassette() {
+        let mut store = Store::new(MemoryStorage::default(), SampleFormat::F32);
+
+        store
+            .save(&recorded_page(CassetteId::new(1), 0, 0.1))
+            .unwrap();
+        store
+            .storage_mut()
+            .file_mut(File::Audio(CassetteId::new(1)))[1] ^= 0xFF;
+        store
+            .save(&recorded_page(CassetteId::new(2), 0, 0.1))
+            .unwrap();
+
+        assert!(!store.scan(CassetteId::new(1)).is_intact());
+        assert!(store.scan(CassetteId::new(2)).is_intact());
+    }
+
+    #[test]
+    fn record_new_cassettes_in_configured_format() {
+        static mut POOL: Pool = Pool::new();
+        let pool = unsafe { &mut POOL };
+        let mut store = Store::new(MemoryStorage::default(), SampleFormat::I16);
+        let cassette_id = CassetteId::new(1);
+
+        store.save(&recorded_page(cassette_id, 0, 0.5)).unwrap();
+        store.save(&recorded_page(cassette_id, 1, -0.5)).unwrap();
+        assert_eq!(
+            store.storage_mut().size(File::Audio(cassette_id)),
+            2 * record_size(Metadata::new(SampleFormat::I16))
+        );
+
+        let handle = store.handle_request(PageRequest::Load(PageId::new(cassette_id, 1)), pool);
+        for sample in handle.page_ref().data.iter().flatten() {
+            assert!((sample + 0.5).abs() < 0.001);
+        }
+    }
+
+    #[test]
+    fn keep_format_of_existing_cassettes() {
+        let mut storage = MemoryStorage::default();
+        let cassette_id = CassetteId::new(1);
+
+        let mut store = Store::new(storage, SampleFormat::I24);
+        store.save(&recorded_page(cassette_id, 0, 0.5)).unwrap();
+        storage = store.storage;
+
+        let mut store = Store::new(storage, SampleFormat::I16);
+        assert_eq!(store.metadata(cassette_id).format, SampleFormat::I24);
+        store.save(&recorded_page(cassette_id, 1, 0.5)).unwrap();
+        assert!(store.scan(cassette_id).is_intact());
+        assert_eq!(store.scan(cassette_id).pages, 2);
+    }
+
+    #[test]
+    fn read_cassettes_without_metadata_as_f32() {
+        static mut POOL: Pool = Pool::new();
+        let pool = unsafe { &mut POOL };
+        let cassette_id = CassetteId::new(1);
+
+        let mut storage = MemoryStorage::default();
+        let mut record: Vec<u8> = [0.123_f32; PAGE_LENGTH]
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+        let checksum = CRC.checksum(&record);
+        record.extend_from_slice(&checksum.to_le_bytes());
+        storage.write(File::Audio(cassette_id), 0, &record).unwrap();
+
+        let mut store = Store::new(storage, SampleFormat::I16);
+        assert_eq!(store.metadata(cassette_id), Metadata::legacy());
+        let handle = store.handle_request(PageRequest::Load(PageId::new(cassette_id, 0)), pool);
+        assert_eq!(handle.page_ref().data[0], [0.123; PAGE_LENGTH]);
+        assert_eq!(handle.page_ref().data[1], [0.0; PAGE_LENGTH]);
+    }
+
+    #[test]
+    fn report_space_used_by_cassette() {
+        let mut store = Store::new(MemoryStorage::with_capacity(1024 * 1024), SampleFormat::I16);
+        let cassette_id = CassetteId::new(1);
+
+        assert!(store.usage(cassette_id).is_empty());
+
+        store.save(&recorded_page(cassette_id, 0, 0.5)).unwrap();
+        store.save(&recorded_page(cassette_id, 1, 0.5)).unwrap();
+        let expected = 2 * record_size(Metadata::new(SampleFormat::I16)) + METADATA_SIZE;
+        assert_eq!(
+            store.usage(cassette_id),
+            Usage {
+                used: expected,
+                budget: 1024 * 1024,
+            }
+        );
+        assert_eq!(store.usage(cassette_id).lit_leds(), 1);
+
+        store.set_budget(Budget::PerCassette);
+        assert_eq!(store.usage(cassette_id).budget, 1024 * 1024 / CASSETTES);
+    }
+
+    #[test]
+    fn keep_space_accounting_in_sync_with_medium() {
+        let mut store = Store::new(MemoryStorage::default(), SampleFormat::F32);
+        let cassette_id = CassetteId::new(1);
+
+        for page_index in [2, 0, 1] {
+            store
+                .save(&recorded_page(cassette_id, page_index, 0.5))
+                .unwrap();
+            let cached = store.usage(cassette_id).used;
+            let actual = store.storage.size(File::Audio(cassette_id))
+                + store.storage.size(File::Metadata(cassette_id));
+            assert_eq!(cached, actual);
+        }
+    }
+
+    fn store_with_recording(cassette_id: CassetteId, pages: usize) -> Store<MemoryStorage> {
+        let mut store = Store::new(MemoryStorage::default(), SampleFormat::I16);
+        for page_index in 0..pages {
+            store
+                .save(&recorded_page(
+                    cassette_id,
+                    page_index,
+                    0.1 * page_index as f32,
+                ))
+                .unwrap();
+        }
+        store
+    }
+
+    #[test]
+    fn copy_cassette_with_its_settings() {
+        static mut POOL: Pool = Pool::new();
+        let pool = unsafe { &mut POOL };
+        let (source, target) = (CassetteId::new(1), CassetteId::new(2));
+        let mut store = store_with_recording(source, 3);
+        let mut settings = Settings::default();
+        settings.tracks[1].volume = 0.25;
+        store.save_settings(source, &settings).unwrap();
+        store.save(&recorded_page(target, 5, 0.9)).unwrap();
+        let mut queues = queues::Queues::new();
+        let mut ends = queues.split();
+
+        store.start_copy(source, target).unwrap();
+        let mut progress = Vec::new();
+        while let JobStatus::Running { done, total, .. } = store.job_status() {
+            assert_eq!(total, 3);
+            progress.push(done);
+            store.process(pool, &mut ends.store);
+        }
+        assert_eq!(progress, [0, 0, 1, 2]);
+        assert_eq!(store.job_status(), JobStatus::Finished(target));
+
+        assert_eq!(store.metadata(target).format, SampleFormat::I16);
+        assert_eq!(store.settings(target), settings);
+        assert_eq!(store.usage(target), store.usage(source));
+        for page_index in 0..3 {
+            let copied =
+                store.handle_request(PageRequest::Load(PageId::new(target, page_index)), pool);
+            let original =
+                store.handle_request(PageRequest::Load(PageId::new(source, page_index)), pool);
+            assert_eq!(copied.page_ref().data, original.page_ref().data);
+            pool.take_page(copied);
+            pool.take_page(original);
+        }
+        assert_eq!(store.diagnostics.corrupted_pages, 0);
+    }
+
+    #[test]
+    fn copy_advances_by_a_single_page_after_serving_the_manager() {
+        static mut POOL: Pool = Pool::new();
+        let pool = unsafe { &mut POOL };
+        let (source, target) = (CassetteId::new(1), CassetteId::new(2));
+        let mut store = store_with_recording(source, 4);
+        let mut queues = queues::Queues::new();
+        let mut ends = queues.split();
+
+        store.start_copy(source, target).unwrap();
+        store.process(pool, &mut ends.store);
+        for done in 1..=2 {
+            ends.transport
+                .load_request_producer
+                .enqueue(PageRequest::Load(PageId::new(source, done)))
+                .ok()
+                .unwrap();
+            store.process(pool, &mut ends.store);
+
+            let handle = ends.transport.load_response_consumer.dequeue().unwrap();
+            assert_eq!(handle.page_ref().id(), PageId::new(source, done));
+            pool.take_page(handle);
+            assert_eq!(
+                store.job_status(),
+                JobStatus::Running {
+                    cassette: target,
+                    done,
+                    total: 4
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn cancelled_copy_leaves_target_blank() {
+        static mut POOL: Pool = Pool::new();
+        let pool = unsafe { &mut POOL };
+        let (source, target) = (CassetteId::new(1), CassetteId::new(2));
+        let mut store = store_with_recording(source, 4);
+        let mut queues = queues::Queues::new();
+        let mut ends = queues.split();
+
+        store.start_copy(source, target).unwrap();
+        store.process(pool, &mut ends.store);
+        store.process(pool, &mut ends.store);
+        store.cancel_job();
+
+        assert_eq!(store.job_status(), JobStatus::Cancelled(target));
+        assert!(store.usage(target).is_empty());
+        assert_eq!(store.settings(target), Settings::default());
+        assert!(store.start_copy(source, target).is_ok());
+    }
+
+    #[test]
+    fn refuse_to_copy_over_cassette_in_use() {
+        static mut POOL: Pool = Pool::new();
+        let pool = unsafe { &mut POOL };
+        let (source, target) = (CassetteId::new(1), CassetteId::new(2));
+        let mut store = store_with_recording(source, 2);
+        let mut queues = queues::Queues::new();
+        let mut ends = queues.split();
+
+        ends.switch_request_producer.enqueue(target).ok().unwrap();
+        store.process(pool, &mut ends.store);
+
+        assert_eq!(
+            store.start_copy(source, target),
+            Err(JobError::CassetteInUse)
+        );
+        assert_eq!(
+            store.start_copy(source, source),
+            Err(JobError::SameCassette)
+        );
+        store.start_copy(target, source).unwrap();
+        assert_eq!(
+            store.start_copy(target, CassetteId::new(3)),
+            Err(JobError::Busy)
+        );
+    }
+
+    #[test]
+    fn switching_to_the_target_cancels_copy() {
+        static mut POOL: Pool = Pool::new();
+        let pool = unsafe { &mut POOL };
+        let (source, target) = (CassetteId::new(1), CassetteId::new(2));
+        let mut store = store_with_recording(source, 4);
+        let mut queues = queues::Queues::new();
+        let mut ends = queues.split();
+
+        store.start_copy(source, target).unwrap();
+        store.process(pool, &mut ends.store);
+        store.process(pool, &mut ends.store);
+        ends.switch_request_producer.enqueue(target).ok().unwrap();
+        store.process(pool, &mut ends.store);
+
+        assert_eq!(store.job_status(), JobStatus::Cancelled(target));
+        let cassette = ends.switch_response_consumer.dequeue().unwrap();
+        assert_eq!(cassette.id, target);
+        assert_eq!(cassette.length, 0);
+    }
+
+    fn bounce_until_finished(store: &mut Store<MemoryStorage>, pool: &mut Pool) {
+        let mut queues = queues::Queues::new();
+        let mut ends = queues.split();
+        while let JobStatus::Running { .. } = store.job_status() {
+            store.process(pool, &mut ends.store);
+        }
+    }
+
+    #[test]
+    fn bounce_mixes_sources_into_the_target_track() {
+        static mut POOL: Pool = Pool::new();
+        let pool = unsafe { &mut POOL };
+        let cassette_id = CassetteId::new(1);
+        let mut store = Store::new(MemoryStorage::default(), SampleFormat::F32);
+        for page_index in 0..2 {
+            let mut page = Page::new(PageId::new(cassette_id, page_index));
+            page.data = [
+                [0.1; PAGE_LENGTH],
+                [0.2; PAGE_LENGTH],
+                [0.3; PAGE_LENGTH],
+                [0.4; PAGE_LENGTH],
+            ];
+            store.save(&page).unwrap();
+        }
+        let mut settings = Settings::default();
+        settings.tracks[0].volume = 0.5;
+        settings.tracks[2].volume = 0.25;
+        store.save_settings(cassette_id, &settings).unwrap();
+
+        store
+            .start_bounce(cassette_id, [true, false, true, true], 2)
+            .unwrap();
+        bounce_until_finished(&mut store, pool);
+
+        assert_eq!(store.job_status(), JobStatus::Finished(cassette_id));
+        for page_index in 0..2 {
+            let handle = store.handle_request(
+                PageRequest::Load(PageId::new(cassette_id, page_index)),
+                pool,
+            );
+            let data = handle.page_ref().data;
+            assert!(data[0].iter().all(|x| *x == 0.0));
+            assert!(data[1].iter().all(|x| *x == 0.2));
+            let mixed = 0.1 * volume_gain(0.5) + 0.3 * volume_gain(0.25) + 0.4;
+            assert!(data[2].iter().all(|x| (x - mixed).abs() < 1e-6));
+            assert!(data[3].iter().all(|x| *x == 0.0));
+            pool.take_page(handle);
+        }
+        let settings = store.settings(cassette_id);
+        assert_eq!(settings.tracks[2].volume, 1.0);
+        assert_eq!(settings.tracks[0].volume, 0.5);
+    }
+
+    #[test]
+    fn bounce_plays_sources_through_pan_and_effects() {
+        const LOWPASS: u8 = 1;
+        static mut POOL: Pool = Pool::new();
+        let pool = unsafe { &mut POOL };
+        let cassette_id = CassetteId::new(1);
+        let mut store = Store::new(MemoryStorage::default(), SampleFormat::F32);
+        let mut page = Page::new(PageId::new(cassette_id, 0));
+        page.data[0] = [0.5; PAGE_LENGTH];
+        page.data[1] = core::array::from_fn(|i| if i % 2 == 0 { 0.5 } else { -0.5 });
+        store.save(&page).unwrap();
+        let mut settings = Settings::default();
+        settings.tracks[0].pan = 0.0;
+        settings.tracks[1].effect = LOWPASS;
+        settings.tracks[1].strength = 1.0;
+        settings.tracks[1].pan = 0.2;
+        store.save_settings(cassette_id, &settings).unwrap();
+
+        store
+            .start_bounce(cassette_id, [true, true, false, false], 0)
+            .unwrap();
+        bounce_until_finished(&mut store, pool);
+
+        let handle = store.handle_request(PageRequest::Load(PageId::new(cassette_id, 0)), pool);
+        let data = handle.page_ref().data;
+        // Panned hard left, only half of the track remains in the mono
+        // fold.
+        let panned = 0.5 * FRAC_1_SQRT_2;
+        assert!(data[0].iter().all(|x| (x - panned).abs() < 0.01));
+        assert!(data[1].iter().all(|x| *x == 0.0));
+        pool.take_page(handle);
+        let settings = store.settings(cassette_id);
+        assert_eq!(settings.tracks[0].pan, 0.5);
+        assert_eq!(settings.tracks[1].effect, LOWPASS);
+    }
+
+    #[test]
+    fn cancelled_bounce_keeps_bounced_pages() {
+        static mut POOL: Pool = Pool::new();
+        let pool = unsafe { &mut POOL };
+        let cassette_id = CassetteId::new(1);
+        let mut store = store_with_recording(cassette_id, 3);
+        let mut queues = queues::Queues::new();
+        let mut ends = queues.split();
+
+        store
+            .start_bounce(cassette_id, [true, true, false, false], 0)
+            .unwrap();
+        store.process(pool, &mut ends.store);
+        store.process(pool, &mut ends.store);
+        store.cancel_job();
+
+        assert_eq!(store.job_status(), JobStatus::Cancelled(cassette_id));
+        let bounced = store.handle_request(PageRequest::Load(PageId::new(cassette_id, 1)), pool);
+        assert!((bounced.page_ref().data[0][0] - 0.2).abs() < 1e-3);
+        assert_eq!(bounced.page_ref().data[1][0], 0.0);
+        pool.take_page(bounced);
+        let untouched = store.handle_request(PageRequest::Load(PageId::new(cassette_id, 2)), pool);
+        assert_eq!(
+            untouched.page_ref().data[1][0],
+            untouched.page_ref().data[0][0]
+        );
+        pool.take_page(untouched);
+    }
+
+    #[test]
+    fn refuse_to_bounce_into_missing_track() {
+        let legacy = CassetteId::new(2);
+        let mut store = store_with_recording(CassetteId::new(1), 2);
+        store
+            .storage
+            .write(File::Audio(legacy), 0, &[0; 4])
+            .unwrap();
+
+        assert_eq!(
+            store.start_bounce(legacy, [true; TRACKS], 1),
+            Err(JobError::NoSuchTrack)
+        );
+        store.start_bounce(legacy, [true; TRACKS], 0).unwrap();
+        assert_eq!(
+            store.start_copy(legacy, CassetteId::new(3)),
+            Err(JobError::Busy)
+        );
+    }
+
+    #[test]
+    fn bounce_of_cassette_in_use_waits_for_its_backup() {
+        static mut POOL: Pool = Pool::new();
+        let pool = unsafe { &mut POOL };
+        let active = CassetteId::new(1);
+        let mut store = store_with_recording(active, 2);
+        let mut queues = queues::Queues::new();
+        let mut ends = queues.split();
+        ends.switch_request_producer.enqueue(active).ok().unwrap();
+        store.process(pool, &mut ends.store);
+        ends.switch_response_consumer.dequeue().unwrap();
+        let mut settings = Settings::default();
+        settings.tracks[0].volume = 0.5;
+        store.save_settings(active, &settings).unwrap();
+
+        store
+            .start_bounce(active, [true, true, false, false], 0)
+            .unwrap();
+        store.process(pool, &mut ends.store);
+        store.process(pool, &mut ends.store);
+        assert_eq!(
+            store.job_status(),
+            JobStatus::Running {
+                cassette: active,
+                done: 0,
+                total: 2
+            }
+        );
+
+        // The page recorded meanwhile is backed up before it gets bounced.
+        let handle = pool.new_page(PageId::new(active, 1));
+        handle.page_mut().data[1] = [0.4; PAGE_LENGTH];
+        handle.page_mut().mark_dirty();
+        ends.transport
+            .save_request_producer
+            .enqueue(handle)
+            .ok()
+            .unwrap();
+        ends.switch_request_producer.enqueue(active).ok().unwrap();
+        store.process(pool, &mut ends.store);
+
+        assert_eq!(store.job_status(), JobStatus::Finished(active));
+        let cassette = ends.switch_response_consumer.dequeue().unwrap();
+        assert_eq!(cassette.settings.tracks[0].volume, 1.0);
+        let bounced = store.handle_request(PageRequest::Load(PageId::new(active, 1)), pool);
+        assert!((bounced.page_ref().data[0][0] - 0.4).abs() < 1e-3);
+        assert_eq!(bounced.page_ref().data[1][0], 0.0);
+        pool.take_page(bounced);
+    }
+}
@@ -0,0 +1,38 @@
+//! Tape running in memory, for tests of crates driving the `Transport`.
+//!
+//! The deck holds a single blank page and no storage, so only the first
+//! page of the tape can be played and recorded.
+
+use super::cassette::Cassette;
+use super::manager::Manager;
+use super::page::PageId;
+use super::pool::Pool;
+use super::queues::Queues;
+
+pub use super::deck::Deck;
+
+/// Call `f` with a deck playing a blank cassette.
+pub fn with_deck<R>(f: impl FnOnce(&mut Deck) -> R) -> R {
+    let mut pool = Pool::new();
+    let mut queues = Queues::new();
+    let mut ends = queues.split();
+
+    let cassette = Cassette::new(0);
+    let handle = pool.new_page(PageId::new(cassette.id, 0));
+    let mut manager = Manager::new();
+    manager.set_cassette(cassette);
+    ends.store
+        .load_response_producer
+        .enqueue(handle)
+        .ok()
+        .unwrap();
+    manager.try_fetching_next_page(&mut ends.transport.load_response_consumer);
+
+    f(&mut Deck::new(
+        manager,
+        ends.transport,
+        ends.config_consumer,
+        ends.switch_request_producer,
+        ends.switch_response_consumer,
+    ))
+}
@@ -0,0 +1,497 @@
+//! Play, pause and record state of the tape.
+//!
+//! Commands of the control layer are collected here and passed to the
+//! `Manager` from the audio routine. Pausing stops the tape, keeping the
+//! tracks armed, so recording continues once the tape starts playing
+//! again.
+//!
+//! Commands driven by external gates are cued at a sample offset within
+//! the next block instead. The block is then processed in segments split at
+//! the cues, so the tape reacts exactly on the sample the gate arrived at.
+//! Pages are swapped on the sample they get full at, so the tape stays
+//! aligned to pages even after a cue in the middle of a block.
+
+use heapless::spsc::{Consumer, Producer};
+use heapless::Vec;
+
+use super::cassette::{CassetteId, TRACKS};
+use super::config::Config;
+use super::manager::Manager;
+use super::page::{Page, PageRequest};
+use super::pool::Handle;
+
+/// Most cues that can be scheduled for a single block.
+pub const MAX_CUES: usize = 8;
+
+/// Command to be executed at a given sample of a block.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Cue {
+    PlayFromBeginning,
+    TogglePlay,
+    /// Arm or disarm recording of the track.
+    Record(usize, bool),
+}
+
+/// Queues used by the transport to move the tape.
+pub(crate) struct Queues<'a> {
+    pub config_producer: Producer<'a, Config, 4>,
+    pub save_request_producer: Producer<'a, Handle, 4>,
+    pub save_request_first_page_producer: Producer<'a, Page, 4>,
+    pub load_request_producer: Producer<'a, PageRequest, 4>,
+    pub load_response_consumer: Consumer<'a, Handle, 4>,
+}
+
+#[derive(Debug)]
+pub struct Transport {
+    playing: bool,
+    recording: [bool; TRACKS],
+    rewind_pending: bool,
+    config_pending: bool,
+    switch_pending: Option<CassetteId>,
+    // Kept ordered by their offset.
+    cues: Vec<(usize, Cue), MAX_CUES>,
+}
+
+impl Default for Transport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Transport {
+    /// Start paused, with no track armed.
+    pub fn new() -> Self {
+        Self {
+            playing: false,
+            recording: [false; TRACKS],
+            rewind_pending: false,
+            config_pending: false,
+            switch_pending: None,
+            cues: Vec::new(),
+        }
+    }
+
+    /// Move to the start of the tape and play, regardless of whether it
+    /// was playing or paused before.
+    pub fn play_from_beginning(&mut self) {
+        self.playing = true;
+        self.rewind_pending = true;
+    }
+
+    pub fn toggle_play(&mut self) {
+        self.playing = !self.playing;
+    }
+
+    pub fn set_recording(&mut self, track: usize, recording: bool) {
+        if self.recording[track] != recording {
+            self.recording[track] = recording;
+            self.config_pending = true;
+        }
+    }
+
+    pub fn stop_recording(&mut self) {
+        for track in 0..TRACKS {
+            self.set_recording(track, false);
+        }
+    }
+
+    /// Back up the current cassette and switch to another one.
+    pub fn switch_cassette(&mut self, cassette_id: CassetteId) {
+        self.switch_pending = Some(cassette_id);
+    }
+
+    pub(crate) fn take_switch(&mut self) -> Option<CassetteId> {
+        self.switch_pending.take()
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    pub fn is_recording(&self, track: usize) -> bool {
+        self.recording[track]
+    }
+
+    /// Whether the tape is to be moved to its start before the next block.
+    pub fn is_rewind_pending(&self) -> bool {
+        self.rewind_pending
+    }
+
+    /// Schedule the command at the given sample of the next processed block.
+    ///
+    /// Cues with the same offset are executed in the order they were given.
+    /// Once `MAX_CUES` are scheduled, further cues are dropped and `false`
+    /// is returned.
+    pub fn cue(&mut self, offset: usize, cue: Cue) -> bool {
+        let position = self
+            .cues
+            .iter()
+            .position(|(scheduled, _)| *scheduled > offset)
+            .unwrap_or(self.cues.len());
+        self.cues.insert(position, (offset, cue)).is_ok()
+    }
+
+    /// Pass the pending changes to the `Manager`.
+    ///
+    /// Changes of armed tracks are sent through the configuration queue.
+    /// If it is full, they are retried on the next call. Pages the manager
+    /// does not need anymore are passed back to the storage routine.
+    pub(crate) fn drive(&mut self, manager: &mut Manager, queues: &mut Queues) {
+        manager.release_pages(&mut queues.save_request_producer);
+        if self.config_pending {
+            let config = Config {
+                recording: self.recording,
+            };
+            self.config_pending = queues.config_producer.enqueue(config).is_err();
+        }
+        if self.rewind_pending {
+            self.rewind_pending = false;
+            manager.rewind(
+                &mut queues.save_request_producer,
+                &mut queues.save_request_first_page_producer,
+            );
+        }
+    }
+
+    /// Run the tape through the `Manager`, or output silence while paused.
+    ///
+    /// Cues are executed on their samples, offsets past the end of the
+    /// block are executed after its last sample.
+    pub(crate) fn process(
+        &mut self,
+        manager: &mut Manager,
+        queues: &mut Queues,
+        input: &[[f32; TRACKS]],
+        output: &mut [[f32; TRACKS]],
+    ) {
+        let mut start = 0;
+        let cues = core::mem::take(&mut self.cues);
+        for (offset, cue) in cues {
+            let end = offset.clamp(start, input.len());
+            self.process_segment(manager, queues, &input[start..end], &mut output[start..end]);
+            self.execute(cue, manager, queues);
+            start = end;
+        }
+        self.process_segment(manager, queues, &input[start..], &mut output[start..]);
+    }
+
+    fn process_segment(
+        &self,
+        manager: &mut Manager,
+        queues: &mut Queues,
+        input: &[[f32; TRACKS]],
+        output: &mut [[f32; TRACKS]],
+    ) {
+        if !self.playing {
+            output.fill([0.0; TRACKS]);
+            return;
+        }
+
+        let mut start = 0;
+        while start < input.len() {
+            if manager.is_waiting_for_page()
+                && manager.try_fetching_next_page(&mut queues.load_response_consumer)
+            {
+                manager.start_loading_next_page(&mut queues.load_request_producer);
+            }
+            start += manager.process(&input[start..], &mut output[start..]);
+            if manager.has_full_page() {
+                manager.start_saving(
+                    &mut queues.save_request_producer,
+                    &mut queues.save_request_first_page_producer,
+                );
+            }
+        }
+    }
+
+    fn execute(&mut self, cue: Cue, manager: &mut Manager, queues: &mut Queues) {
+        match cue {
+            Cue::PlayFromBeginning => {
+                self.playing = true;
+                manager.rewind(
+                    &mut queues.save_request_producer,
+                    &mut queues.save_request_first_page_producer,
+                );
+                // The first page is usually cached, so the rest of the block
+                // can be played from it right away.
+                let acquired = manager.try_fetching_next_page(&mut queues.load_response_consumer);
+                if acquired {
+                    manager.start_loading_next_page(&mut queues.load_request_producer);
+                }
+            }
+            Cue::TogglePlay => self.toggle_play(),
+            Cue::Record(track, recording) => {
+                self.set_recording(track, recording);
+                manager.set_recording(self.recording);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::cassette::{Cassette, CassetteId};
+    use super::super::page::{PageId, PAGE_LENGTH};
+    use super::super::pool::Pool;
+    use super::super::queues::{self, Ends};
+    use super::*;
+
+    /// Manager playing a cassette with a marker at its very first sample.
+    fn manager_with_marked_page(pool: &mut Pool, ends: &mut Ends) -> Manager {
+        let cassette = Cassette::new(0);
+        let handle = pool.new_page(PageId::new(cassette.id, 0));
+        let mut manager = Manager::new();
+        manager.set_cassette(cassette);
+        handle.page_mut().data[0][0] = 0.7;
+        ends.store
+            .load_response_producer
+            .enqueue(handle)
+            .ok()
+            .unwrap();
+        assert!(manager.try_fetching_next_page(&mut ends.transport.load_response_consumer));
+        manager
+    }
+
+    #[test]
+    fn paused_tape_outputs_silence_without_moving() {
+        static mut POOL: Pool = Pool::new();
+        let pool = unsafe { &mut POOL };
+        let mut channels = queues::Queues::new();
+        let mut ends = channels.split();
+        let mut manager = manager_with_marked_page(pool, &mut ends);
+        let mut transport = Transport::new();
+
+        let mut output = [[1.0; TRACKS]; 32];
+        transport.process(
+            &mut manager,
+            &mut ends.transport,
+            &[[0.0; TRACKS]; 32],
+            &mut output,
+        );
+        assert_eq!(output, [[0.0; TRACKS]; 32]);
+
+        transport.toggle_play();
+        transport.process(
+            &mut manager,
+            &mut ends.transport,
+            &[[0.0; TRACKS]; 32],
+            &mut output,
+        );
+        assert_eq!(output[0][0], 0.7);
+    }
+
+    #[test]
+    fn rewind_moves_to_the_first_sample() {
+        static mut POOL: Pool = Pool::new();
+        let pool = unsafe { &mut POOL };
+        let mut channels = queues::Queues::new();
+        let mut ends = channels.split();
+        let mut manager = manager_with_marked_page(pool, &mut ends);
+        let mut transport = Transport::new();
+        transport.toggle_play();
+        let mut output = [[0.0; TRACKS]; 32];
+        for _ in 0..3 {
+            transport.process(
+                &mut manager,
+                &mut ends.transport,
+                &[[0.0; TRACKS]; 32],
+                &mut output,
+            );
+        }
+
+        transport.play_from_beginning();
+        assert!(transport.is_rewind_pending());
+        transport.drive(&mut manager, &mut ends.transport);
+        assert!(!transport.is_rewind_pending());
+        assert!(manager.is_waiting_for_page());
+        assert!(manager.try_fetching_next_page(&mut ends.transport.load_response_consumer));
+        transport.process(
+            &mut manager,
+            &mut ends.transport,
+            &[[0.0; TRACKS]; 32],
+            &mut output,
+        );
+        assert_eq!(output[0][0], 0.7);
+    }
+
+    #[test]
+    fn armed_tracks_are_sent_once_changed() {
+        let mut manager = Manager::new();
+        let mut channels = queues::Queues::new();
+        let mut ends = channels.split();
+        let mut transport = Transport::new();
+
+        transport.set_recording(2, true);
+        transport.set_recording(2, true);
+        for _ in 0..2 {
+            transport.drive(&mut manager, &mut ends.transport);
+        }
+
+        assert_eq!(
+            ends.config_consumer.dequeue().unwrap().recording,
+            [false, false, true, false]
+        );
+        assert!(ends.config_consumer.dequeue().is_none());
+    }
+
+    #[test]
+    fn rewind_cue_plays_from_the_start_on_its_sample() {
+        static mut POOL: Pool = Pool::new();
+        let pool = unsafe { &mut POOL };
+        let mut channels = queues::Queues::new();
+        let mut ends = channels.split();
+        let mut manager = manager_with_marked_page(pool, &mut ends);
+        let mut transport = Transport::new();
+        transport.toggle_play();
+        let mut output = [[0.0; TRACKS]; 32];
+        transport.process(
+            &mut manager,
+            &mut ends.transport,
+            &[[0.0; TRACKS]; 32],
+            &mut output,
+        );
+
+        // The first page is taken to the cache by the rewind and served
+        // from it again.
+        transport.cue(13, Cue::PlayFromBeginning);
+        transport.process(
+            &mut manager,
+            &mut ends.transport,
+            &[[0.0; TRACKS]; 32],
+            &mut output,
+        );
+
+        let marker = output.iter().position(|frame| frame[0] == 0.7);
+        assert_eq!(marker, Some(13));
+    }
+
+    #[test]
+    fn record_cues_punch_in_and_out_on_their_samples() {
+        static mut POOL: Pool = Pool::new();
+        let pool = unsafe { &mut POOL };
+        let mut channels = queues::Queues::new();
+        let mut ends = channels.split();
+        let mut manager = manager_with_marked_page(pool, &mut ends);
+        let mut transport = Transport::new();
+        transport.toggle_play();
+
+        transport.cue(20, Cue::Record(1, false));
+        transport.cue(5, Cue::Record(1, true));
+        let input: [[f32; TRACKS]; 32] = core::array::from_fn(|i| [i as f32; TRACKS]);
+        let mut output = [[0.0; TRACKS]; 32];
+        transport.process(&mut manager, &mut ends.transport, &input, &mut output);
+        assert!(!transport.is_recording(1));
+
+        // Play the recording back, the second pass outputs what was
+        // recorded in the first one.
+        transport.cue(0, Cue::PlayFromBeginning);
+        transport.process(
+            &mut manager,
+            &mut ends.transport,
+            &[[0.0; TRACKS]; 32],
+            &mut output,
+        );
+        for (i, frame) in output.iter().enumerate() {
+            let expected = if (5..20).contains(&i) { i as f32 } else { 0.0 };
+            assert_eq!(frame[1], expected, "sample {i}");
+        }
+    }
+
+    #[test]
+    fn recording_after_offset_cue_continues_on_the_next_page() {
+        static mut POOL: Pool = Pool::new();
+        let pool = unsafe { &mut POOL };
+        let mut channels = queues::Queues::new();
+        let mut ends = channels.split();
+        let mut manager = manager_with_marked_page(pool, &mut ends);
+        let mut transport = Transport::new();
+        transport.toggle_play();
+        let mut output = [[0.0; TRACKS]; 32];
+        transport.process(
+            &mut manager,
+            &mut ends.transport,
+            &[[0.0; TRACKS]; 32],
+            &mut output,
+        );
+
+        // Each recorded sample holds its index on the tape.
+        let block = |n: usize| -> [[f32; TRACKS]; 32] {
+            core::array::from_fn(|i| [(n * 32 + i) as f32 - 13.0; TRACKS])
+        };
+        transport.cue(0, Cue::Record(0, true));
+        transport.cue(13, Cue::PlayFromBeginning);
+        transport.process(&mut manager, &mut ends.transport, &block(0), &mut output);
+        let request = ends.store.load_request_consumer.dequeue().unwrap();
+        assert_eq!(request.page_id(), PageId::new(CassetteId::new(0), 1));
+        let handle = pool.new_page(request.page_id());
+        ends.store
+            .load_response_producer
+            .enqueue(handle)
+            .ok()
+            .unwrap();
+        for n in 1..=16 {
+            transport.process(&mut manager, &mut ends.transport, &block(n), &mut output);
+        }
+
+        // The first page was saved by the rewind already, and once again as
+        // it got full.
+        let saved = &mut ends.store.save_request_first_page_consumer;
+        assert_eq!(saved.dequeue().unwrap().index(), 0);
+        let first_page = saved.dequeue().unwrap();
+        assert_eq!(first_page.index(), 0);
+        for (i, sample) in first_page.data[0].iter().enumerate() {
+            assert_eq!(*sample, i as f32, "sample {i}");
+        }
+
+        transport.cue(0, Cue::PlayFromBeginning);
+        transport.process(
+            &mut manager,
+            &mut ends.transport,
+            &[[0.0; TRACKS]; 32],
+            &mut output,
+        );
+        let second_page = ends.store.save_request_consumer.dequeue().unwrap();
+        assert_eq!(second_page.page_ref().index(), 1);
+        let recorded = 16 * 32 + 32 - 13 - PAGE_LENGTH;
+        for (i, sample) in second_page.page_ref().data[0].iter().enumerate() {
+            let expected = if i < recorded {
+                (PAGE_LENGTH + i) as f32
+            } else {
+                0.0
+            };
+            assert_eq!(*sample, expected, "sample {i}");
+        }
+        pool.take_page(second_page);
+    }
+
+    #[test]
+    fn cues_past_the_block_are_executed_at_its_end() {
+        static mut POOL: Pool = Pool::new();
+        let pool = unsafe { &mut POOL };
+        let mut channels = queues::Queues::new();
+        let mut ends = channels.split();
+        let mut manager = manager_with_marked_page(pool, &mut ends);
+        let mut transport = Transport::new();
+
+        transport.cue(PAGE_LENGTH, Cue::TogglePlay);
+        let mut output = [[1.0; TRACKS]; 32];
+        transport.process(
+            &mut manager,
+            &mut ends.transport,
+            &[[0.0; TRACKS]; 32],
+            &mut output,
+        );
+
+        assert_eq!(output, [[0.0; TRACKS]; 32]);
+        assert!(transport.is_playing());
+    }
+
+    #[test]
+    fn cues_beyond_capacity_are_dropped() {
+        let mut transport = Transport::new();
+        for offset in 0..MAX_CUES {
+            assert!(transport.cue(offset, Cue::TogglePlay));
+        }
+        assert!(!transport.cue(0, Cue::TogglePlay));
+    }
+}
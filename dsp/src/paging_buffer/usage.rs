@@ -0,0 +1,102 @@
+//! Accounting of space occupied by cassettes.
+
+/// Number of LEDs forming the bar graph of the display.
+pub const LEDS: usize = 8;
+
+/// What is the space used by a cassette compared against.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Budget {
+    /// Capacity of the whole medium.
+    Card,
+    /// Capacity of the medium evenly split between all cassettes.
+    PerCassette,
+}
+
+/// Space occupied by a cassette.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Usage {
+    pub used: usize,
+    pub budget: usize,
+}
+
+impl Usage {
+    pub fn is_empty(&self) -> bool {
+        self.used == 0
+    }
+
+    /// Number of LEDs to be lit on the bar graph.
+    ///
+    /// Any non-empty cassette lights at least a single LED, so it can be
+    /// distinguished from a blank one.
+    pub fn lit_leds(&self) -> usize {
+        if self.is_empty() {
+            return 0;
+        }
+        if self.budget == 0 {
+            return LEDS;
+        }
+        let lit = (self.used as u64 * LEDS as u64).div_ceil(self.budget as u64) as usize;
+        lit.clamp(1, LEDS)
+    }
+
+    pub fn leds(&self) -> [bool; LEDS] {
+        let lit = self.lit_leds();
+        let mut leds = [false; LEDS];
+        for led in leds.iter_mut().take(lit) {
+            *led = true;
+        }
+        leds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_cassette_lights_no_led() {
+        let usage = Usage {
+            used: 0,
+            budget: 1000,
+        };
+        assert_eq!(usage.lit_leds(), 0);
+        assert_eq!(usage.leds(), [false; LEDS]);
+    }
+
+    #[test]
+    fn tiny_cassette_lights_a_single_led() {
+        let usage = Usage {
+            used: 1,
+            budget: 1_000_000_000,
+        };
+        assert_eq!(usage.lit_leds(), 1);
+        assert_eq!(
+            usage.leds(),
+            [true, false, false, false, false, false, false, false]
+        );
+    }
+
+    #[test]
+    fn partially_used_budget_lights_proportional_leds() {
+        let usage = Usage {
+            used: 500,
+            budget: 1000,
+        };
+        assert_eq!(usage.lit_leds(), 4);
+
+        let usage = Usage {
+            used: 501,
+            budget: 1000,
+        };
+        assert_eq!(usage.lit_leds(), 5);
+    }
+
+    #[test]
+    fn exhausted_budget_lights_all_leds() {
+        let usage = Usage {
+            used: 1200,
+            budget: 1000,
+        };
+        assert_eq!(usage.leds(), [true; LEDS]);
+    }
+}
@@ -0,0 +1,640 @@
+//! Global configuration persisted across power cycles.
+//!
+//! The configuration is kept in two alternating slots of the configuration
+//! file. Each write goes to the slot not holding the latest copy, so a write
+//! interrupted by a power loss leaves the previous copy intact. It also
+//! halves the wear of each of the slots.
+//!
+//! Each slot starts with a header carrying the schema version and a sequence
+//! number used to find the latest copy. The payload follows, protected by a
+//! CRC32 together with the header.
+//!
+//! Fields of the payload are only ever appended, each addition bumping the
+//! version. A payload written by an older version is read up to its length
+//! and the missing fields are set to their defaults. Fields introduced
+//! after the stored version, as well as changes of meaning of already
+//! stored fields, are handled in `migrate`.
+
+use crc::{Crc, CRC_32_ISO_HDLC};
+
+use crate::input::{InputStage, Monitoring, Route};
+use crate::paging_buffer::{CassetteId, Error, File, PickupMode, PickupModes, Storage, CASSETTES};
+
+/// Number of CV inputs that can be mapped to controls.
+pub const CV_INPUTS: usize = 2;
+
+/// Number of potentiometers on the panel.
+pub const POTS: usize = 13;
+
+const MAGIC: [u8; 4] = *b"TBCF";
+const VERSION: u8 = 3;
+const SLOTS: usize = 2;
+const SLOT_SIZE: usize = 512;
+const HEADER_SIZE: usize = 12;
+const CHECKSUM_SIZE: usize = 4;
+const MAX_PAYLOAD_SIZE: usize = SLOT_SIZE - HEADER_SIZE - CHECKSUM_SIZE;
+
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// Configuration of the module that is not tied to any cassette.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Save {
+    pub last_cassette: CassetteId,
+    pub mappings: [Option<Mapping>; CV_INPUTS],
+    pub calibration: Calibration,
+    /// Strength of CV inputs mapped to pots, full scale of the input
+    /// offsets the pot by this much. Negative ranges invert the input.
+    pub mapping_ranges: [f32; CV_INPUTS],
+    /// Button the gate input acts as, playing from the beginning if unset.
+    pub gate_mapping: Option<Mapping>,
+    /// How knobs take over parameters recovered from a cassette.
+    pub pickup_modes: PickupModes,
+    /// Whether LEDs show tracks with parameters waiting for pickup.
+    pub pickup_indication: bool,
+    /// Routing of the input to tracks and its monitoring.
+    pub input: InputStage,
+}
+
+/// Control driven by a CV input.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Mapping {
+    /// Index of the pot, as ordered in `Calibration::pots`.
+    Pot(u8),
+    /// Index of the button on the panel.
+    Button(u8),
+}
+
+/// Corrections of analog inputs measured during calibration.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Calibration {
+    pub pots: [Linear; POTS],
+    pub cv: [Linear; CV_INPUTS],
+}
+
+/// Linear correction, applied as `raw * scale + offset`.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Linear {
+    pub offset: f32,
+    pub scale: f32,
+}
+
+impl Default for Save {
+    fn default() -> Self {
+        Self {
+            last_cassette: CassetteId::new(0),
+            mappings: [None; CV_INPUTS],
+            calibration: Calibration::default(),
+            mapping_ranges: [1.0; CV_INPUTS],
+            gate_mapping: None,
+            pickup_modes: PickupModes::default(),
+            pickup_indication: false,
+            input: InputStage::default(),
+        }
+    }
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self {
+            pots: [Linear::default(); POTS],
+            cv: [Linear::default(); CV_INPUTS],
+        }
+    }
+}
+
+impl Default for Linear {
+    fn default() -> Self {
+        Self {
+            offset: 0.0,
+            scale: 1.0,
+        }
+    }
+}
+
+impl Linear {
+    pub fn apply(&self, raw: f32) -> f32 {
+        raw * self.scale + self.offset
+    }
+}
+
+impl Save {
+    fn write_payload(&self, writer: &mut Writer) {
+        writer.u8(self.last_cassette.index() as u8);
+        for mapping in self.mappings.iter() {
+            write_mapping(writer, mapping);
+        }
+        for linear in self
+            .calibration
+            .pots
+            .iter()
+            .chain(self.calibration.cv.iter())
+        {
+            writer.f32(linear.offset);
+            writer.f32(linear.scale);
+        }
+        for range in self.mapping_ranges.iter() {
+            writer.f32(*range);
+        }
+        write_mapping(writer, &self.gate_mapping);
+        for mode in pickup_modes(&self.pickup_modes) {
+            writer.u8(mode.to_u8());
+        }
+        writer.u8(self.pickup_indication as u8);
+        for route in self.input.routes.iter() {
+            writer.u8(route.to_u8());
+        }
+        writer.u8(self.input.monitoring.to_u8());
+        writer.f32(self.input.monitor_level);
+    }
+
+    fn read_payload(reader: &mut Reader) -> Self {
+        let mut save = Save::default();
+
+        if let Some(index) = reader.u8() {
+            if (index as usize) < CASSETTES {
+                save.last_cassette = CassetteId::new(index as usize);
+            }
+        }
+        for mapping in save.mappings.iter_mut() {
+            if let Some(stored) = read_mapping(reader) {
+                *mapping = stored;
+            }
+        }
+        let calibration = &mut save.calibration;
+        for linear in calibration.pots.iter_mut().chain(calibration.cv.iter_mut()) {
+            if let (Some(offset), Some(scale)) = (reader.f32(), reader.f32()) {
+                *linear = Linear { offset, scale };
+            }
+        }
+        for range in save.mapping_ranges.iter_mut() {
+            if let Some(value) = reader.f32() {
+                *range = value;
+            }
+        }
+        if let Some(stored) = read_mapping(reader) {
+            save.gate_mapping = stored;
+        }
+        let modes = &mut save.pickup_modes;
+        for mode in [
+            &mut modes.volume,
+            &mut modes.pan,
+            &mut modes.strength,
+            &mut modes.pitch,
+        ] {
+            if let Some(stored) = reader.u8().and_then(PickupMode::from_u8) {
+                *mode = stored;
+            }
+        }
+        if let Some(indication) = reader.u8() {
+            save.pickup_indication = indication != 0;
+        }
+        for route in save.input.routes.iter_mut() {
+            if let Some(stored) = reader.u8().and_then(Route::from_u8) {
+                *route = stored;
+            }
+        }
+        if let Some(stored) = reader.u8().and_then(Monitoring::from_u8) {
+            save.input.monitoring = stored;
+        }
+        if let Some(level) = reader.f32() {
+            save.input.monitor_level = level;
+        }
+
+        save
+    }
+
+    fn to_slot(self, sequence: u32) -> [u8; SLOT_SIZE] {
+        let mut slot = [0; SLOT_SIZE];
+        let mut writer = Writer {
+            bytes: &mut slot[HEADER_SIZE..HEADER_SIZE + MAX_PAYLOAD_SIZE],
+            position: 0,
+        };
+        self.write_payload(&mut writer);
+        let payload_size = writer.position;
+
+        slot[..4].copy_from_slice(&MAGIC);
+        slot[4] = VERSION;
+        slot[6..8].copy_from_slice(&(payload_size as u16).to_le_bytes());
+        slot[8..12].copy_from_slice(&sequence.to_le_bytes());
+        let end = HEADER_SIZE + payload_size;
+        let checksum = CRC.checksum(&slot[..end]);
+        slot[end..end + CHECKSUM_SIZE].copy_from_slice(&checksum.to_le_bytes());
+        slot
+    }
+
+    /// Parse the slot, returning the configuration and its sequence number.
+    fn from_slot(slot: &[u8; SLOT_SIZE]) -> Option<(Self, u32)> {
+        if slot[..4] != MAGIC {
+            return None;
+        }
+        let version = slot[4];
+        if version == 0 || version > VERSION {
+            return None;
+        }
+        let payload_size = u16::from_le_bytes([slot[6], slot[7]]) as usize;
+        if payload_size > MAX_PAYLOAD_SIZE {
+            return None;
+        }
+        let end = HEADER_SIZE + payload_size;
+        let checksum = u32::from_le_bytes(slot[end..end + CHECKSUM_SIZE].try_into().unwrap());
+        if CRC.checksum(&slot[..end]) != checksum {
+            return None;
+        }
+        let sequence = u32::from_le_bytes(slot[8..12].try_into().unwrap());
+
+        let mut reader = Reader {
+            bytes: &slot[HEADER_SIZE..end],
+            position: 0,
+        };
+        let mut save = Self::read_payload(&mut reader);
+        migrate(version, &mut save);
+        Some((save, sequence))
+    }
+}
+
+fn write_mapping(writer: &mut Writer, mapping: &Option<Mapping>) {
+    let (tag, target) = match mapping {
+        None => (0, 0),
+        Some(Mapping::Pot(pot)) => (1, *pot),
+        Some(Mapping::Button(button)) => (2, *button),
+    };
+    writer.u8(tag);
+    writer.u8(target);
+}
+
+fn pickup_modes(modes: &PickupModes) -> [PickupMode; 4] {
+    [modes.volume, modes.pan, modes.strength, modes.pitch]
+}
+
+/// Read a mapping, returning `None` past the end of the payload.
+fn read_mapping(reader: &mut Reader) -> Option<Option<Mapping>> {
+    let (tag, target) = (reader.u8()?, reader.u8()?);
+    Some(match tag {
+        1 => Some(Mapping::Pot(target)),
+        2 => Some(Mapping::Button(target)),
+        _ => None,
+    })
+}
+
+/// Adjust configuration stored by an older version of the firmware.
+///
+/// Version 2 introduced pickup modes and version 3 the input stage. Fields
+/// newer than the stored version are reset, whatever the payload held.
+fn migrate(version: u8, save: &mut Save) {
+    debug_assert!(version <= VERSION);
+    if version < 2 {
+        save.pickup_modes = PickupModes::default();
+        save.pickup_indication = false;
+    }
+    if version < 3 {
+        // The input was not monitored before, keep it that way.
+        save.input = InputStage {
+            monitoring: Monitoring::Never,
+            ..InputStage::default()
+        };
+    }
+}
+
+struct Writer<'a> {
+    bytes: &'a mut [u8],
+    position: usize,
+}
+
+impl Writer<'_> {
+    fn u8(&mut self, value: u8) {
+        self.bytes[self.position] = value;
+        self.position += 1;
+    }
+
+    fn f32(&mut self, value: f32) {
+        self.bytes[self.position..self.position + 4].copy_from_slice(&value.to_le_bytes());
+        self.position += 4;
+    }
+}
+
+/// Reads fields of the payload, returning `None` past its end.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Reader<'_> {
+    fn u8(&mut self) -> Option<u8> {
+        let value = *self.bytes.get(self.position)?;
+        self.position += 1;
+        Some(value)
+    }
+
+    fn f32(&mut self) -> Option<f32> {
+        let bytes = self.bytes.get(self.position..self.position + 4)?;
+        self.position += 4;
+        Some(f32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+}
+
+/// Keeps track of the persisted configuration.
+pub struct Persistence {
+    sequence: u32,
+    latest_slot: usize,
+    persisted: Option<Save>,
+}
+
+impl Persistence {
+    /// Recover the latest valid configuration from the storage.
+    ///
+    /// Defaults are returned if none of the slots holds a valid copy.
+    pub fn load<S: Storage>(storage: &mut S) -> (Self, Save) {
+        let mut latest: Option<(Save, u32, usize)> = None;
+        for index in 0..SLOTS {
+            let mut slot = [0; SLOT_SIZE];
+            if storage
+                .read(File::Config, index * SLOT_SIZE, &mut slot)
+                .is_err()
+            {
+                continue;
+            }
+            if let Some((save, sequence)) = Save::from_slot(&slot) {
+                let newer = latest.is_none_or(|(_, latest_sequence, _)| {
+                    sequence.wrapping_sub(latest_sequence) as i32 > 0
+                });
+                if newer {
+                    latest = Some((save, sequence, index));
+                }
+            }
+        }
+
+        match latest {
+            Some((save, sequence, index)) => (
+                Self {
+                    sequence,
+                    latest_slot: index,
+                    persisted: Some(save),
+                },
+                save,
+            ),
+            None => (
+                Self {
+                    sequence: 0,
+                    latest_slot: SLOTS - 1,
+                    persisted: None,
+                },
+                Save::default(),
+            ),
+        }
+    }
+
+    /// Store the configuration, unless it matches the persisted one.
+    ///
+    /// Callers are expected to debounce frequent changes, e.g. using
+    /// `paging_buffer::Debounce`.
+    pub fn save<S: Storage>(&mut self, storage: &mut S, save: &Save) -> Result<(), Error> {
+        if self.persisted.as_ref() == Some(save) {
+            return Ok(());
+        }
+
+        let sequence = self.sequence.wrapping_add(1);
+        let slot_index = (self.latest_slot + 1) % SLOTS;
+        storage.write(
+            File::Config,
+            slot_index * SLOT_SIZE,
+            &save.to_slot(sequence),
+        )?;
+
+        self.sequence = sequence;
+        self.latest_slot = slot_index;
+        self.persisted = Some(*save);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::paging_buffer::memory::MemoryStorage;
+
+    use super::*;
+
+    fn custom_save() -> Save {
+        let mut save = Save {
+            last_cassette: CassetteId::new(5),
+            mappings: [Some(Mapping::Pot(3)), Some(Mapping::Button(1))],
+            mapping_ranges: [-0.4, 1.0],
+            gate_mapping: Some(Mapping::Button(2)),
+            pickup_modes: PickupModes {
+                volume: PickupMode::Scaled,
+                pitch: PickupMode::Jump,
+                ..PickupModes::default()
+            },
+            pickup_indication: true,
+            input: InputStage {
+                routes: [Route::Stereo, Route::Stereo, Route::Left, Route::Sum],
+                monitoring: Monitoring::Always,
+                monitor_level: 0.7,
+            },
+            ..Save::default()
+        };
+        save.calibration.pots[12] = Linear {
+            offset: -0.01,
+            scale: 1.02,
+        };
+        save.calibration.cv[1] = Linear {
+            offset: 0.1,
+            scale: 0.98,
+        };
+        save
+    }
+
+    #[test]
+    fn configuration_survives_serialization() {
+        let save = custom_save();
+        assert_eq!(Save::from_slot(&save.to_slot(7)), Some((save, 7)));
+    }
+
+    #[test]
+    fn load_defaults_from_empty_storage() {
+        let mut storage = MemoryStorage::default();
+        let (_, save) = Persistence::load(&mut storage);
+        assert_eq!(save, Save::default());
+    }
+
+    #[test]
+    fn load_the_latest_saved_configuration() {
+        let mut storage = MemoryStorage::default();
+        let (mut persistence, _) = Persistence::load(&mut storage);
+
+        persistence.save(&mut storage, &Save::default()).unwrap();
+        persistence.save(&mut storage, &custom_save()).unwrap();
+        let mut latest = custom_save();
+        latest.last_cassette = CassetteId::new(2);
+        persistence.save(&mut storage, &latest).unwrap();
+
+        let (_, save) = Persistence::load(&mut storage);
+        assert_eq!(save, latest);
+    }
+
+    #[test]
+    fn interrupted_write_keeps_previous_configuration() {
+        let mut storage = MemoryStorage::default();
+        let (mut persistence, _) = Persistence::load(&mut storage);
+        persistence.save(&mut storage, &custom_save()).unwrap();
+
+        // Simulate a torn write of the following copy.
+        let mut newer = custom_save();
+        newer.last_cassette = CassetteId::new(1);
+        persistence.save(&mut storage, &newer).unwrap();
+        storage.file_mut(File::Config)[SLOT_SIZE + HEADER_SIZE] ^= 0xFF;
+
+        let (_, save) = Persistence::load(&mut storage);
+        assert_eq!(save, custom_save());
+    }
+
+    #[test]
+    fn unchanged_configuration_is_not_written() {
+        let mut storage = MemoryStorage::default();
+        let (mut persistence, _) = Persistence::load(&mut storage);
+        persistence.save(&mut storage, &custom_save()).unwrap();
+
+        storage.cut_power_after(0);
+        assert!(persistence.save(&mut storage, &custom_save()).is_ok());
+    }
+
+    #[test]
+    fn sequence_wraps_around() {
+        let mut storage = MemoryStorage::default();
+        let (mut persistence, _) = Persistence::load(&mut storage);
+        persistence.sequence = u32::MAX - 1;
+
+        persistence.save(&mut storage, &Save::default()).unwrap();
+        persistence.save(&mut storage, &custom_save()).unwrap();
+        assert_eq!(persistence.sequence, 0);
+
+        let (_, save) = Persistence::load(&mut storage);
+        assert_eq!(save, custom_save());
+    }
+
+    #[test]
+    fn missing_fields_of_shorter_payload_are_defaulted() {
+        // Only the last cassette and the mappings, as if written before
+        // calibration was appended.
+        let mut slot = custom_save().to_slot(1);
+        let payload_size = 1 + 2 * CV_INPUTS;
+        slot[6..8].copy_from_slice(&(payload_size as u16).to_le_bytes());
+        let end = HEADER_SIZE + payload_size;
+        let checksum = CRC.checksum(&slot[..end]);
+        slot[end..end + CHECKSUM_SIZE].copy_from_slice(&checksum.to_le_bytes());
+
+        let (save, _) = Save::from_slot(&slot).unwrap();
+        assert_eq!(save.last_cassette, custom_save().last_cassette);
+        assert_eq!(save.mappings, custom_save().mappings);
+        assert_eq!(save.calibration, Calibration::default());
+        assert_eq!(save.mapping_ranges, Save::default().mapping_ranges);
+        assert_eq!(save.gate_mapping, None);
+        assert_eq!(save.pickup_modes, PickupModes::default());
+        assert!(!save.pickup_indication);
+        assert_eq!(save.input, InputStage::default());
+    }
+
+    /// Slot of the given version holding the payload.
+    fn slot_with_payload(version: u8, payload: &[u8]) -> [u8; SLOT_SIZE] {
+        let mut slot = [0; SLOT_SIZE];
+        slot[..4].copy_from_slice(&MAGIC);
+        slot[4] = version;
+        slot[6..8].copy_from_slice(&(payload.len() as u16).to_le_bytes());
+        slot[8..12].copy_from_slice(&3_u32.to_le_bytes());
+        let end = HEADER_SIZE + payload.len();
+        slot[HEADER_SIZE..end].copy_from_slice(payload);
+        let checksum = CRC.checksum(&slot[..end]);
+        slot[end..end + CHECKSUM_SIZE].copy_from_slice(&checksum.to_le_bytes());
+        slot
+    }
+
+    #[test]
+    fn configuration_of_version_2_is_migrated() {
+        let mut payload = [0; MAX_PAYLOAD_SIZE];
+        let mut writer = Writer {
+            bytes: &mut payload,
+            position: 0,
+        };
+        // Last cassette, CV mappings and calibration of version 1.
+        writer.u8(5);
+        writer.u8(1);
+        writer.u8(3);
+        writer.u8(0);
+        writer.u8(0);
+        for _ in 0..POTS + CV_INPUTS {
+            writer.f32(0.1);
+            writer.f32(0.9);
+        }
+        // Mapping ranges and the gate mapping, appended within version 1.
+        writer.f32(-0.4);
+        writer.f32(1.0);
+        writer.u8(2);
+        writer.u8(2);
+        // Pickup modes and indication of version 2.
+        for mode in [
+            PickupMode::Scaled,
+            PickupMode::PassThrough,
+            PickupMode::Jump,
+            PickupMode::Jump,
+        ] {
+            writer.u8(mode.to_u8());
+        }
+        writer.u8(1);
+        let length = writer.position;
+
+        let (save, sequence) = Save::from_slot(&slot_with_payload(2, &payload[..length])).unwrap();
+        assert_eq!(sequence, 3);
+
+        // Fields of the stored version are preserved.
+        let linear = Linear {
+            offset: 0.1,
+            scale: 0.9,
+        };
+        assert_eq!(save.last_cassette, CassetteId::new(5));
+        assert_eq!(save.mappings, [Some(Mapping::Pot(3)), None]);
+        assert_eq!(save.calibration.pots, [linear; POTS]);
+        assert_eq!(save.calibration.cv, [linear; CV_INPUTS]);
+        assert_eq!(save.mapping_ranges, [-0.4, 1.0]);
+        assert_eq!(save.gate_mapping, Some(Mapping::Button(2)));
+        assert_eq!(
+            save.pickup_modes,
+            PickupModes {
+                volume: PickupMode::Scaled,
+                pan: PickupMode::PassThrough,
+                strength: PickupMode::Jump,
+                pitch: PickupMode::Jump,
+            }
+        );
+        assert!(save.pickup_indication);
+
+        // The input stage of version 3 keeps the input unmonitored.
+        assert_eq!(save.input.monitoring, Monitoring::Never);
+        assert_eq!(save.input.routes, InputStage::default().routes);
+    }
+
+    #[test]
+    fn fields_newer_than_the_stored_version_are_reset() {
+        // Pickup fields present in a payload of version 1.
+        let mut slot = custom_save().to_slot(1);
+        let payload_size = u16::from_le_bytes([slot[6], slot[7]]) as usize;
+        let payload = slot[HEADER_SIZE..HEADER_SIZE + payload_size].to_vec();
+        slot = slot_with_payload(1, &payload);
+
+        let (save, _) = Save::from_slot(&slot).unwrap();
+        assert_eq!(save.calibration, custom_save().calibration);
+        assert_eq!(save.gate_mapping, custom_save().gate_mapping);
+        assert_eq!(save.pickup_modes, PickupModes::default());
+        assert!(!save.pickup_indication);
+        assert_eq!(save.input.monitoring, Monitoring::Never);
+    }
+
+    #[test]
+    fn configuration_of_newer_version_is_ignored() {
+        let mut slot = custom_save().to_slot(1);
+        slot[4] = VERSION + 1;
+        let end = HEADER_SIZE + u16::from_le_bytes([slot[6], slot[7]]) as usize;
+        let checksum = CRC.checksum(&slot[..end]);
+        slot[end..end + CHECKSUM_SIZE].copy_from_slice(&checksum.to_le_bytes());
+
+        assert_eq!(Save::from_slot(&slot), None);
+    }
+}
@@ -0,0 +1,77 @@
+//! Measurement of tape saturation on the target.
+//!
+//! Processes blocks of audio with tape on every track and reports the
+//! cycles taken, counted by DWT, relative to the budget of the audio
+//! routine. Run it with `make bench-tape`.
+
+#![no_main]
+#![no_std]
+#![allow(clippy::no_mangle_with_rust_abi)] // rtic::app fails this.
+
+use placeholder_firmware as _; // Global logger and panicking behavior.
+
+#[rtic::app(device = stm32h7xx_hal::pac, peripherals = true)]
+mod app {
+    use cortex_m::peripheral::DWT;
+
+    use placeholder_dsp::effect::{Effect, Tape};
+    use placeholder_dsp::paging_buffer::TRACKS;
+    use placeholder_firmware::system::System;
+
+    const SAMPLE_RATE: u32 = 48_000;
+    const BLOCK: usize = 32;
+    const BLOCKS: u32 = 1000;
+
+    #[shared]
+    struct Shared {}
+
+    #[local]
+    struct Local {}
+
+    #[init]
+    fn init(cx: init::Context) -> (Shared, Local, init::Monotonics) {
+        let mut core = cx.core;
+        core.DCB.enable_trace();
+        core.DWT.enable_cycle_counter();
+        let system = System::init(core, cx.device);
+        let budget = system.system_clock.raw() / SAMPLE_RATE * BLOCK as u32;
+
+        let mut tapes = [Tape::default(); TRACKS];
+        for tape in tapes.iter_mut() {
+            tape.set_strength(0.8);
+        }
+        let mut phase = 0.0_f32;
+        let mut block = [0.0; BLOCK];
+
+        let mut cycles = 0;
+        for _ in 0..BLOCKS {
+            for tape in tapes.iter_mut() {
+                for x in block.iter_mut() {
+                    phase = (phase + 0.01) % 1.0;
+                    *x = phase * 2.0 - 1.0;
+                }
+                let start = DWT::cycle_count();
+                tape.process(&mut block);
+                cycles += DWT::cycle_count().wrapping_sub(start) as u64;
+            }
+        }
+
+        let per_block = (cycles / BLOCKS as u64) as u32;
+        defmt::info!(
+            "Tape on all tracks takes {} cycles per block of {} samples, {}.{}% of the budget",
+            per_block,
+            BLOCK,
+            per_block * 100 / budget,
+            per_block * 1000 / budget % 10,
+        );
+
+        (Shared {}, Local {}, init::Monotonics())
+    }
+
+    #[idle]
+    fn idle(_: idle::Context) -> ! {
+        loop {
+            cortex_m::asm::nop();
+        }
+    }
+}
@@ -6,38 +6,526 @@ use placeholder_firmware as _; // Global logger and panicking behavior.
 
 #[rtic::app(device = stm32h7xx_hal::pac, peripherals = true, dispatchers = [EXTI0, EXTI1, EXTI2])]
 mod app {
+    use core::mem::MaybeUninit;
+
+    use daisy::audio::Interface;
     use daisy::led::LedUser;
     use fugit::ExtU64;
     use systick_monotonic::Systick;
 
-    use placeholder_firmware::system::System;
+    use placeholder_control::bindings::Bindings;
+    use placeholder_control::calibration::{self, Calibrator, Outcome, STEPS};
+    use placeholder_control::cv::Mapper;
+    use placeholder_control::gate::{Edge, Gate};
+    use placeholder_control::gestures::{Gestures, Timings, BUTTONS};
+    use placeholder_control::pots::{Pots, Snapshot};
+    use placeholder_control::switch::CassetteSwitch;
+    use placeholder_dsp::effect::Rack;
+    use placeholder_dsp::mixer::{Mixer, CHANNELS};
+    use placeholder_dsp::paging_buffer::{
+        self, BlockStorage, CassetteId, Debounce, Deck, Library, Pool, Queues, SampleFormat,
+        Selector, Settings, SettingsPickup, SettingsQueue, Transport, Usage, TRACKS,
+    };
+    use placeholder_dsp::save::{Calibration, Persistence, Save};
+    use placeholder_firmware::reset::reset_on_request;
+    use placeholder_firmware::system::buttons::{button_on_channel, Buttons};
+    use placeholder_firmware::system::gate::GateInput;
+    use placeholder_firmware::system::leds::{Leds, LEDS};
+    use placeholder_firmware::system::multiplexer::Multiplexer;
+    use placeholder_firmware::system::sample_clock::SampleClock;
+    use placeholder_firmware::system::sd::Sd;
+    use placeholder_firmware::system::{pots, System};
 
     // Blinks on the PCB's LED signalize the revision.
     const BLINKS: u8 = 1;
 
+    // A single channel of the multiplexers is read per tick, so a snapshot
+    // of all the pots gets published and buttons are evaluated at 125 Hz.
+    const CONTROL_TICK_MS: u64 = 1;
+
+    // Samples processed by the audio routine at once. Gate edges are cued
+    // within the block being captured when they arrive.
+    const BLOCK_LENGTH: usize = 32;
+
+    // The configuration and settings of the cassette are written once they
+    // stay unchanged for this many control ticks, sparing the SD card while
+    // knobs are being turned.
+    const SAVE_DEBOUNCE_TICKS: u32 = 2000;
+
+    // Space occupied by a newly selected cassette is shown for this many
+    // control ticks.
+    const USAGE_DISPLAY_TICKS: u32 = 2000;
+
+    // Pages of the pool would not fit into the flash as initialized data.
+    // The pool is therefore kept in the AXI SRAM, initialized in `init`.
+    #[link_section = ".sram"]
+    static mut POOL: MaybeUninit<Pool> = MaybeUninit::uninit();
+
     // 1 kHz / 1 ms granularity for task scheduling.
     #[monotonic(binds = SysTick, default = true)]
     type Mono = Systick<1000>;
 
     #[shared]
-    struct Shared {}
+    struct Shared {
+        snapshot: Snapshot,
+        cassette: CassetteId,
+        transport: Transport,
+        gate_action: Gate,
+        gate_high: bool,
+        save: Save,
+        // Mix settings of the cassette, reconciled with the knobs.
+        settings: Settings,
+        // Settings read from a newly selected cassette, waiting to be
+        // picked up by the knobs.
+        recovered: Option<Settings>,
+        // Space occupied by a newly selected cassette, waiting to be shown.
+        usage: Option<Usage>,
+        // Accessed only by tasks of the lowest priority.
+        #[lock_free]
+        library: Option<(Library<'static, BlockStorage<Sd>>, Persistence)>,
+    }
 
     #[local]
     struct Local {
         status_led: LedUser,
+        audio: Interface,
+        deck: Option<Deck<'static>>,
+        rack: Rack,
+        mixer: Mixer,
+        multiplexer: Multiplexer,
+        pot_inputs: pots::Pots,
+        pots: Pots,
+        buttons: Buttons,
+        gate: GateInput,
+        sample_clock: SampleClock,
+        pressed: [bool; BUTTONS],
+        gestures: Gestures,
+        bindings: Bindings,
+        mapper: Mapper,
+        calibrator: Option<Calibrator>,
+        leds: Leds,
+        cassette_switch: CassetteSwitch,
+        selector: Selector,
+        save_debounce: Debounce<()>,
+        settings_pickup: SettingsPickup,
+        settings_debounce: Debounce<Settings>,
+        settings_queue: Option<SettingsQueue<'static>>,
+        usage_display: Option<([bool; LEDS], u32)>,
     }
 
-    #[init]
+    #[init(local = [queues: Queues = Queues::new()])]
     fn init(cx: init::Context) -> (Shared, Local, init::Monotonics) {
         defmt::info!("Starting the firmware, initializing resources");
 
-        let system = System::init(cx.core, cx.device);
+        let mut system = System::init(cx.core, cx.device);
+
+        let storage = reset_on_request(
+            system.card,
+            &mut system.buttons,
+            &mut system.multiplexer,
+            &mut system.leds,
+            system.system_clock.raw(),
+        );
+
+        // Without a card, the tape stays silent and only the input gets
+        // monitored.
+        let mut transport = Transport::new();
+        let (library, deck, settings_queue, save) = match storage {
+            Some(mut storage) => {
+                let (persistence, save) = Persistence::load(&mut storage);
+                // SAFETY: `init` runs only once, before any task that could
+                // access the pool through the paging buffer.
+                let slot = &raw mut POOL;
+                let pool = unsafe { (*slot).write(Pool::new()) };
+                let (deck, library, settings_queue) =
+                    paging_buffer::split(cx.local.queues, pool, storage, SampleFormat::I24);
+                transport.switch_cassette(save.last_cassette);
+                (
+                    Some((library, persistence)),
+                    Some(deck),
+                    Some(settings_queue),
+                    save,
+                )
+            }
+            None => (None, None, None, Save::default()),
+        };
+        // Settings of the cassette arrive once it gets loaded.
+        let settings = Settings::default();
+
         let mono = system.mono;
         let status_led = system.status_led;
+        let mut multiplexer = system.multiplexer;
+        let pots = Pots::new(&save.calibration);
+        multiplexer.select(pots.channel());
 
         blink::spawn(true, BLINKS).unwrap();
+        control::spawn_after(CONTROL_TICK_MS.millis()).unwrap();
+        let audio = system.audio.spawn().unwrap();
+
+        (
+            Shared {
+                snapshot: Snapshot::default(),
+                cassette: save.last_cassette,
+                transport,
+                gate_action: Gate::new(save.gate_mapping),
+                gate_high: false,
+                save,
+                settings,
+                recovered: None,
+                usage: None,
+                library,
+            },
+            Local {
+                status_led,
+                audio,
+                deck,
+                rack: Rack::new(),
+                mixer: Mixer::new(),
+                multiplexer,
+                pot_inputs: system.pots,
+                pots,
+                buttons: system.buttons,
+                gate: system.gate,
+                sample_clock: system.sample_clock,
+                pressed: [false; BUTTONS],
+                gestures: Gestures::new(Timings::default()),
+                bindings: Bindings::new(),
+                mapper: Mapper::new(&save),
+                calibrator: None,
+                leds: system.leds,
+                cassette_switch: CassetteSwitch::new(),
+                selector: Selector::new(save.last_cassette),
+                save_debounce: Debounce::new(SAVE_DEBOUNCE_TICKS),
+                settings_pickup: SettingsPickup::new(settings, &save.pickup_modes),
+                settings_debounce: Debounce::new(SAVE_DEBOUNCE_TICKS),
+                settings_queue,
+                usage_display: None,
+            },
+            init::Monotonics(mono),
+        )
+    }
+
+    #[task(
+        local = [
+            multiplexer,
+            pot_inputs,
+            pots,
+            buttons,
+            pressed,
+            gestures,
+            bindings,
+            mapper,
+            calibrator,
+            leds,
+            cassette_switch,
+            selector,
+            save_debounce,
+            settings_pickup,
+            settings_debounce,
+            settings_queue,
+            usage_display
+        ],
+        shared = [
+            snapshot,
+            cassette,
+            transport,
+            gate_action,
+            gate_high,
+            save,
+            settings,
+            recovered,
+            usage
+        ]
+    )]
+    fn control(mut cx: control::Context) {
+        control::spawn_after(CONTROL_TICK_MS.millis()).unwrap();
 
-        (Shared {}, Local { status_led }, init::Monotonics(mono))
+        let pot_inputs = cx.local.pot_inputs;
+        let pots = cx.local.pots;
+
+        // The channel was selected at the end of the previous tick, it had
+        // plenty of time to settle.
+        if let Some(button) = button_on_channel(pots.channel()) {
+            cx.local.pressed[button.index()] = cx.local.buttons.is_selected_pressed();
+        }
+        let multiplexed = pot_inputs.read_multiplexed();
+        let snapshot = pots.step(multiplexed, || pot_inputs.read_direct());
+        cx.local.multiplexer.select(pots.channel());
+
+        if let Some(snapshot) = snapshot {
+            if let Some(calibrator) = cx.local.calibrator.as_mut() {
+                calibrator.update(&snapshot, pot_inputs.read_cv());
+                let mut outcome = None;
+                for event in cx.local.gestures.update(*cx.local.pressed, &snapshot) {
+                    outcome = outcome.or(calibrator.apply(event));
+                }
+                let lit = (calibrator.step().index() + 1) * LEDS / STEPS;
+                cx.local.leds.set(core::array::from_fn(|led| led < lit));
+
+                if let Some(outcome) = outcome {
+                    let save_debounce = &mut *cx.local.save_debounce;
+                    let calibration = cx.shared.save.lock(|save| {
+                        if let Outcome::Finished(calibration) = outcome {
+                            defmt::info!("Calibration finished");
+                            save.calibration = calibration;
+                            save_debounce.set(());
+                        } else {
+                            defmt::info!("Calibration canceled");
+                        }
+                        save.calibration
+                    });
+                    pots.set_calibration(&calibration);
+                    cx.local.mapper.set_calibration(&calibration);
+                    cx.local.leds.set_all(false);
+                    *cx.local.calibrator = None;
+                }
+            } else {
+                let mapper = &mut *cx.local.mapper;
+                mapper.update(pot_inputs.read_cv(), &snapshot);
+                mapper.update_gate(cx.shared.gate_high.lock(|high| *high));
+                cx.shared
+                    .snapshot
+                    .lock(|shared| *shared = mapper.modulate(&snapshot));
+
+                // Gestures are recognized on the knobs as set on the panel, so
+                // modulation by CV does not trigger combos.
+                let mut pressed = *cx.local.pressed;
+                mapper.press(&mut pressed);
+                let events = cx.local.gestures.update(pressed, &snapshot);
+                if events.iter().any(|event| calibration::is_requested(*event)) {
+                    defmt::info!("Starting calibration");
+                    // The calibrator measures raw readings.
+                    pots.set_calibration(&Calibration::default());
+                    let previous = cx.shared.save.lock(|save| save.calibration);
+                    *cx.local.calibrator = Some(Calibrator::new(&previous));
+                }
+                let bindings = &mut *cx.local.bindings;
+                let pickup = &mut *cx.local.settings_pickup;
+                cx.shared.transport.lock(|transport| {
+                    for event in events {
+                        bindings.apply(event, transport, mapper, pickup, &snapshot);
+                    }
+                });
+
+                let save_debounce = &mut *cx.local.save_debounce;
+                cx.shared.save.lock(|save| {
+                    let changed = save.mappings != mapper.mappings()
+                        || save.mapping_ranges != mapper.ranges()
+                        || save.gate_mapping != mapper.gate_mapping();
+                    if changed {
+                        save.mappings = mapper.mappings();
+                        save.mapping_ranges = mapper.ranges();
+                        save.gate_mapping = mapper.gate_mapping();
+                        save_debounce.set(());
+                    }
+                });
+                let gate_action = Gate::new(mapper.gate_mapping());
+                cx.shared.gate_action.lock(|shared| *shared = gate_action);
+
+                let pickup = &mut *cx.local.settings_pickup;
+                if let Some(recovered) = cx.shared.recovered.lock(Option::take) {
+                    let modes = cx.shared.save.lock(|save| save.pickup_modes);
+                    *pickup = SettingsPickup::new(recovered, &modes);
+                    // Changes made before the recovery belonged to the
+                    // previous cassette, they were flushed to it already.
+                    cx.local.settings_debounce.flush();
+                }
+                let previous = pickup.settings();
+                let settings = pickup.update(&snapshot.knobs());
+                if settings != previous {
+                    cx.local.settings_debounce.set(settings);
+                }
+                cx.shared.settings.lock(|shared| *shared = settings);
+            }
+        }
+        let reading = pot_inputs.read_cassette_switch();
+        cx.local.cassette_switch.update(reading);
+        let bank_held = cx.local.bindings.is_bank_held();
+        if let Some(position) = cx.local.cassette_switch.position() {
+            if let Some(cassette) = cx.local.selector.update(position, bank_held) {
+                defmt::info!("Selected cassette {}", cassette.index());
+                let outgoing = cx
+                    .shared
+                    .cassette
+                    .lock(|shared| core::mem::replace(shared, cassette));
+                // Queued ahead of the switch, so they are saved before the
+                // cassette gets backed up.
+                if let Some(settings) = cx.local.settings_debounce.flush() {
+                    store_settings(cx.local.settings_queue, outgoing, settings);
+                }
+                cx.shared.save.lock(|save| save.last_cassette = cassette);
+                cx.local.save_debounce.set(());
+                let bindings = &mut *cx.local.bindings;
+                cx.shared.transport.lock(|transport| {
+                    bindings.cassette_changed(transport);
+                    transport.switch_cassette(cassette);
+                });
+                // Fails only while a measurement is still queued, which then
+                // reads the latest cassette anyway.
+                measure_usage::spawn().ok();
+            }
+        }
+        let usage_display = &mut *cx.local.usage_display;
+        if let Some(usage) = cx.shared.usage.lock(Option::take) {
+            *usage_display = Some((usage.leds(), USAGE_DISPLAY_TICKS));
+        }
+        let usage_leds = usage_display.map(|(leds, _)| leds);
+        *usage_display =
+            usage_display.and_then(|(leds, ticks_left)| Some((leds, ticks_left.checked_sub(1)?)));
+        if cx.local.calibrator.is_none() {
+            // The bank is shown for as long as it can be selected, then the
+            // space occupied by a newly selected cassette for a while, and
+            // tracks waiting for their knobs otherwise, if enabled.
+            let indication = cx.shared.save.lock(|save| save.pickup_indication);
+            let waiting = cx.local.settings_pickup.waiting_tracks();
+            let leds = cx
+                .local
+                .selector
+                .bank_leds()
+                .or(usage_leds)
+                .unwrap_or_else(|| {
+                    core::array::from_fn(|led| indication && led < TRACKS && waiting[led])
+                });
+            cx.local.leds.set(leds);
+        }
+
+        if cx.local.save_debounce.tick().is_some() {
+            // Fails only while the previous save is still queued, which then
+            // reads the latest configuration anyway.
+            persist::spawn().ok();
+        }
+        if let Some(settings) = cx.local.settings_debounce.tick() {
+            let cassette = cx.shared.cassette.lock(|cassette| *cassette);
+            store_settings(cx.local.settings_queue, cassette, settings);
+        }
+    }
+
+    fn store_settings(
+        settings_queue: &mut Option<SettingsQueue>,
+        cassette: CassetteId,
+        settings: Settings,
+    ) {
+        let Some(settings_queue) = settings_queue else {
+            return;
+        };
+        if !settings_queue.save(cassette, settings) {
+            defmt::error!("Failed to queue the settings of the cassette");
+        }
+    }
+
+    /// Run a block of audio through the tape, the effects of tracks and the
+    /// mixer. Audio is transferred through DMA, which asks for the next block
+    /// once it is done with the previous one.
+    #[task(
+        binds = DMA1_STR1,
+        priority = 3,
+        local = [audio, deck, rack, mixer],
+        shared = [transport, settings, recovered, save]
+    )]
+    fn audio(mut cx: audio::Context) {
+        let settings = cx.shared.settings.lock(|settings| *settings);
+        let input_stage = cx.shared.save.lock(|save| save.input);
+        let deck = cx.local.deck;
+        let rack = cx.local.rack;
+        let mixer = cx.local.mixer;
+        rack.set_settings(&settings);
+        mixer.set_settings(&settings);
+
+        let transport = &mut cx.shared.transport;
+        cx.local
+            .audio
+            .handle_interrupt_dma1_str1(|buffer| {
+                let mut input = [[0.0; CHANNELS]; BLOCK_LENGTH];
+                for (input, (left, right)) in input.iter_mut().zip(buffer.iter()) {
+                    *input = [*left, *right];
+                }
+                let mut routed = [[0.0; TRACKS]; BLOCK_LENGTH];
+                input_stage.route(&input, &mut routed);
+
+                let mut tracks = [[0.0; TRACKS]; BLOCK_LENGTH];
+                let armed = transport.lock(|transport| {
+                    if let Some(deck) = deck.as_mut() {
+                        deck.process(transport, &routed, &mut tracks);
+                    }
+                    core::array::from_fn(|track| transport.is_recording(track))
+                });
+                rack.process(&mut tracks);
+
+                let mut monitor = [[0.0; CHANNELS]; BLOCK_LENGTH];
+                input_stage.monitor(&input, armed, &mut monitor);
+                let mut output = [[0.0; CHANNELS]; BLOCK_LENGTH];
+                mixer.process(&tracks, &monitor, &mut output);
+                for (frame, [left, right]) in buffer.iter_mut().zip(output) {
+                    *frame = (left, right);
+                }
+            })
+            .unwrap();
+
+        if let Some(deck) = deck.as_mut() {
+            if let Some(settings) = deck.take_settings() {
+                cx.shared
+                    .recovered
+                    .lock(|recovered| *recovered = Some(settings));
+            }
+            // Fails only while the previous run is still queued, which then
+            // serves this block's requests too.
+            serve_pages::spawn().ok();
+        }
+    }
+
+    /// Timestamp the edge of the gate and cue it on the transport at its
+    /// sample within the block being captured.
+    #[task(binds = EXTI15_10, priority = 2, local = [gate, sample_clock], shared = [transport, gate_action, gate_high])]
+    fn gate(cx: gate::Context) {
+        let time = cx.local.sample_clock.now();
+        let gate = cx.local.gate;
+        gate.clear_interrupt();
+        let edge = Edge {
+            time,
+            rising: gate.is_high(),
+        };
+
+        let block_start = time - time % BLOCK_LENGTH as u32;
+        (
+            cx.shared.transport,
+            cx.shared.gate_action,
+            cx.shared.gate_high,
+        )
+            .lock(|transport, gate_action, gate_high| {
+                *gate_high = edge.rising;
+                gate_action.schedule([edge], block_start, BLOCK_LENGTH, transport);
+            });
+    }
+
+    /// Serve pages and settings requested by the tape.
+    #[task(shared = [library])]
+    fn serve_pages(cx: serve_pages::Context) {
+        if let Some((library, _)) = cx.shared.library {
+            library.process();
+        }
+    }
+
+    /// Measure the space occupied by the selected cassette, to be shown by
+    /// the control task.
+    #[task(shared = [library, cassette, usage])]
+    fn measure_usage(mut cx: measure_usage::Context) {
+        let Some((library, _)) = cx.shared.library else {
+            return;
+        };
+        let cassette = cx.shared.cassette.lock(|cassette| *cassette);
+        let usage = library.usage(cassette);
+        cx.shared.usage.lock(|shared| *shared = Some(usage));
+    }
+
+    #[task(shared = [library, save])]
+    fn persist(mut cx: persist::Context) {
+        let Some((library, persistence)) = cx.shared.library else {
+            return;
+        };
+        let save = cx.shared.save.lock(|save| *save);
+        if persistence.save(library.storage_mut(), &save).is_err() {
+            defmt::error!("Failed to persist the configuration");
+        }
     }
 
     #[task(local = [status_led])]
@@ -5,6 +5,7 @@ use defmt_rtt as _; // Global logger.
 use panic_probe as _;
 use stm32h7xx_hal as _; // Readable panic.
 
+pub mod reset;
 pub mod system;
 
 // Same panicking *behavior* as `panic-probe` but doesn't print a panic message
@@ -0,0 +1,104 @@
+//! Factory reset triggered by holding the PP button during startup.
+
+use placeholder_dsp::paging_buffer::{factory_reset, resume_interrupted_reset, BlockStorage};
+
+use crate::system::buttons::{Button, Buttons};
+use crate::system::leds::{Leds, LEDS};
+use crate::system::multiplexer::Multiplexer;
+use crate::system::sd::Sd;
+use crate::system::Card;
+
+const CONFIRMATION_STEP_MS: u32 = 250;
+const BLINK_MS: u32 = 150;
+const BLINKS: usize = 3;
+
+/// Erase all recordings and configuration if requested by the user.
+///
+/// Reset interrupted by a power loss is finished first. Then, if the PP button
+/// is held, LEDs light up one by one. If the button is held until all of
+/// them are lit, the reset is executed and confirmed by blinking.
+///
+/// A card that was not formatted yet gets formatted after the same
+/// confirmation. It is not used otherwise.
+pub fn reset_on_request(
+    card: Option<Card>,
+    buttons: &mut Buttons,
+    multiplexer: &mut Multiplexer,
+    leds: &mut Leds,
+    system_clock_hz: u32,
+) -> Option<BlockStorage<Sd>> {
+    let delay_ms = |ms: u32| cortex_m::asm::delay(system_clock_hz / 1000 * ms);
+
+    let storage = match card? {
+        Card::Formatted(mut storage) => {
+            match resume_interrupted_reset(&mut storage) {
+                Ok(true) => defmt::warn!("Finished factory reset interrupted by power loss"),
+                Ok(false) => (),
+                Err(_) => defmt::error!("Failed to finish interrupted factory reset"),
+            }
+
+            if !is_confirmed(buttons, multiplexer, leds, delay_ms) {
+                return Some(storage);
+            }
+
+            defmt::info!("Executing factory reset");
+            if factory_reset(&mut storage).is_err() {
+                defmt::error!("Factory reset failed, it will be resumed on the next start");
+            }
+            storage
+        }
+        Card::Unformatted(sd) => {
+            if !is_confirmed(buttons, multiplexer, leds, delay_ms) {
+                defmt::warn!("The SD card was not formatted, nothing will be saved");
+                return None;
+            }
+
+            defmt::info!("Formatting the SD card");
+            match BlockStorage::format(sd) {
+                Ok(storage) => storage,
+                Err(_) => {
+                    defmt::error!("Failed to format the SD card");
+                    return None;
+                }
+            }
+        }
+    };
+
+    for _ in 0..BLINKS {
+        leds.set_all(false);
+        delay_ms(BLINK_MS);
+        leds.set_all(true);
+        delay_ms(BLINK_MS);
+    }
+    leds.set_all(false);
+
+    // Wait for the button release, so it is not picked up by the control loop.
+    while buttons.is_pressed(Button::PlayPause, multiplexer) {}
+
+    Some(storage)
+}
+
+/// Whether the PP button is held until all the LEDs light up one by one.
+fn is_confirmed(
+    buttons: &mut Buttons,
+    multiplexer: &mut Multiplexer,
+    leds: &mut Leds,
+    delay_ms: impl Fn(u32),
+) -> bool {
+    if !buttons.is_pressed(Button::PlayPause, multiplexer) {
+        return false;
+    }
+
+    let mut state = [false; LEDS];
+    for led in 0..LEDS {
+        state[led] = true;
+        leds.set(state);
+        delay_ms(CONFIRMATION_STEP_MS);
+        if !buttons.is_pressed(Button::PlayPause, multiplexer) {
+            defmt::info!("Factory reset was canceled");
+            leds.set_all(false);
+            return false;
+        }
+    }
+    true
+}
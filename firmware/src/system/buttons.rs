@@ -0,0 +1,54 @@
+//! Push buttons read through the GPIO multiplexer.
+
+use super::hal::gpio::{gpioc, Analog, Input};
+
+use super::multiplexer::Multiplexer;
+
+pub use placeholder_control::gestures::Button;
+
+fn channel(button: Button) -> u8 {
+    match button {
+        Button::Record3 => 0,
+        Button::Record2 => 1,
+        Button::Record1 => 2,
+        Button::Record4 => 3,
+        Button::PlayFromBeginning => 5,
+        Button::PlayPause => 7,
+    }
+}
+
+/// Button routed to the given channel of the GPIO multiplexer.
+pub fn button_on_channel(channel: u8) -> Option<Button> {
+    Button::ALL
+        .into_iter()
+        .find(|button| self::channel(*button) == channel)
+}
+
+pub struct Buttons {
+    pin: gpioc::PC2<Input>,
+}
+
+impl Buttons {
+    pub fn new(pin: gpioc::PC2<Analog>) -> Self {
+        Self {
+            pin: pin.into_floating_input(),
+        }
+    }
+
+    pub fn is_pressed(&mut self, button: Button, multiplexer: &mut Multiplexer) -> bool {
+        multiplexer.select(channel(button));
+        // Give the multiplexer output time to settle.
+        cortex_m::asm::delay(MULTIPLEXER_SETTLE_CYCLES);
+        self.is_selected_pressed()
+    }
+
+    /// Whether the button on the channel that is already selected and
+    /// settled is pressed.
+    pub fn is_selected_pressed(&mut self) -> bool {
+        // Buttons are pulled up and shorted to the ground when pressed.
+        self.pin.is_low()
+    }
+}
+
+// About 2 us at 480 MHz.
+const MULTIPLEXER_SETTLE_CYCLES: u32 = 1000;
@@ -0,0 +1,31 @@
+//! Gate input of the PFB jack.
+//!
+//! Both edges of the gate raise the EXTI15_10 interrupt, so even triggers
+//! shorter than a control tick are caught and timestamped right away.
+
+use super::hal::gpio::{gpiog, Analog, Edge, ExtiPin, Input};
+use super::hal::pac::{EXTI, SYSCFG};
+
+pub struct GateInput {
+    pin: gpiog::PG14<Input>,
+}
+
+impl GateInput {
+    pub fn new(pin: gpiog::PG14<Analog>, syscfg: &mut SYSCFG, exti: &mut EXTI) -> Self {
+        let mut pin = pin.into_floating_input();
+        pin.make_interrupt_source(syscfg);
+        pin.trigger_on_edge(exti, Edge::RisingFalling);
+        pin.enable_interrupt(exti);
+        Self { pin }
+    }
+
+    pub fn is_high(&self) -> bool {
+        // The input stage of the Patch SM inverts the signal.
+        self.pin.is_low()
+    }
+
+    /// Acknowledge the interrupt raised by an edge.
+    pub fn clear_interrupt(&mut self) {
+        self.pin.clear_interrupt_pending_bit();
+    }
+}
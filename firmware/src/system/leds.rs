@@ -0,0 +1,35 @@
+//! Bar of LEDs driven through the 74HC164 shift register.
+
+use super::hal::gpio::{gpiob, gpiod, Alternate, Analog, Output, PushPull};
+
+pub const LEDS: usize = 8;
+
+pub struct Leds {
+    data: gpiob::PB4<Output<PushPull>>,
+    clock: gpiod::PD3<Output<PushPull>>,
+}
+
+impl Leds {
+    pub fn new(data: gpiob::PB4<Alternate<0>>, clock: gpiod::PD3<Analog>) -> Self {
+        Self {
+            data: data.into_push_pull_output(),
+            clock: clock.into_push_pull_output(),
+        }
+    }
+
+    /// Display the given state, LEDs are ordered from left to right.
+    pub fn set(&mut self, leds: [bool; LEDS]) {
+        // The first shifted bit ends up on Q7. Outputs Q0 to Q7 are wired
+        // to LEDs 5, 6, 7, 8, 1, 2, 3, 4.
+        const ORDER: [usize; LEDS] = [3, 2, 1, 0, 7, 6, 5, 4];
+        for index in ORDER {
+            self.data.set_state(leds[index].into());
+            self.clock.set_high();
+            self.clock.set_low();
+        }
+    }
+
+    pub fn set_all(&mut self, on: bool) {
+        self.set([on; LEDS]);
+    }
+}
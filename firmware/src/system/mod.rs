@@ -1,15 +1,55 @@
+pub mod buttons;
+pub mod gate;
+pub mod leds;
+pub mod multiplexer;
+pub mod pots;
+pub mod sample_clock;
+pub mod sd;
+
 pub use stm32h7xx_hal as hal;
 
+use daisy::audio::Interface;
 use daisy::led::LedUser;
+use hal::adc;
+use hal::delay::DelayFromCountDownTimer;
+use hal::gpio::Speed;
 use hal::pac::CorePeripherals;
 use hal::pac::Peripherals as DevicePeripherals;
+use hal::prelude::*;
+use hal::sdmmc::{SdCard, Sdmmc};
 use hal::time::Hertz;
+use placeholder_dsp::paging_buffer::{BlockStorage, Error};
 use systick_monotonic::Systick;
 
+use buttons::Buttons;
+use gate::GateInput;
+use leds::Leds;
+use multiplexer::Multiplexer;
+use pots::Pots;
+use sample_clock::SampleClock;
+use sd::Sd;
+
 pub struct System {
     pub mono: Systick<1000>,
     pub status_led: LedUser,
     pub system_clock: Hertz,
+    /// Audio codec, not started yet.
+    pub audio: Interface,
+    pub multiplexer: Multiplexer,
+    pub buttons: Buttons,
+    pub gate: GateInput,
+    pub sample_clock: SampleClock,
+    pub leds: Leds,
+    pub pots: Pots,
+    pub card: Option<Card>,
+}
+
+/// SD card detected during startup.
+#[allow(clippy::large_enum_variant)] // Passed around only during startup.
+pub enum Card {
+    Formatted(BlockStorage<Sd>),
+    /// The card must be formatted before it can be used.
+    Unformatted(Sd),
 }
 
 impl System {
@@ -19,7 +59,7 @@ impl System {
     ///
     /// The system can be initialized only once. It panics otherwise.
     #[must_use]
-    pub fn init(mut cp: CorePeripherals, dp: DevicePeripherals) -> Self {
+    pub fn init(mut cp: CorePeripherals, mut dp: DevicePeripherals) -> Self {
         enable_cache(&mut cp);
 
         let board = daisy::Board::take().unwrap();
@@ -30,10 +70,90 @@ impl System {
         let status_led = daisy::board_split_leds!(pins).USER;
         let system_clock = ccdr.clocks.sys_ck();
 
+        let multiplexer = Multiplexer::new(pins.GPIO.PIN_A9, pins.GPIO.PIN_A8, pins.GPIO.PIN_A3);
+        let buttons = Buttons::new(pins.GPIO.PIN_D8);
+        let gate = GateInput::new(pins.GPIO.PIN_B9, &mut dp.SYSCFG, &mut dp.EXTI);
+        let sample_clock = SampleClock::new(dp.TIM5, ccdr.peripheral.TIM5, &ccdr.clocks);
+        let leds = Leds::new(pins.GPIO.PIN_D1, pins.GPIO.PIN_D10);
+        let audio = daisy::board_split_audio!(ccdr, pins);
+
+        let mut delay = DelayFromCountDownTimer::new(dp.TIM2.timer(
+            100.Hz(),
+            ccdr.peripheral.TIM2,
+            &ccdr.clocks,
+        ));
+        let (adc_1, _) = adc::adc12(
+            dp.ADC1,
+            dp.ADC2,
+            4.MHz(),
+            &mut delay,
+            ccdr.peripheral.ADC12,
+            &ccdr.clocks,
+        );
+        let pots = Pots::new(
+            adc_1.enable(),
+            pins.GPIO.PIN_C2,
+            (
+                pins.GPIO.PIN_C9,
+                pins.GPIO.PIN_C8,
+                pins.GPIO.PIN_C7,
+                pins.GPIO.PIN_C6,
+            ),
+            pins.GPIO.PIN_C3,
+            pins.GPIO.PIN_A2,
+            (pins.GPIO.PIN_C5, pins.GPIO.PIN_C4),
+        );
+
+        let sdmmc: Sdmmc<_, SdCard> = dp.SDMMC1.sdmmc(
+            (
+                pins.GPIO
+                    .PIN_D6
+                    .into_alternate()
+                    .internal_pull_up(false)
+                    .speed(Speed::VeryHigh),
+                pins.GPIO
+                    .PIN_D7
+                    .into_alternate()
+                    .internal_pull_up(true)
+                    .speed(Speed::VeryHigh),
+                pins.GPIO
+                    .PIN_D5
+                    .into_alternate()
+                    .internal_pull_up(true)
+                    .speed(Speed::VeryHigh),
+                pins.GPIO
+                    .PIN_D4
+                    .into_alternate()
+                    .internal_pull_up(true)
+                    .speed(Speed::VeryHigh),
+                pins.GPIO
+                    .PIN_D3
+                    .into_alternate()
+                    .internal_pull_up(true)
+                    .speed(Speed::VeryHigh),
+                pins.GPIO
+                    .PIN_D2
+                    .into_alternate()
+                    .internal_pull_up(true)
+                    .speed(Speed::VeryHigh),
+            ),
+            ccdr.peripheral.SDMMC1,
+            &ccdr.clocks,
+        );
+        let card = init_card(sdmmc);
+
         Self {
             mono,
             status_led,
             system_clock,
+            audio,
+            multiplexer,
+            buttons,
+            gate,
+            sample_clock,
+            leds,
+            pots,
+            card,
         }
     }
 }
@@ -45,3 +165,28 @@ fn enable_cache(cp: &mut CorePeripherals) {
     // NOTE: This requires cache management around all use of DMA.
     cp.SCB.enable_dcache(&mut cp.CPUID);
 }
+
+fn init_card(mut sdmmc: Sdmmc<hal::pac::SDMMC1, SdCard>) -> Option<Card> {
+    const ATTEMPTS: usize = 3;
+    for _ in 0..ATTEMPTS {
+        if sdmmc.init(50.MHz()).is_ok() {
+            return match BlockStorage::new(Sd::new(sdmmc)) {
+                Ok(storage) => Some(Card::Formatted(storage)),
+                Err((Error::Unformatted, sd)) => {
+                    defmt::warn!("The SD card is not formatted");
+                    Some(Card::Unformatted(sd))
+                }
+                Err((Error::Incompatible, sd)) => {
+                    defmt::warn!("The SD card holds recordings too long for banks");
+                    Some(Card::Unformatted(sd))
+                }
+                Err(_) => {
+                    defmt::error!("Failed to load the table of the SD card");
+                    None
+                }
+            };
+        }
+    }
+    defmt::warn!("No SD card was detected");
+    None
+}
@@ -0,0 +1,32 @@
+//! Address lines shared by both of the 4051 multiplexers.
+
+use super::hal::gpio::{gpioa, gpiob, Analog, Output, PushPull};
+
+pub struct Multiplexer {
+    address_a: gpiob::PB15<Output<PushPull>>,
+    address_b: gpiob::PB14<Output<PushPull>>,
+    address_c: gpioa::PA0<Output<PushPull>>,
+}
+
+impl Multiplexer {
+    pub fn new(
+        address_a: gpiob::PB15<Analog>,
+        address_b: gpiob::PB14<Analog>,
+        address_c: gpioa::PA0<Analog>,
+    ) -> Self {
+        Self {
+            address_a: address_a.into_push_pull_output(),
+            address_b: address_b.into_push_pull_output(),
+            address_c: address_c.into_push_pull_output(),
+        }
+    }
+
+    /// Route the given channel of both multiplexers to their outputs.
+    ///
+    /// The caller must give the signal a moment to settle before reading it.
+    pub fn select(&mut self, channel: u8) {
+        self.address_a.set_state((channel & 0b001 != 0).into());
+        self.address_b.set_state((channel & 0b010 != 0).into());
+        self.address_c.set_state((channel & 0b100 != 0).into());
+    }
+}
@@ -0,0 +1,99 @@
+//! Potentiometers read through the ADC, either directly or through the pot
+//! multiplexer, together with the resistor ladder of the cassette switch
+//! and the CV inputs.
+
+use super::hal::adc::{Adc, Enabled, Resolution};
+use super::hal::gpio::{gpioa, gpiob, gpioc, Analog};
+use super::hal::pac::ADC1;
+use super::hal::prelude::*;
+
+use placeholder_control::pots::DIRECT_POTS;
+use placeholder_dsp::save::CV_INPUTS;
+
+pub struct Pots {
+    adc: Adc<ADC1, Enabled>,
+    multiplexed: gpioa::PA7<Analog>,
+    volume_1: gpioc::PC4<Analog>,
+    volume_2: gpiob::PB1<Analog>,
+    volume_3: gpioc::PC0<Analog>,
+    volume_4: gpioc::PC1<Analog>,
+    pitch: gpioa::PA2<Analog>,
+    cassette_switch: gpioa::PA1<Analog>,
+    cv_1: gpioa::PA3<Analog>,
+    cv_2: gpioa::PA6<Analog>,
+}
+
+/// Pins of the directly wired volume pots, ordered by tracks.
+pub type VolumePins = (
+    gpioc::PC4<Analog>,
+    gpiob::PB1<Analog>,
+    gpioc::PC0<Analog>,
+    gpioc::PC1<Analog>,
+);
+
+/// Pins of the CV inputs, ordered by inputs.
+pub type CvPins = (gpioa::PA3<Analog>, gpioa::PA6<Analog>);
+
+impl Pots {
+    pub fn new(
+        mut adc: Adc<ADC1, Enabled>,
+        multiplexed: gpioa::PA7<Analog>,
+        volume: VolumePins,
+        pitch: gpioa::PA2<Analog>,
+        cassette_switch: gpioa::PA1<Analog>,
+        cv: CvPins,
+    ) -> Self {
+        adc.set_resolution(Resolution::SixteenBit);
+        Self {
+            adc,
+            multiplexed,
+            volume_1: volume.0,
+            volume_2: volume.1,
+            volume_3: volume.2,
+            volume_4: volume.3,
+            pitch,
+            cassette_switch,
+            cv_1: cv.0,
+            cv_2: cv.1,
+        }
+    }
+
+    /// Read the pot on the channel currently selected on the multiplexer.
+    pub fn read_multiplexed(&mut self) -> f32 {
+        let sample: u32 = self.adc.read(&mut self.multiplexed).unwrap();
+        self.normalize(sample)
+    }
+
+    /// Read the directly wired pots, in the order of
+    /// `placeholder_control::pots::DIRECT`.
+    pub fn read_direct(&mut self) -> [f32; DIRECT_POTS] {
+        let samples: [u32; DIRECT_POTS] = [
+            self.adc.read(&mut self.volume_1).unwrap(),
+            self.adc.read(&mut self.volume_2).unwrap(),
+            self.adc.read(&mut self.volume_3).unwrap(),
+            self.adc.read(&mut self.volume_4).unwrap(),
+            self.adc.read(&mut self.pitch).unwrap(),
+        ];
+        samples.map(|sample| self.normalize(sample))
+    }
+
+    /// Read the output of the cassette switch ladder.
+    pub fn read_cassette_switch(&mut self) -> f32 {
+        let sample: u32 = self.adc.read(&mut self.cassette_switch).unwrap();
+        self.normalize(sample)
+    }
+
+    /// Read the CV inputs, ranging from -1.0 to 1.0 for -5 to +5 V.
+    pub fn read_cv(&mut self) -> [f32; CV_INPUTS] {
+        let samples: [u32; CV_INPUTS] = [
+            self.adc.read(&mut self.cv_1).unwrap(),
+            self.adc.read(&mut self.cv_2).unwrap(),
+        ];
+        // The input stage of the Patch SM inverts the signal.
+        samples.map(|sample| 1.0 - 2.0 * self.normalize(sample))
+    }
+
+    fn normalize(&self, sample: u32) -> f32 {
+        sample as f32 / self.adc.slope() as f32
+    }
+}
@@ -0,0 +1,26 @@
+//! Free-running clock counting samples, used to timestamp gate edges.
+
+use super::hal::pac::TIM5;
+use super::hal::prelude::*;
+use super::hal::rcc::{rec, CoreClocks};
+use super::hal::timer::Timer;
+
+/// Rate of the clock, matching the sample rate of audio.
+pub const SAMPLE_RATE: u32 = 48_000;
+
+pub struct SampleClock {
+    timer: Timer<TIM5>,
+}
+
+impl SampleClock {
+    pub fn new(tim: TIM5, prec: rec::Tim5, clocks: &CoreClocks) -> Self {
+        Self {
+            timer: tim.tick_timer(SAMPLE_RATE.Hz(), prec, clocks),
+        }
+    }
+
+    /// Current time in samples, wrapping around.
+    pub fn now(&self) -> u32 {
+        self.timer.counter()
+    }
+}
@@ -0,0 +1,46 @@
+//! Block access to the SD card.
+
+use placeholder_dsp::paging_buffer::{BlockDevice, Error, BLOCK_SIZE};
+
+use super::hal::pac::SDMMC1;
+use super::hal::sdmmc::{SdCard, Sdmmc};
+
+pub struct Sd {
+    sdmmc: Sdmmc<SDMMC1, SdCard>,
+}
+
+impl Sd {
+    pub fn new(sdmmc: Sdmmc<SDMMC1, SdCard>) -> Self {
+        Self { sdmmc }
+    }
+}
+
+impl BlockDevice for Sd {
+    fn read_block(&mut self, index: u32, block: &mut [u8; BLOCK_SIZE]) -> Result<(), Error> {
+        self.sdmmc.read_block(index, block).map_err(|error| {
+            defmt::error!(
+                "Failed reading block {}: {}",
+                index,
+                defmt::Debug2Format(&error)
+            );
+            Error::Io
+        })
+    }
+
+    fn write_block(&mut self, index: u32, block: &[u8; BLOCK_SIZE]) -> Result<(), Error> {
+        self.sdmmc.write_block(index, block).map_err(|error| {
+            defmt::error!(
+                "Failed writing block {}: {}",
+                index,
+                defmt::Debug2Format(&error)
+            );
+            Error::Io
+        })
+    }
+
+    fn blocks(&mut self) -> u32 {
+        self.sdmmc
+            .card()
+            .map_or(0, |card| (card.size() / BLOCK_SIZE as u64) as u32)
+    }
+}
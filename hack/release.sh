@@ -5,6 +5,7 @@ version=${1}
 
 sed -i "s/## Unreleased/## Unreleased\n\n## ${version}/" CHANGELOG.md
 sed -i "s/version =.* # hack\/release.sh$/version = \"${version}\" # hack\/release.sh/" dsp/Cargo.toml
+sed -i "s/version =.* # hack\/release.sh$/version = \"${version}\" # hack\/release.sh/" control/Cargo.toml
 sed -i "s/version =.* # hack\/release.sh$/version = \"${version}\" # hack\/release.sh/" tests/Cargo.toml
 sed -i "s/rev .*/rev \"v${version}\")/" hardware/Module.kicad_sch
 sed -i "s/gr_text \"board .*\" /gr_text \"board v${version}\" /" hardware/Module.kicad_pcb
@@ -1,6 +1,13 @@
 Feature: Several audio effects can be applied to individual tracks
 
   Scenario: Turning the knob while holding the PP button switches between effects
+    Given the effect knob of track 2 is set to 0.0
+    When the PP button is pressed
+    And the effect knob of track 2 is turned by 0.9
+    And the PP button is released
+    Then track 2 uses the "Wow and flutter" effect
+    And track 1 uses the "Bypass" effect
+    And the tape is paused
 
   Scenario: After a new effect is selected, it immediately reflects the current knob position in its strength
 
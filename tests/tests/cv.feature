@@ -1,11 +1,43 @@
 Feature: CV input can be used to control input attributes
 
   Scenario: Mapping pot
+    When CV mapping is toggled
+    And CV 1 is wiggled
+    And the volume knob of track 2 is turned by 0.5
+    And CV mapping is toggled
+    Then CV 1 is mapped to the volume knob of track 2
 
   Scenario: Mapping button
+    Given the tape is paused
+    When CV mapping is toggled
+    And CV 2 is wiggled
+    And the PP button is tapped
+    And CV mapping is toggled
+    Then CV 2 is mapped to the PP button
+    When a gate is sent to CV 2
+    Then the tape is playing
 
   Scenario: Reset
+    Given CV 1 is mapped to the volume knob of track 1
+    When CV mapping is toggled
+    And CV 1 is wiggled
+    And CV mapping is toggled
+    Then CV 1 is not mapped
 
   Scenario: Persistence
+    Given CV 2 is mapped to the pan knob of track 3
+    When the module restarts
+    Then CV 2 is mapped to the pan knob of track 3
 
   Scenario: Ranges
+    Given the volume knob of track 1 is set to 0.5
+    When CV mapping is toggled
+    And CV 1 is wiggled
+    And the volume knob of track 1 is turned by -0.25
+    And CV mapping is toggled
+    And CV 1 is set to 5 V
+    Then the volume of track 1 is 0.0
+    When CV 1 is set to -5 V
+    Then the volume of track 1 is 0.5
+    When CV 1 is set to 2.5 V
+    Then the volume of track 1 is 0.125
@@ -1,15 +1,45 @@
 Feature: Button inputs can issue play from the beginning, pause or continue
 
   Scenario: Tapping the PFB button will immediately start playing from the start
+    Given the tape is paused
+    When the PFB button is pressed
+    Then the tape is playing
+    And the tape moves to the beginning
 
   Scenario: If the module is already playing, PFB will move to the beginning and continue
+    Given the tape is playing
+    When the PFB button is tapped
+    Then the tape is playing
+    And the tape moves to the beginning
 
   Scenario: If the module is recording, PFB will continue recording from the beginning
+    Given the tape is playing
+    And track 2 is recording
+    When the PFB button is tapped
+    Then the tape moves to the beginning
+    And track 2 is recording
 
   Scenario: Holding the PP button does not toggle play
+    Given the tape is playing
+    When the PP button is held
+    Then the tape is playing
 
   Scenario: Tapping the PP button toggles play
+    Given the tape is playing
+    When the PP button is tapped
+    Then the tape is paused
+    When the PP button is tapped
+    Then the tape is playing
 
   Scenario: Recording continues after plause and continue are triggered using the PP button
+    Given the tape is playing
+    And track 1 is recording
+    When the PP button is tapped
+    And the PP button is tapped
+    Then the tape is playing
+    And track 1 is recording
 
   Scenario: Pause is canceled after clicking PFB
+    Given the tape is paused
+    When the PFB button is tapped
+    Then the tape is playing
@@ -1,9 +1,30 @@
 Feature: Input signal can be recorded into any of the tracks
 
   Scenario: Tapping the record button toggles recording for given track, starting on press, stopping on release
+    Given the tape is playing
+    When the REC1 button is pressed
+    Then track 1 is recording
+    When the REC1 button is released
+    Then track 1 is recording
+    When the REC1 button is pressed
+    Then track 1 is recording
+    When the REC1 button is released
+    Then track 1 is not recording
 
   Scenario: Holding the record button enables recording for the given track for as long as it is held
+    Given the tape is playing
+    When the REC2 button is pressed
+    And the REC2 button is kept down
+    Then track 2 is recording
+    When the REC2 button is released
+    Then track 2 is not recording
 
   Scenario: Clicking the record button 5 immediately erases content of the track and leaves in playback
 
   Scenario: Changing the cassette selections stops recording on all tracks
+    Given the tape is playing
+    And track 1 is recording
+    And track 3 is recording
+    When another cassette is selected
+    Then track 1 is not recording
+    And track 3 is not recording
@@ -1,8 +1,280 @@
-use cucumber::World;
+use cucumber::{given, then, when, World};
 
-#[derive(Debug, Default, World)]
-pub struct ControlWorld {}
+use placeholder_control::bindings::Bindings;
+use placeholder_control::cv::Mapper;
+use placeholder_control::gestures::{Button, Gestures, Timings, BUTTONS};
+use placeholder_control::pots::{Pot, Snapshot};
+use placeholder_dsp::effect;
+use placeholder_dsp::paging_buffer::{PickupModes, Settings, SettingsPickup, Transport};
+use placeholder_dsp::save::{Mapping, Save, CV_INPUTS};
+
+#[derive(Debug, World)]
+pub struct ControlWorld {
+    gestures: Gestures,
+    bindings: Bindings,
+    transport: Transport,
+    mapper: Mapper,
+    pickup: SettingsPickup,
+    pressed: [bool; BUTTONS],
+    pots: Snapshot,
+    cv: [f32; CV_INPUTS],
+    modulated: Snapshot,
+}
+
+impl Default for ControlWorld {
+    fn default() -> Self {
+        let mut world = Self {
+            gestures: Gestures::new(Timings::default()),
+            bindings: Bindings::new(),
+            transport: Transport::new(),
+            mapper: Mapper::new(&Save::default()),
+            pickup: SettingsPickup::new(Settings::default(), &PickupModes::default()),
+            pressed: [false; BUTTONS],
+            pots: Snapshot::default(),
+            cv: [0.0; CV_INPUTS],
+            modulated: Snapshot::default(),
+        };
+        world.tick();
+        world
+    }
+}
+
+impl ControlWorld {
+    fn tick(&mut self) {
+        self.mapper.update(self.cv, &self.pots);
+        self.modulated = self.mapper.modulate(&self.pots);
+        let mut pressed = self.pressed;
+        self.mapper.press(&mut pressed);
+        for event in self.gestures.update(pressed, &self.pots) {
+            self.bindings.apply(
+                event,
+                &mut self.transport,
+                &mut self.mapper,
+                &mut self.pickup,
+                &self.pots,
+            );
+        }
+    }
+
+    fn ticks(&mut self, count: u32) {
+        for _ in 0..count {
+            self.tick();
+        }
+    }
+
+    fn press(&mut self, button: Button) {
+        self.pressed[button.index()] = true;
+        self.ticks(Timings::default().debounce);
+    }
+
+    fn release(&mut self, button: Button) {
+        self.pressed[button.index()] = false;
+        self.ticks(Timings::default().debounce);
+    }
+}
+
+fn button(name: &str) -> Button {
+    match name {
+        "PFB" => Button::PlayFromBeginning,
+        "PP" => Button::PlayPause,
+        "REC1" => Button::Record1,
+        "REC2" => Button::Record2,
+        "REC3" => Button::Record3,
+        "REC4" => Button::Record4,
+        _ => panic!("Unknown button {name}"),
+    }
+}
+
+fn pot(name: &str, track: usize) -> Pot {
+    match name {
+        "volume" => Pot::Volume(track - 1),
+        "pan" => Pot::Pan(track - 1),
+        "effect" => Pot::Effect(track - 1),
+        _ => panic!("Unknown knob {name}"),
+    }
+}
+
+fn set_pot(pots: &mut Snapshot, pot: Pot, value: f32) {
+    match pot {
+        Pot::Volume(track) => pots.volume[track] = value,
+        Pot::Pan(track) => pots.pan[track] = value,
+        Pot::Effect(track) => pots.effect[track] = value,
+        Pot::Pitch => pots.pitch = value,
+    }
+}
+
+#[given(expr = "the tape is {word}")]
+fn given_tape(world: &mut ControlWorld, state: String) {
+    let playing = match state.as_str() {
+        "playing" => true,
+        "paused" => false,
+        _ => panic!("Unknown state {state}"),
+    };
+    if world.transport.is_playing() != playing {
+        world.transport.toggle_play();
+    }
+}
+
+#[given(expr = "track {int} is recording")]
+fn given_recording(world: &mut ControlWorld, track: usize) {
+    world.transport.set_recording(track - 1, true);
+}
+
+#[given(expr = "CV {int} is mapped to the {word} knob of track {int}")]
+fn given_cv_mapped(world: &mut ControlWorld, input: usize, name: String, track: usize) {
+    let mut save = Save::default();
+    save.mappings[input - 1] = Some(Mapping::Pot(pot(&name, track).index() as u8));
+    world.mapper = Mapper::new(&save);
+}
+
+#[given(expr = "the {word} knob of track {int} is set to {float}")]
+fn given_pot(world: &mut ControlWorld, name: String, track: usize, value: f32) {
+    set_pot(&mut world.pots, pot(&name, track), value);
+    world.tick();
+}
+
+#[when("CV mapping is toggled")]
+fn when_cv_mapping_toggled(world: &mut ControlWorld) {
+    world.press(Button::PlayPause);
+    world.press(Button::PlayFromBeginning);
+    world.release(Button::PlayFromBeginning);
+    world.release(Button::PlayPause);
+}
+
+#[when(expr = "CV {int} is wiggled")]
+fn when_cv_wiggled(world: &mut ControlWorld, input: usize) {
+    for value in [1.0, -1.0, 0.0] {
+        world.cv[input - 1] = value;
+        world.tick();
+    }
+}
+
+#[when(expr = "CV {int} is set to {float} V")]
+fn when_cv_set(world: &mut ControlWorld, input: usize, volts: f32) {
+    world.cv[input - 1] = volts / 5.0;
+    world.tick();
+}
+
+#[when(expr = "a gate is sent to CV {int}")]
+fn when_gate_sent(world: &mut ControlWorld, input: usize) {
+    world.cv[input - 1] = 1.0;
+    world.ticks(Timings::default().debounce * 2);
+    world.cv[input - 1] = 0.0;
+    world.ticks(Timings::default().debounce * 2);
+}
+
+#[when(expr = "the {word} knob of track {int} is turned by {float}")]
+fn when_pot_turned(world: &mut ControlWorld, name: String, track: usize, travel: f32) {
+    let pot = pot(&name, track);
+    let value = world.pots.get(pot) + travel;
+    set_pot(&mut world.pots, pot, value);
+    world.tick();
+}
+
+#[when("the module restarts")]
+fn when_restarts(world: &mut ControlWorld) {
+    let save = Save {
+        mappings: world.mapper.mappings(),
+        mapping_ranges: world.mapper.ranges(),
+        ..Save::default()
+    };
+    *world = ControlWorld::default();
+    world.mapper = Mapper::new(&save);
+}
+
+#[when(expr = "the {word} button is pressed")]
+fn when_pressed(world: &mut ControlWorld, name: String) {
+    world.press(button(&name));
+}
+
+#[when(expr = "the {word} button is released")]
+fn when_released(world: &mut ControlWorld, name: String) {
+    world.release(button(&name));
+}
+
+#[when(expr = "the {word} button is kept down")]
+fn when_kept_down(world: &mut ControlWorld, _name: String) {
+    world.ticks(Timings::default().hold * 2);
+}
+
+#[when("another cassette is selected")]
+fn when_cassette_selected(world: &mut ControlWorld) {
+    world.bindings.cassette_changed(&mut world.transport);
+}
+
+#[when(expr = "the {word} button is tapped")]
+fn when_tapped(world: &mut ControlWorld, name: String) {
+    world.press(button(&name));
+    world.release(button(&name));
+}
+
+#[when(expr = "the {word} button is held")]
+fn when_held(world: &mut ControlWorld, name: String) {
+    world.press(button(&name));
+    world.ticks(Timings::default().hold * 2);
+    world.release(button(&name));
+}
+
+#[then(expr = "the tape is {word}")]
+fn then_tape(world: &mut ControlWorld, state: String) {
+    let playing = match state.as_str() {
+        "playing" => true,
+        "paused" => false,
+        _ => panic!("Unknown state {state}"),
+    };
+    assert_eq!(world.transport.is_playing(), playing);
+}
+
+#[then("the tape moves to the beginning")]
+fn then_rewinds(world: &mut ControlWorld) {
+    assert!(world.transport.is_rewind_pending());
+}
+
+#[then(expr = "track {int} is recording")]
+fn then_recording(world: &mut ControlWorld, track: usize) {
+    assert!(world.transport.is_recording(track - 1));
+}
+
+#[then(expr = "track {int} is not recording")]
+fn then_not_recording(world: &mut ControlWorld, track: usize) {
+    assert!(!world.transport.is_recording(track - 1));
+}
+
+#[then(expr = "track {int} uses the {string} effect")]
+fn then_effect(world: &mut ControlWorld, track: usize, name: String) {
+    let index = world.pickup.settings().tracks[track - 1].effect;
+    assert_eq!(effect::name(index), Some(name.as_str()));
+}
+
+#[then(expr = "CV {int} is mapped to the {word} knob of track {int}")]
+fn then_cv_mapped_to_pot(world: &mut ControlWorld, input: usize, name: String, track: usize) {
+    let index = pot(&name, track).index() as u8;
+    assert_eq!(
+        world.mapper.mappings()[input - 1],
+        Some(Mapping::Pot(index))
+    );
+}
+
+#[then(expr = "CV {int} is mapped to the {word} button")]
+fn then_cv_mapped_to_button(world: &mut ControlWorld, input: usize, name: String) {
+    let index = button(&name).index() as u8;
+    assert_eq!(
+        world.mapper.mappings()[input - 1],
+        Some(Mapping::Button(index))
+    );
+}
+
+#[then(expr = "CV {int} is not mapped")]
+fn then_cv_not_mapped(world: &mut ControlWorld, input: usize) {
+    assert_eq!(world.mapper.mappings()[input - 1], None);
+}
+
+#[then(expr = "the {word} of track {int} is {float}")]
+fn then_pot_value(world: &mut ControlWorld, name: String, track: usize, value: f32) {
+    let actual = world.modulated.get(pot(&name, track));
+    assert!((actual - value).abs() < 0.001, "{actual} != {value}");
+}
 
 fn main() {
-    futures::executor::block_on(ControlWorld::run("tests"));
+    futures::executor::block_on(ControlWorld::cucumber().run_and_exit("tests"));
 }